@@ -0,0 +1,383 @@
+//! This module contains the simulated annealing search engine used by the balancing and constraint check phases.
+//!
+//! The search works on a scratch copy of the replica placement. On each iteration it proposes either moving a
+//! random replica to a random node or swapping the primary of a random failover unit with one of its secondaries,
+//! rejects the proposals violating a hard constraint, and accepts the others according to the Metropolis criterion
+//! on the cluster score. The temperature decreases geometrically, so the search accepts fewer and fewer
+//! score regressions over time. The best placement found is turned into movements.
+//!
+//! A failover unit is touched by at most one movement in a search: either a single replica is moved or the
+//! primary is swapped. This keeps the generated solutions independent of each other.
+
+use std::{collections::BTreeMap, ops::Range};
+
+use time::Duration;
+use uuid::Uuid;
+
+use crate::{
+    balance::LoadTable,
+    constraint::PlacementChecker,
+    failoverunit::failover_unit::{FailoverUnit, ReplicaRole},
+    node::node_id::NodeId,
+    random::SeededRng,
+    solver::Movement,
+    ClusterSnapshot,
+};
+
+/// Minimum score improvement for the result of a search to be worth any movement
+const MIN_SCORE_IMPROVEMENT: f64 = 1e-9;
+
+/// Settings of the simulated annealing search
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AnnealingConfig {
+    /// Maximum number of proposals evaluated by a search
+    pub max_iterations: u64,
+    /// Wall-clock budget of a search. A search running out of time returns the best solution found so far.
+    pub time_budget: Duration,
+    /// Seed of the random number generator. Two searches over the same snapshot with the same seed produce the same
+    /// solutions, as long as they are bounded by `max_iterations` rather than by `time_budget`.
+    pub seed: u64,
+    /// Temperature at the first iteration
+    pub initial_temperature: f64,
+    /// Factor applied to the temperature after each iteration, in (0, 1)
+    pub cooling_rate: f64,
+    /// Probability of proposing a primary swap instead of a replica move, in [0, 1]
+    pub swap_probability: f64,
+}
+
+impl Default for AnnealingConfig {
+    fn default() -> Self {
+        AnnealingConfig {
+            max_iterations: 10_000,
+            time_budget: Duration::new(1, 0),
+            seed: 0,
+            initial_temperature: 0.05,
+            cooling_rate: 0.999,
+            swap_probability: 0.2,
+        }
+    }
+}
+
+/// Statistics of a search
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchStatistics {
+    pub iterations: u64,
+    pub accepted_moves: u64,
+    pub rejected_moves: u64,
+    pub initial_score: f64,
+    pub final_score: f64,
+    /// Whether the search stopped because it ran out of its wall-clock budget
+    pub aborted_on_time_budget: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Slot {
+    fu_id: Uuid,
+    role: ReplicaRole,
+    node: NodeId,
+}
+
+/// The movement a failover unit went through during the search
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Touch {
+    Moved {
+        slot: usize,
+        role: ReplicaRole,
+        from: NodeId,
+        to: NodeId,
+    },
+    Swapped {
+        from: NodeId,
+        to: NodeId,
+    },
+}
+
+pub(crate) struct AnnealingSearch<'a> {
+    snapshot: &'a ClusterSnapshot,
+    config: AnnealingConfig,
+    rng: SeededRng,
+    checker: PlacementChecker<'a>,
+    slots: Vec<Slot>,
+    /// The nodes a replica can be moved to
+    node_ids: Vec<NodeId>,
+    /// The slots of each failover unit with a primary and a secondary, the slots being sorted by failover unit
+    swappable: Vec<Range<usize>>,
+    loads: LoadTable,
+    touched: BTreeMap<Uuid, Touch>,
+}
+
+impl<'a> AnnealingSearch<'a> {
+    pub(crate) fn new(snapshot: &'a ClusterSnapshot, config: &AnnealingConfig) -> Self {
+        let mut slots = snapshot
+            .failover_units
+            .values()
+            .flat_map(|fu| {
+                fu.active_replicas().map(|replica| Slot {
+                    fu_id: fu.id(),
+                    role: replica.role(),
+                    node: replica.location(),
+                })
+            })
+            .collect::<Vec<Slot>>();
+        // replicas are kept in a hash map, sort them so that the search only depends on the seed
+        slots.sort_by_key(|slot| (slot.fu_id, slot.node));
+        let swappable = slots
+            .chunk_by(|a, b| a.fu_id == b.fu_id)
+            .scan(0, |start, fu_slots| {
+                let range = *start..*start + fu_slots.len();
+                *start = range.end;
+                Some((range, fu_slots))
+            })
+            .filter(|(_, fu_slots)| {
+                [ReplicaRole::Primary, ReplicaRole::Secondary]
+                    .iter()
+                    .all(|role| fu_slots.iter().any(|slot| slot.role == *role))
+            })
+            .map(|(range, _)| range)
+            .collect();
+
+        AnnealingSearch {
+            snapshot,
+            config: config.clone(),
+            rng: SeededRng::new(config.seed),
+            checker: PlacementChecker::new(snapshot),
+            slots,
+            node_ids: snapshot.nodes.keys().copied().collect(),
+            swappable,
+            loads: LoadTable::new(snapshot),
+            touched: BTreeMap::new(),
+        }
+    }
+
+    /// Runs the search and returns the movements leading to the best placement found
+    pub(crate) fn run(mut self) -> (Vec<Movement>, SearchStatistics) {
+        let start = std::time::Instant::now();
+        let time_budget =
+            std::time::Duration::try_from(self.config.time_budget).unwrap_or_default();
+        let mut statistics = SearchStatistics {
+            initial_score: self.loads.score(),
+            ..Default::default()
+        };
+        let mut current_score = statistics.initial_score;
+        let mut best_score = current_score;
+        let mut best_touched = self.touched.clone();
+        let mut temperature = self.config.initial_temperature;
+
+        if self.slots.is_empty() || self.node_ids.len() < 2 {
+            statistics.final_score = best_score;
+            return (vec![], statistics);
+        }
+
+        while statistics.iterations < self.config.max_iterations {
+            if start.elapsed() >= time_budget {
+                statistics.aborted_on_time_budget = true;
+                break;
+            }
+            statistics.iterations += 1;
+
+            let Some(undo) = self.propose() else {
+                statistics.rejected_moves += 1;
+                temperature *= self.config.cooling_rate;
+                continue;
+            };
+            let score = self.loads.score();
+            let delta = score - current_score;
+            let accepted = delta <= 0.0
+                || (temperature > 0.0 && self.rng.next_f64() < (-delta / temperature).exp());
+            if accepted {
+                statistics.accepted_moves += 1;
+                current_score = score;
+                if current_score < best_score {
+                    best_score = current_score;
+                    best_touched = self.touched.clone();
+                }
+            } else {
+                statistics.rejected_moves += 1;
+                self.undo(undo);
+            }
+            temperature *= self.config.cooling_rate;
+        }
+
+        if statistics.initial_score - best_score < MIN_SCORE_IMPROVEMENT {
+            best_score = statistics.initial_score;
+            best_touched.clear();
+        }
+        statistics.final_score = best_score;
+
+        let movements = best_touched
+            .iter()
+            .filter_map(|(fu_id, touch)| match *touch {
+                Touch::Moved { role, from, to, .. } => {
+                    (to != from).then_some(Movement::MoveReplica {
+                        fu_id: *fu_id,
+                        role,
+                        from,
+                        to,
+                    })
+                }
+                Touch::Swapped { from, to } => Some(Movement::SwapPrimary {
+                    fu_id: *fu_id,
+                    from,
+                    to,
+                }),
+            })
+            .collect();
+
+        (movements, statistics)
+    }
+
+    fn propose(&mut self) -> Option<Undo> {
+        if self.rng.next_f64() < self.config.swap_probability {
+            self.propose_swap()
+        } else {
+            self.propose_move()
+        }
+    }
+
+    fn propose_move(&mut self) -> Option<Undo> {
+        let slot_index = self.rng.next_index(self.slots.len());
+        let slot = self.slots[slot_index].clone();
+        let previous_touch = self.touched.get(&slot.fu_id).copied();
+        match previous_touch {
+            Some(Touch::Moved { slot: moved, .. }) if moved != slot_index => return None,
+            Some(Touch::Swapped { .. }) => return None,
+            _ => {}
+        }
+
+        let target = self.node_ids[self.rng.next_index(self.node_ids.len())];
+        let fu = self.snapshot.failover_units.get(&slot.fu_id)?;
+        if target == slot.node
+            || self
+                .checker
+                .check_move(fu, slot.role, Some(slot.node), target)
+                .is_err()
+        {
+            return None;
+        }
+
+        self.move_slot(fu, slot_index, target);
+        let original_location = match previous_touch {
+            Some(Touch::Moved { from, .. }) => from,
+            _ => slot.node,
+        };
+        self.touched.insert(
+            slot.fu_id,
+            Touch::Moved {
+                slot: slot_index,
+                role: slot.role,
+                from: original_location,
+                to: target,
+            },
+        );
+
+        Some(Undo::Move {
+            slot: slot_index,
+            from: slot.node,
+            previous_touch,
+        })
+    }
+
+    fn propose_swap(&mut self) -> Option<Undo> {
+        if self.swappable.is_empty() {
+            return None;
+        }
+        let fu_slots = self.swappable[self.rng.next_index(self.swappable.len())].clone();
+        let fu_id = self.slots[fu_slots.start].fu_id;
+        // an untouched failover unit still has the roles it started with
+        if self.touched.contains_key(&fu_id) {
+            return None;
+        }
+        let fu = self.snapshot.failover_units.get(&fu_id)?;
+        let primary = fu_slots
+            .clone()
+            .find(|index| self.slots[*index].role == ReplicaRole::Primary)?;
+        let secondaries = fu_slots
+            .filter(|index| self.slots[*index].role == ReplicaRole::Secondary)
+            .collect::<Vec<usize>>();
+        let secondary = secondaries[self.rng.next_index(secondaries.len())];
+        let (from, to) = (self.slots[primary].node, self.slots[secondary].node);
+        let accepts_primary = self
+            .snapshot
+            .nodes
+            .get(&to)
+            .is_some_and(|node| node.accepts_new_replicas())
+            && !self.snapshot.is_upgrading(fu, to);
+        if !accepts_primary {
+            return None;
+        }
+
+        let overflow_before = self.loads.overflow();
+        self.swap_slots(fu, primary, secondary);
+        if self.loads.overflow() > overflow_before {
+            self.swap_slots(fu, secondary, primary);
+            return None;
+        }
+        self.touched.insert(fu_id, Touch::Swapped { from, to });
+
+        Some(Undo::Swap {
+            fu_id,
+            primary,
+            secondary,
+        })
+    }
+
+    fn undo(&mut self, undo: Undo) {
+        match undo {
+            Undo::Move {
+                slot,
+                from,
+                previous_touch,
+            } => {
+                let fu_id = self.slots[slot].fu_id;
+                if let Some(fu) = self.snapshot.failover_units.get(&fu_id) {
+                    self.move_slot(fu, slot, from);
+                }
+                match previous_touch {
+                    Some(touch) => self.touched.insert(fu_id, touch),
+                    None => self.touched.remove(&fu_id),
+                };
+            }
+            Undo::Swap {
+                fu_id,
+                primary,
+                secondary,
+            } => {
+                if let Some(fu) = self.snapshot.failover_units.get(&fu_id) {
+                    self.swap_slots(fu, secondary, primary);
+                }
+                self.touched.remove(&fu_id);
+            }
+        }
+    }
+
+    fn move_slot(&mut self, fu: &FailoverUnit, slot_index: usize, to: NodeId) {
+        let (role, from) = (self.slots[slot_index].role, self.slots[slot_index].node);
+        self.loads.move_replica(self.snapshot, fu, role, from, to);
+        self.checker.apply(fu, role, Some(from), Some(to));
+        self.slots[slot_index].node = to;
+    }
+
+    /// Swaps the roles of the primary slot and the secondary slot
+    fn swap_slots(&mut self, fu: &FailoverUnit, primary: usize, secondary: usize) {
+        let (from, to) = (self.slots[primary].node, self.slots[secondary].node);
+        self.loads.swap_primary(self.snapshot, fu, from, to);
+        self.checker.apply_swap(fu, from, to);
+        self.slots[primary].role = ReplicaRole::Secondary;
+        self.slots[secondary].role = ReplicaRole::Primary;
+    }
+}
+
+/// What has to be done to revert a proposal that was not accepted
+enum Undo {
+    Move {
+        slot: usize,
+        from: NodeId,
+        previous_touch: Option<Touch>,
+    },
+    Swap {
+        fu_id: Uuid,
+        primary: usize,
+        secondary: usize,
+    },
+}
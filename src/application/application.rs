@@ -1,11 +1,10 @@
 //! Represents an application in a Service Fabric cluster.
 
 use super::application_description::ApplicationDescription;
-use std::collections::HashSet;
 
+#[derive(Clone)]
 pub struct Application {
     pub(crate) application_desc: ApplicationDescription,
-    pub(crate) services: HashSet<String>,
 }
 
 impl Application {
@@ -1,6 +1,27 @@
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ApplicationCapacitiesDescription {
-    metric_name: String,
+    pub(crate) metric_name: String,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     total_capacity: i32,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     max_instance_capacity: i32,
-    reservation_capacity: i32,
+    /// Load reserved for the application on each of its minimum nodes
+    pub(crate) reservation_capacity: i32,
+}
+
+impl ApplicationCapacitiesDescription {
+    pub fn new(
+        metric_name: &str,
+        total_capacity: i32,
+        max_instance_capacity: i32,
+        reservation_capacity: i32,
+    ) -> Self {
+        ApplicationCapacitiesDescription {
+            metric_name: String::from(metric_name),
+            total_capacity,
+            max_instance_capacity,
+            reservation_capacity,
+        }
+    }
 }
@@ -1,13 +1,38 @@
 //! The internal state of an [Application]
 
-
 use super::application_capacities_description::ApplicationCapacitiesDescription;
 use std::collections::HashMap;
 
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ApplicationDescription {
     pub(crate) app_name: String,
-    capacities: HashMap<String, ApplicationCapacitiesDescription>,
+    pub(crate) capacities: HashMap<String, ApplicationCapacitiesDescription>,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     scaleout_count: i32,
-    minimum_nodes: i32,
+    pub(crate) minimum_nodes: i32,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     application_id: u64,
 }
+
+impl ApplicationDescription {
+    pub fn new(app_name: &str) -> Self {
+        ApplicationDescription {
+            app_name: String::from(app_name),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_capacity(mut self, capacity: ApplicationCapacitiesDescription) -> Self {
+        self.capacities
+            .insert(capacity.metric_name.clone(), capacity);
+        self
+    }
+
+    /// Reserves the capacities of the application on the given number of nodes
+    pub fn with_minimum_nodes(mut self, minimum_nodes: i32) -> Self {
+        self.minimum_nodes = minimum_nodes;
+        self
+    }
+}
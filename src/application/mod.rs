@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod application;
 pub mod application_capacities_description;
 pub mod application_description;
@@ -0,0 +1,239 @@
+//! This module computes how balanced the load of the cluster is. The score is shared by the search strategies so that
+//! every strategy optimizes the same objective.
+
+use std::collections::{BTreeMap, HashMap};
+
+use uuid::Uuid;
+
+use crate::{
+    failoverunit::failover_unit::{FailoverUnit, ReplicaRole},
+    node::node_id::NodeId,
+    solver::Movement,
+    ClusterSnapshot,
+};
+
+/// Penalty applied to the score for each unit of capacity overflow (as a ratio of the node capacity)
+const CAPACITY_OVERFLOW_PENALTY: f64 = 1000.0;
+
+/// Load of one metric across the nodes, with the running sums needed to compute the score incrementally
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetricLoads {
+    pub(crate) weight: f64,
+    pub(crate) loads: BTreeMap<NodeId, i64>,
+    pub(crate) capacities: HashMap<NodeId, u32>,
+    sum: f64,
+    sum_of_squares: f64,
+    overflow: f64,
+}
+
+impl MetricLoads {
+    /// The load of the node as a ratio of its capacity, or the raw load if the node has no capacity for the metric
+    pub(crate) fn normalized(&self, node_id: NodeId, load: i64) -> f64 {
+        match self.capacities.get(&node_id) {
+            Some(capacity) if *capacity > 0 => load as f64 / *capacity as f64,
+            _ => load as f64,
+        }
+    }
+
+    fn overflow_of(&self, node_id: NodeId, load: i64) -> f64 {
+        match self.capacities.get(&node_id) {
+            Some(capacity) if load > *capacity as i64 => {
+                (load - *capacity as i64) as f64 / (*capacity).max(1) as f64
+            }
+            _ => 0.0,
+        }
+    }
+
+    pub(crate) fn add(&mut self, node_id: NodeId, delta: i64) {
+        let Some(load) = self.loads.get(&node_id).copied() else {
+            return;
+        };
+        let (old, new) = (
+            self.normalized(node_id, load),
+            self.normalized(node_id, load + delta),
+        );
+        self.sum += new - old;
+        self.sum_of_squares += new * new - old * old;
+        self.overflow += self.overflow_of(node_id, load + delta) - self.overflow_of(node_id, load);
+        self.loads.insert(node_id, load + delta);
+    }
+
+    /// Mean of the normalized node loads
+    pub(crate) fn mean(&self) -> f64 {
+        if self.loads.is_empty() {
+            return 0.0;
+        }
+        self.sum / self.loads.len() as f64
+    }
+
+    /// Standard deviation of the normalized node loads
+    pub(crate) fn std_dev(&self) -> f64 {
+        if self.loads.is_empty() {
+            return 0.0;
+        }
+        let mean = self.mean();
+        (self.sum_of_squares / self.loads.len() as f64 - mean * mean)
+            .max(0.0)
+            .sqrt()
+    }
+
+    /// Coefficient of variation of the normalized node loads
+    pub(crate) fn imbalance(&self) -> f64 {
+        if self.loads.is_empty() || self.sum <= 0.0 {
+            return 0.0;
+        }
+        self.std_dev() / self.mean()
+    }
+}
+
+/// Per-metric node loads of the nodes that are up, kept up to date while movements are tried out. The resource
+/// governance metrics are not balanced, their node capacities being hard limits only.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LoadTable {
+    pub(crate) metrics: BTreeMap<String, MetricLoads>,
+    /// Role of the replicas added or swapped by the movements applied so far, the snapshot holding the others
+    roles: BTreeMap<(Uuid, NodeId), ReplicaRole>,
+}
+
+impl LoadTable {
+    pub(crate) fn new(snapshot: &ClusterSnapshot) -> Self {
+        let mut metrics = BTreeMap::<String, MetricLoads>::new();
+        for service in snapshot.services.values() {
+            for metric in snapshot
+                .service_metrics(service)
+                .filter(|metric| !metric.is_rg_metric())
+            {
+                let metric_loads = metrics.entry(String::from(metric.name())).or_default();
+                metric_loads.weight = metric_loads.weight.max(metric.weight);
+            }
+        }
+        for (metric_name, metric_loads) in metrics.iter_mut() {
+            for (node_id, node) in snapshot.nodes.iter().filter(|(_, node)| node.is_up()) {
+                if let Some(capacity) = node.capacity(metric_name) {
+                    metric_loads.capacities.insert(*node_id, capacity);
+                }
+                metric_loads.loads.insert(*node_id, 0);
+                metric_loads.add(*node_id, snapshot.node_load(*node_id, metric_name) as i64);
+            }
+        }
+
+        LoadTable {
+            metrics,
+            roles: BTreeMap::new(),
+        }
+    }
+
+    /// Weighted sum of the metric imbalances, plus a large penalty for every capacity overflow.
+    /// The lower the better, 0 means perfectly balanced.
+    pub(crate) fn score(&self) -> f64 {
+        self.metrics
+            .values()
+            .map(|metric| {
+                metric.weight * metric.imbalance() + CAPACITY_OVERFLOW_PENALTY * metric.overflow
+            })
+            .sum()
+    }
+
+    /// Total capacity overflow over all the metrics
+    pub(crate) fn overflow(&self) -> f64 {
+        self.metrics.values().map(|metric| metric.overflow).sum()
+    }
+
+    /// Weighted sum of the normalized loads of the node over all the metrics
+    pub(crate) fn weighted_node_load(&self, node_id: NodeId) -> f64 {
+        self.metrics
+            .values()
+            .filter_map(|metric| {
+                let load = metric.loads.get(&node_id)?;
+                Some(metric.weight * metric.normalized(node_id, *load))
+            })
+            .sum()
+    }
+
+    pub(crate) fn move_replica(
+        &mut self,
+        snapshot: &ClusterSnapshot,
+        fu: &FailoverUnit,
+        role: ReplicaRole,
+        from: NodeId,
+        to: NodeId,
+    ) {
+        for (metric_name, load) in snapshot.replica_loads(fu, role) {
+            if let Some(metric) = self.metrics.get_mut(&metric_name) {
+                metric.add(from, -(load as i64));
+                metric.add(to, load as i64);
+            }
+        }
+    }
+
+    /// Moves the difference between the primary and the secondary load from `from` to `to`
+    pub(crate) fn swap_primary(
+        &mut self,
+        snapshot: &ClusterSnapshot,
+        fu: &FailoverUnit,
+        from: NodeId,
+        to: NodeId,
+    ) {
+        let primary_loads = snapshot.replica_loads(fu, ReplicaRole::Primary);
+        let secondary_loads = snapshot.replica_loads(fu, ReplicaRole::Secondary);
+        for ((metric_name, primary_load), (_, secondary_load)) in
+            primary_loads.into_iter().zip(secondary_loads)
+        {
+            if let Some(metric) = self.metrics.get_mut(&metric_name) {
+                let delta = primary_load as i64 - secondary_load as i64;
+                metric.add(from, -delta);
+                metric.add(to, delta);
+            }
+        }
+    }
+
+    /// Applies the load change of a movement planned against the snapshot, after the movements applied so far
+    pub(crate) fn apply_movement(&mut self, snapshot: &ClusterSnapshot, movement: &Movement) {
+        let fu_id = movement.fu_id();
+        let Some(fu) = snapshot.failover_units.get(&fu_id) else {
+            return;
+        };
+        match *movement {
+            Movement::AddReplica { node, .. } => {
+                self.add_replica(snapshot, fu, ReplicaRole::Secondary, node, 1);
+                self.roles.insert((fu_id, node), ReplicaRole::Secondary);
+            }
+            Movement::DropReplica { node, .. } => {
+                let role = self.roles.remove(&(fu_id, node)).or_else(|| {
+                    fu.active_replicas()
+                        .find(|replica| replica.location() == node)
+                        .map(|replica| replica.role())
+                });
+                if let Some(role) = role {
+                    self.add_replica(snapshot, fu, role, node, -1);
+                }
+            }
+            Movement::MoveReplica { role, from, to, .. } => {
+                self.move_replica(snapshot, fu, role, from, to);
+                if let Some(role) = self.roles.remove(&(fu_id, from)) {
+                    self.roles.insert((fu_id, to), role);
+                }
+            }
+            Movement::SwapPrimary { from, to, .. } => {
+                self.swap_primary(snapshot, fu, from, to);
+                self.roles.insert((fu_id, from), ReplicaRole::Secondary);
+                self.roles.insert((fu_id, to), ReplicaRole::Primary);
+            }
+        }
+    }
+
+    fn add_replica(
+        &mut self,
+        snapshot: &ClusterSnapshot,
+        fu: &FailoverUnit,
+        role: ReplicaRole,
+        node_id: NodeId,
+        sign: i64,
+    ) {
+        for (metric_name, load) in snapshot.replica_loads(fu, role) {
+            if let Some(metric) = self.metrics.get_mut(&metric_name) {
+                metric.add(node_id, sign * load as i64);
+            }
+        }
+    }
+}
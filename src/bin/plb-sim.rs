@@ -0,0 +1,205 @@
+//! Offline PLB simulator.
+//!
+//! Loads a cluster snapshot written by `PlacementAndLoadBalancing::dump_snapshot`, then advances a simulated clock
+//! tick by tick. On every tick PLB refreshes, and the generated solutions are applied back to the simulated cluster
+//! and acknowledged the way FM would report them, so they are part of the snapshot on the next tick. The node load table seen by the
+//! refresh, the constraint violations and the solutions of every phase are printed on every tick.
+//!
+//! Instead of a cluster file, `--generate <node count>` simulates a synthetic cluster with the default generator
+//! parameters, one partition per node in each service and the given seed.
+//!
+//! Usage: plb-sim <cluster.json | --generate <node count> [--seed <seed>]> [--ticks <count>] [--tick-seconds <seconds>]
+//!                [--balancing <dummy|greedy|annealing>]
+//!
+//! The simulator needs the `serde` feature: `cargo run --features serde --bin plb-sim -- <arguments>`
+
+use std::{collections::BTreeMap, env, process};
+
+use anyhow::{anyhow, Context, Result};
+use plb_rs::{
+    config::{PLBConfig, SearchStrategyKind},
+    generator::ClusterSpec,
+    node::node_id::NodeId,
+    pending::MovementOutcome,
+    PlacementAndLoadBalancing,
+};
+use time::{Duration, OffsetDateTime};
+
+struct Arguments {
+    cluster_file: String,
+    generated_nodes: Option<usize>,
+    seed: u64,
+    ticks: u32,
+    tick_seconds: i64,
+    balancing: SearchStrategyKind,
+}
+
+fn parse_strategy(name: &str) -> Result<SearchStrategyKind> {
+    match name.to_ascii_lowercase().as_str() {
+        "dummy" => Ok(SearchStrategyKind::Dummy),
+        "greedy" => Ok(SearchStrategyKind::Greedy),
+        "annealing" => Ok(SearchStrategyKind::Annealing),
+        _ => Err(anyhow!("Unknown search strategy {}", name)),
+    }
+}
+
+fn parse_arguments() -> Result<Arguments> {
+    let mut args = env::args().skip(1);
+    let mut arguments = Arguments {
+        cluster_file: String::new(),
+        generated_nodes: None,
+        seed: 0,
+        ticks: 10,
+        tick_seconds: 1,
+        balancing: PLBConfig::default().balancing_strategy,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--ticks" => arguments.ticks = value()?.parse().context("Invalid tick count")?,
+            "--tick-seconds" => {
+                arguments.tick_seconds = value()?.parse().context("Invalid tick duration")?
+            }
+            "--generate" => {
+                arguments.generated_nodes = Some(value()?.parse().context("Invalid node count")?)
+            }
+            "--seed" => arguments.seed = value()?.parse().context("Invalid seed")?,
+            "--balancing" => arguments.balancing = parse_strategy(&value()?)?,
+            _ if arguments.cluster_file.is_empty() && !arg.starts_with("--") => {
+                arguments.cluster_file = arg
+            }
+            _ => return Err(anyhow!("Unexpected argument {}", arg)),
+        }
+    }
+    if arguments.cluster_file.is_empty() == arguments.generated_nodes.is_none() {
+        return Err(anyhow!("Expected either a cluster file or --generate"));
+    }
+    if arguments.tick_seconds <= 0 {
+        return Err(anyhow!("The tick duration must be positive"));
+    }
+
+    Ok(arguments)
+}
+
+fn print_node_loads(node_loads: &BTreeMap<NodeId, BTreeMap<String, u32>>) {
+    let metric_names = node_loads
+        .values()
+        .next()
+        .map(|loads| loads.keys().cloned().collect::<Vec<String>>())
+        .unwrap_or_default();
+    let width = metric_names
+        .iter()
+        .map(|metric_name| metric_name.len())
+        .max()
+        .unwrap_or_default()
+        .max(8);
+
+    print!("  {:<8}", "Node");
+    for metric_name in metric_names.iter() {
+        print!(" {:>width$}", metric_name);
+    }
+    println!();
+    for (node_id, loads) in node_loads {
+        print!("  {:<8}", node_id.to_string());
+        for load in loads.values() {
+            print!(" {:>width$}", load);
+        }
+        println!();
+    }
+}
+
+fn run(arguments: Arguments) -> Result<()> {
+    let mut plb = match arguments.generated_nodes {
+        Some(node_count) => ClusterSpec {
+            node_count,
+            partitions_per_service: node_count,
+            seed: arguments.seed,
+            ..Default::default()
+        }
+        .generate()
+        .into_plb()?,
+        None => PlacementAndLoadBalancing::from_snapshot_file(&arguments.cluster_file)?,
+    };
+    plb.set_config(PLBConfig {
+        balancing_strategy: arguments.balancing,
+        ..Default::default()
+    });
+
+    let start = OffsetDateTime::now_utc();
+    let tick = Duration::seconds(arguments.tick_seconds);
+    for tick_index in 1..=arguments.ticks {
+        let now = start + tick * tick_index;
+        let report = plb.refresh(now)?;
+
+        println!("Tick {} (+{}s)", tick_index, (now - start).whole_seconds());
+        println!("Node loads:");
+        print_node_loads(&plb.node_loads());
+        for violation in report.violations.iter() {
+            println!("  Violation {:?}", violation);
+        }
+        for follow_up in report.follow_ups.iter() {
+            println!("  Follow-up {:?}", follow_up.movement.to_solution());
+        }
+        for phase in report.phases.iter() {
+            println!(
+                "{:?} phase ({}, {:.3}ms), solutions ({}):",
+                phase.phase,
+                phase.strategy,
+                phase.duration.as_secs_f64() * 1000.0,
+                phase.solutions.len()
+            );
+            for solution in phase.solutions.iter() {
+                println!("  {:?}", solution);
+            }
+            for dropped in phase.dropped.iter() {
+                println!(
+                    "  Dropped {:?}: {:?}",
+                    dropped.movement.to_solution(),
+                    dropped.violation
+                );
+            }
+        }
+
+        // apply the movements back to the simulated cluster, they are picked up by the next refresh, and acknowledge
+        // them the way FM would
+        let mut fu_descs = BTreeMap::new();
+        let mut outcomes = vec![];
+        for (id, movement) in report.solution_ids().into_iter().zip(plb.last_movements()) {
+            let fu_id = movement.fu_id();
+            let Some(fu_desc) = fu_descs
+                .entry(fu_id)
+                .or_insert_with(|| plb.failover_unit_description(fu_id))
+            else {
+                outcomes.push((id, MovementOutcome::Failed));
+                continue;
+            };
+            match fu_desc.apply_movement(movement) {
+                Ok(()) => outcomes.push((id, MovementOutcome::Succeeded)),
+                Err(err) => {
+                    println!("  Rejected {:?}: {}", movement.to_solution(), err);
+                    outcomes.push((id, MovementOutcome::Failed));
+                }
+            }
+        }
+        for fu_desc in fu_descs.into_values().flatten() {
+            plb.update_failover_unit(fu_desc);
+        }
+        for (id, outcome) in outcomes {
+            plb.acknowledge_solution(id, outcome)?;
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let result = parse_arguments().and_then(run);
+    if let Err(err) = result {
+        eprintln!("plb-sim: {:#}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,261 @@
+//! This module reports the free capacity of the cluster, to plan when and how to grow it.
+//!
+//! The free capacity of a node for a metric is its capacity minus the load of its replicas. Only the nodes that accept
+//! new replicas are counted, i.e. up and not being deactivated, and only for the metrics they declare a capacity for:
+//! a node without a capacity for a metric can take any load of it. The free capacities are aggregated per fault
+//! domain, per node type, the value of the [NODE_TYPE_PROPERTY] node property, and for the whole cluster.
+//!
+//! The headroom of a metric is the free capacity of the cluster once the application reservations are set aside: an
+//! application reserves its reservation capacity on each of its minimum nodes, and the part of the reservation its
+//! replicas do not use yet is not available to the other applications.
+//!
+//! The number of nodes to add for the unplaceable replicas is found by simulating the placement with more and more
+//! nodes of a given shape, see [crate::whatif].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+
+use crate::{
+    config::PLBConfig,
+    node::{node_description::DomainId, node_description::NodeDescription, node_id::NodeId},
+    pending::PendingMovements,
+    whatif::{TopologyChange, WhatIfSimulator},
+    ClusterSnapshot,
+};
+
+/// The node property holding the type of the node
+pub const NODE_TYPE_PROPERTY: &str = "NodeType";
+
+/// Capacity and load of a set of nodes for a metric
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetricCapacity {
+    pub capacity: u64,
+    pub load: u64,
+    /// Capacity left on the nodes, a node whose load exceeds its capacity has none
+    pub free: u64,
+}
+
+impl MetricCapacity {
+    fn add(&mut self, other: &MetricCapacity) {
+        self.capacity += other.capacity;
+        self.load += other.load;
+        self.free += other.free;
+    }
+}
+
+/// Free capacity per metric
+pub type MetricCapacities = BTreeMap<String, MetricCapacity>;
+
+/// The capacity planning report of the cluster, see [crate::capacity]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapacityReport {
+    pub nodes: BTreeMap<NodeId, MetricCapacities>,
+    pub fault_domains: BTreeMap<DomainId, MetricCapacities>,
+    /// Free capacity per node type, the nodes without a type being under the empty string
+    pub node_types: BTreeMap<String, MetricCapacities>,
+    pub cluster: MetricCapacities,
+    /// Part of the application reservations not used by their replicas yet, per metric
+    pub unused_reservations: BTreeMap<String, u64>,
+    /// Free capacity of the cluster once the unused reservations are set aside, per metric. Negative when the
+    /// applications reserve more than is left.
+    pub headroom: BTreeMap<String, i64>,
+    /// The largest load per replica of a partition that still fits on the nodes, per metric and per replica count of
+    /// the partitions of the snapshot, see [CapacityReport::largest_placeable_partition]
+    pub largest_placeable_load: BTreeMap<String, BTreeMap<usize, u64>>,
+    /// Replicas the placement cannot place
+    pub unplaceable_replicas: u32,
+    /// Number of nodes of the requested shape to add for every unplaceable replica to be placed, None if adding as many
+    /// nodes as there are unplaceable replicas does not do
+    pub additional_nodes: Option<u32>,
+}
+
+impl CapacityReport {
+    /// Computes the free capacities of the snapshot, without the unplaceable replicas
+    pub(crate) fn new(snapshot: &ClusterSnapshot) -> Self {
+        let mut report = CapacityReport::default();
+        for (node_id, node) in &snapshot.nodes {
+            if !node.accepts_new_replicas() {
+                continue;
+            }
+            let capacities = node
+                .node_description
+                .capacities
+                .iter()
+                .map(|(metric_name, capacity)| {
+                    let load = snapshot.node_load(*node_id, metric_name) as u64;
+                    let capacity = *capacity as u64;
+                    let metric_capacity = MetricCapacity {
+                        capacity,
+                        load,
+                        free: capacity.saturating_sub(load),
+                    };
+                    (metric_name.clone(), metric_capacity)
+                })
+                .collect::<MetricCapacities>();
+            let node_type = node.property(NODE_TYPE_PROPERTY).unwrap_or_default();
+            for aggregate in [
+                report
+                    .fault_domains
+                    .entry(DomainId::from(node.fault_domain()))
+                    .or_default(),
+                report
+                    .node_types
+                    .entry(String::from(node_type))
+                    .or_default(),
+                &mut report.cluster,
+            ] {
+                for (metric_name, metric_capacity) in &capacities {
+                    aggregate
+                        .entry(metric_name.clone())
+                        .or_default()
+                        .add(metric_capacity);
+                }
+            }
+            report.nodes.insert(*node_id, capacities);
+        }
+
+        report.unused_reservations = unused_reservations(snapshot);
+        let metric_names = report
+            .cluster
+            .keys()
+            .chain(report.unused_reservations.keys())
+            .cloned()
+            .collect::<Vec<String>>();
+        for metric_name in metric_names {
+            let free = report
+                .cluster
+                .get(&metric_name)
+                .map(|metric_capacity| metric_capacity.free)
+                .unwrap_or_default();
+            let reserved = report
+                .unused_reservations
+                .get(&metric_name)
+                .copied()
+                .unwrap_or_default();
+            report
+                .headroom
+                .insert(metric_name, free as i64 - reserved as i64);
+        }
+        // the replicas a partition targets are the active ones plus the missing ones
+        let replica_counts = snapshot
+            .failover_units
+            .values()
+            .map(|fu| (fu.active_replicas().count() as i32 + fu.replia_diff()).max(1) as usize)
+            .collect::<BTreeSet<usize>>();
+        report.largest_placeable_load = report
+            .cluster
+            .keys()
+            .map(|metric_name| {
+                let loads = replica_counts
+                    .iter()
+                    .map(|replica_count| {
+                        (
+                            *replica_count,
+                            report.largest_placeable_partition(metric_name, *replica_count),
+                        )
+                    })
+                    .collect();
+                (metric_name.clone(), loads)
+            })
+            .collect();
+
+        report
+    }
+
+    /// The largest load per replica of a partition with the given number of replicas that still fits on the nodes,
+    /// each replica on a distinct node. Other constraints, like the fault domains, are not considered.
+    pub fn largest_placeable_partition(&self, metric_name: &str, replica_count: usize) -> u64 {
+        let mut free = self
+            .nodes
+            .values()
+            .filter_map(|capacities| capacities.get(metric_name))
+            .map(|metric_capacity| metric_capacity.free)
+            .collect::<Vec<u64>>();
+        free.sort_unstable_by(|a, b| b.cmp(a));
+        replica_count
+            .checked_sub(1)
+            .and_then(|index| free.get(index))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// The part of the reservation of each application not used by its replicas, summed per metric
+fn unused_reservations(snapshot: &ClusterSnapshot) -> BTreeMap<String, u64> {
+    let mut unused = BTreeMap::<String, u64>::new();
+    for (app_name, app) in &snapshot.apps {
+        let app_desc = &app.application_desc;
+        let minimum_nodes = app_desc.minimum_nodes.max(0) as u64;
+        if minimum_nodes == 0 || app_desc.capacities.is_empty() {
+            continue;
+        }
+        let mut loads = BTreeMap::<String, u64>::new();
+        for service in snapshot.services_of_application(app_name) {
+            for fu in snapshot.failover_units_of_service(service.servcie_name()) {
+                for replica in fu.active_replicas() {
+                    for (metric_name, load) in snapshot.replica_loads(fu, replica.role()) {
+                        *loads.entry(metric_name).or_default() += load as u64;
+                    }
+                }
+            }
+        }
+        for capacity in app_desc.capacities.values() {
+            let reserved = capacity.reservation_capacity.max(0) as u64 * minimum_nodes;
+            let load = loads
+                .get(&capacity.metric_name)
+                .copied()
+                .unwrap_or_default();
+            *unused.entry(capacity.metric_name.clone()).or_default() +=
+                reserved.saturating_sub(load);
+        }
+    }
+
+    unused
+}
+
+/// Counts the replicas the placement cannot place, and searches the smallest number of nodes of the shape to add for
+/// all of them to be placed. The added nodes are given ids after the greatest node id of the snapshot.
+pub(crate) fn plan_additional_nodes(
+    snapshot: &ClusterSnapshot,
+    pending: &PendingMovements,
+    config: &PLBConfig,
+    node_shape: &NodeDescription,
+) -> Result<(u32, Option<u32>)> {
+    let first_id = snapshot
+        .nodes
+        .keys()
+        .next_back()
+        .map(|node_id| node_id.id_value + 1)
+        .unwrap_or_default();
+    let unplaceable_with = |node_count: u32| -> Result<u32> {
+        let changes = (0..node_count as u128)
+            .map(|index| {
+                let mut node_desc = node_shape.clone();
+                node_desc.node_instance.id = NodeId::new(first_id + index);
+                TopologyChange::AddNode(node_desc)
+            })
+            .collect::<Vec<TopologyChange>>();
+        Ok(WhatIfSimulator::new(snapshot, pending, config, &changes)?.unplaceable_replica_count())
+    };
+
+    let unplaceable = unplaceable_with(0)?;
+    if unplaceable == 0 {
+        return Ok((0, Some(0)));
+    }
+    if unplaceable_with(unplaceable)? > 0 {
+        return Ok((unplaceable, None));
+    }
+    // the smallest node count placing every replica is in (low, high]
+    let (mut low, mut high) = (0, unplaceable);
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        if unplaceable_with(middle)? == 0 {
+            high = middle;
+        } else {
+            low = middle;
+        }
+    }
+
+    Ok((unplaceable, Some(high)))
+}
@@ -0,0 +1,109 @@
+//! This module contains the configuration of the PLB engine
+
+use std::collections::BTreeMap;
+
+use time::Duration;
+
+use crate::{
+    annealing::AnnealingConfig,
+    forecast::LoadForecastConfig,
+    scheduler::Phase,
+    service::{built_in_type::BuiltInType, service_metric::ServiceMetric},
+};
+
+/// The built-in search strategies, see [crate::strategy]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SearchStrategyKind {
+    /// Places new replicas on the node with the greatest node id and never balances
+    Dummy,
+    /// Places new replicas on the least loaded node and balances by repeatedly taking the best single move
+    Greedy,
+    /// Places new replicas like the greedy strategy and balances with a simulated annealing search. The search is
+    /// bounded by a wall-clock budget, so its solutions depend on the machine unless `max_iterations` is reached first.
+    Annealing,
+}
+
+/// Configuration of the PLB engine
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PLBConfig {
+    /// Search strategy used by the placement phase
+    pub placement_strategy: SearchStrategyKind,
+    /// Search strategy used by the load balancing phase. Greedy by default, so that the movements of a refresh are
+    /// bounded by `greedy_max_moves` and reproducible.
+    pub balancing_strategy: SearchStrategyKind,
+    /// Search strategy used by the constraint check phase
+    pub constraint_check_strategy: SearchStrategyKind,
+    /// Settings of the simulated annealing search
+    pub annealing: AnnealingConfig,
+    /// Maximum number of moves the greedy strategy generates in a phase
+    pub greedy_max_moves: usize,
+    /// Whether the greedy and annealing strategies balance by swapping the primary and secondary roles within the
+    /// failover units first, which copies no data, before moving replicas. At most `greedy_max_moves` primaries are
+    /// swapped in a phase, the swaps counting against the moves of the greedy strategy.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub primary_swap_balancing: bool,
+    /// Time after which a solution FM did not acknowledge is no longer considered in flight
+    #[cfg_attr(feature = "serde", serde(default = "default_pending_movement_timeout"))]
+    pub pending_movement_timeout: Duration,
+    /// Load above which a node counts as active for a metric in the cluster score, 0 for the metrics not listed
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub metric_activity_thresholds: BTreeMap<String, u32>,
+    /// Settings of the load forecasting of the predicted metrics
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub load_forecast: LoadForecastConfig,
+    /// Metrics every service has on top of the ones it declares, unless it declares a metric with the same name. By
+    /// default the built-in PrimaryCount, ReplicaCount and Count metrics, so that the replica counts are balanced even
+    /// without any reported load.
+    #[cfg_attr(feature = "serde", serde(default = "default_metrics"))]
+    pub default_metrics: Vec<ServiceMetric>,
+    /// A reported load more than this factor times the previous load reported for the same replica role is flagged as
+    /// a spike, 0 disables the detection
+    #[cfg_attr(feature = "serde", serde(default = "default_load_spike_factor"))]
+    pub load_spike_factor: f64,
+}
+
+fn default_pending_movement_timeout() -> Duration {
+    Duration::minutes(5)
+}
+
+fn default_load_spike_factor() -> f64 {
+    10.0
+}
+
+fn default_metrics() -> Vec<ServiceMetric> {
+    vec![
+        ServiceMetric::built_in(BuiltInType::PrimaryCount, 1.0),
+        ServiceMetric::built_in(BuiltInType::ReplicaCount, 0.3),
+        ServiceMetric::built_in(BuiltInType::Count, 0.1),
+    ]
+}
+
+impl Default for PLBConfig {
+    fn default() -> Self {
+        PLBConfig {
+            placement_strategy: SearchStrategyKind::Dummy,
+            balancing_strategy: SearchStrategyKind::Greedy,
+            constraint_check_strategy: SearchStrategyKind::Greedy,
+            annealing: AnnealingConfig::default(),
+            greedy_max_moves: 100,
+            primary_swap_balancing: false,
+            pending_movement_timeout: default_pending_movement_timeout(),
+            metric_activity_thresholds: BTreeMap::new(),
+            load_forecast: LoadForecastConfig::default(),
+            default_metrics: default_metrics(),
+            load_spike_factor: default_load_spike_factor(),
+        }
+    }
+}
+
+impl PLBConfig {
+    pub fn strategy_for(&self, phase: Phase) -> SearchStrategyKind {
+        match phase {
+            Phase::Placement => self.placement_strategy,
+            Phase::LoadBalancing => self.balancing_strategy,
+            Phase::ConstraintCheck => self.constraint_check_strategy,
+        }
+    }
+}
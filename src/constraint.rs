@@ -0,0 +1,497 @@
+//! This module contains the hard placement constraints that every replica placement or movement has to respect
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::{
+    failoverunit::failover_unit::{FailoverUnit, ReplicaRole},
+    governance::{reserved_load, PackageKey, PackageUsage},
+    node::{node::Node, node_id::NodeId},
+    service::service_package::{is_rg_metric_name, ServicePackageDescription},
+    ClusterSnapshot,
+};
+
+/// The reason a node cannot host a replica of a failover unit
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NodeRejectReason {
+    /// The node is not part of the cluster snapshot
+    NodeNotFound,
+    /// The node is down
+    NodeDown,
+    /// The node is being deactivated and does not accept new replicas
+    NodeDeactivating,
+    /// The node properties do not satisfy the placement constraints of the service
+    PlacementConstraintNotSatisfied,
+    /// The node is in the block list of the service type
+    BlockListed,
+    /// The upgrade domain of the node is being upgraded for the service of the failover unit
+    UpgradeDomainUpgrading,
+    /// The node already hosts a replica of the failover unit
+    ReplicaAlreadyOnNode,
+    /// Another replica of the failover unit is hosted in the fault domain of the node
+    FaultDomainAlreadyUsed,
+    /// The node does not host any replica of the parent service the service is affinitized with
+    AffinityParentAbsent,
+    /// Placing the replica would exceed the node capacity for the metric
+    CapacityExceeded(String),
+}
+
+/// A hard constraint broken by the current placement of the cluster
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstraintViolation {
+    /// The aggregated load of the node exceeds its capacity for the metric
+    CapacityExceeded {
+        node_id: NodeId,
+        metric_name: String,
+        load: u32,
+        capacity: u32,
+    },
+    /// A replica is hosted on a node it could not be placed on. The constraint check phase moves it to a node passing
+    /// every hard constraint, if there is one.
+    ReplicaMisplaced {
+        fu_id: Uuid,
+        node_id: NodeId,
+        reason: NodeRejectReason,
+    },
+}
+
+impl ConstraintViolation {
+    /// The name of the kind of violation, e.g. to group the violations in metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ConstraintViolation::CapacityExceeded { .. } => "CapacityExceeded",
+            ConstraintViolation::ReplicaMisplaced { .. } => "ReplicaMisplaced",
+        }
+    }
+
+    /// The names of all the kinds of violations
+    pub const KINDS: [&'static str; 2] = ["CapacityExceeded", "ReplicaMisplaced"];
+}
+
+/// Why each node of the cluster can or cannot host a new replica of a failover unit
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlacementExplanation {
+    pub fu_id: Uuid,
+    /// Every reason each node is rejected for, empty for the nodes that can host the replica
+    pub nodes: BTreeMap<NodeId, Vec<NodeRejectReason>>,
+    /// Number of nodes rejected for each reason. A node rejected for several reasons is counted for each of them.
+    pub rejection_counts: BTreeMap<NodeRejectReason, usize>,
+}
+
+impl PlacementExplanation {
+    /// The nodes that can host the replica
+    pub fn eligible_nodes(&self) -> Vec<NodeId> {
+        self.nodes
+            .iter()
+            .filter(|(_, reasons)| reasons.is_empty())
+            .map(|(node_id, _)| *node_id)
+            .collect()
+    }
+}
+
+impl ClusterSnapshot {
+    /// Returns the (node, metric) pairs whose aggregated load exceeds the node capacity
+    pub(crate) fn capacity_violations(&self) -> Vec<(NodeId, String)> {
+        let metric_names = self.metric_names();
+        self.nodes
+            .iter()
+            .flat_map(|(node_id, node)| {
+                metric_names.iter().filter_map(move |metric_name| {
+                    let capacity = node.capacity(metric_name)?;
+                    (self.node_load(*node_id, metric_name) > capacity)
+                        .then(|| (*node_id, metric_name.clone()))
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the hard constraints broken by the current placement: the node capacities exceeded, then the replicas
+    /// hosted on nodes failing the placement constraints, block list, fault domain or affinity of their service
+    pub(crate) fn constraint_violations(&self) -> Vec<ConstraintViolation> {
+        let mut violations = self
+            .capacity_violations()
+            .into_iter()
+            .map(
+                |(node_id, metric_name)| ConstraintViolation::CapacityExceeded {
+                    node_id,
+                    load: self.node_load(node_id, &metric_name),
+                    capacity: self.nodes[&node_id]
+                        .capacity(&metric_name)
+                        .unwrap_or_default(),
+                    metric_name,
+                },
+            )
+            .collect::<Vec<ConstraintViolation>>();
+        for fu in self.failover_units.values() {
+            let service = self.service_of(fu);
+            let service_type = self.service_type_of(fu);
+            let parent_service = service
+                .and_then(|service| service.affinitized_service())
+                .filter(|parent_service| self.services.contains_key(*parent_service));
+            for replica in fu.active_replicas() {
+                let node_id = replica.location();
+                let Some(node) = self.nodes.get(&node_id) else {
+                    continue;
+                };
+                let shares_fault_domain = !node.fault_domain().is_empty()
+                    && fu.active_replicas().any(|other| {
+                        other.location() != node_id
+                            && self.nodes.get(&other.location()).is_some_and(|other_node| {
+                                other_node.fault_domain() == node.fault_domain()
+                            })
+                    });
+                let reason = if service.is_some_and(|service| !service.allows_node(node)) {
+                    NodeRejectReason::PlacementConstraintNotSatisfied
+                } else if service_type
+                    .is_some_and(|service_type| service_type.is_blocked_on(node_id))
+                {
+                    NodeRejectReason::BlockListed
+                } else if shares_fault_domain {
+                    NodeRejectReason::FaultDomainAlreadyUsed
+                } else if parent_service.is_some_and(|parent_service| {
+                    !self
+                        .failover_units_of_service(parent_service)
+                        .any(|parent_fu| parent_fu.has_replica_on(node_id))
+                }) {
+                    NodeRejectReason::AffinityParentAbsent
+                } else {
+                    continue;
+                };
+                violations.push(ConstraintViolation::ReplicaMisplaced {
+                    fu_id: fu.id(),
+                    node_id,
+                    reason,
+                });
+            }
+        }
+
+        violations
+    }
+
+    /// Evaluates every node against the hard constraints for a new replica of the failover unit
+    pub(crate) fn explain_placement(&self, fu: &FailoverUnit) -> PlacementExplanation {
+        let checker = PlacementChecker::new(self);
+        let nodes = self
+            .nodes
+            .keys()
+            .map(|node_id| {
+                (
+                    *node_id,
+                    checker.rejections(fu, ReplicaRole::Secondary, None, *node_id, false),
+                )
+            })
+            .collect::<BTreeMap<NodeId, Vec<NodeRejectReason>>>();
+        let mut rejection_counts = BTreeMap::new();
+        for reason in nodes.values().flatten() {
+            *rejection_counts.entry(reason.clone()).or_default() += 1;
+        }
+
+        PlacementExplanation {
+            fu_id: fu.id(),
+            nodes,
+            rejection_counts,
+        }
+    }
+}
+
+/// Checks nodes against the hard constraints for a sequence of planned movements.
+/// The checker keeps track of the load and replica locations changed by the movements planned so far so that
+/// a plan consisting of multiple movements never overcommits a node.
+pub(crate) struct PlacementChecker<'a> {
+    snapshot: &'a ClusterSnapshot,
+    load_delta: HashMap<(NodeId, String), i64>,
+    /// Change of the replica count of the resource governed packages on each node
+    package_delta: HashMap<NodeId, BTreeMap<PackageKey, PackageUsage>>,
+    added_locations: HashMap<Uuid, HashSet<NodeId>>,
+    removed_locations: HashMap<Uuid, HashSet<NodeId>>,
+}
+
+impl<'a> PlacementChecker<'a> {
+    pub(crate) fn new(snapshot: &'a ClusterSnapshot) -> Self {
+        PlacementChecker {
+            snapshot,
+            load_delta: HashMap::new(),
+            package_delta: HashMap::new(),
+            added_locations: HashMap::new(),
+            removed_locations: HashMap::new(),
+        }
+    }
+
+    /// Returns the load of the node for the metric, including the planned movements
+    pub(crate) fn node_load(&self, node_id: NodeId, metric_name: &str) -> u32 {
+        if is_rg_metric_name(metric_name) {
+            return self.rg_node_load(node_id, metric_name, None);
+        }
+        let delta = self
+            .load_delta
+            .get(&(node_id, String::from(metric_name)))
+            .copied()
+            .unwrap_or_default();
+        (self.snapshot.node_load(node_id, metric_name) as i64 + delta).max(0) as u32
+    }
+
+    /// Returns the load of the node for the resource governance metric, including the planned movements, and one more
+    /// replica of the package if given
+    fn rg_node_load(
+        &self,
+        node_id: NodeId,
+        metric_name: &str,
+        added_package: Option<(&PackageKey, &ServicePackageDescription)>,
+    ) -> u32 {
+        let mut packages = self.snapshot.packages_on(node_id);
+        for (key, delta) in self.package_delta.get(&node_id).into_iter().flatten() {
+            packages.entry(key.clone()).or_default().merge(delta);
+        }
+        if let Some((key, service_package)) = added_package {
+            packages
+                .entry(key.clone())
+                .or_default()
+                .add(1, service_package);
+        }
+        reserved_load(packages.values(), metric_name)
+    }
+
+    /// Whether the failover unit has a replica on the node, including the planned movements
+    pub(crate) fn has_replica_on(&self, fu: &FailoverUnit, node_id: NodeId) -> bool {
+        let added = self
+            .added_locations
+            .get(&fu.id())
+            .is_some_and(|nodes| nodes.contains(&node_id));
+        let removed = self
+            .removed_locations
+            .get(&fu.id())
+            .is_some_and(|nodes| nodes.contains(&node_id));
+        added || (fu.has_replica_on(node_id) && !removed)
+    }
+
+    /// Checks whether a replica of the failover unit with the given role can be placed on the node
+    pub(crate) fn check(
+        &self,
+        fu: &FailoverUnit,
+        role: ReplicaRole,
+        node_id: NodeId,
+    ) -> Result<(), NodeRejectReason> {
+        self.check_move(fu, role, None, node_id)
+    }
+
+    /// Checks whether a replica of the failover unit with the given role can be moved from the `from` node, or
+    /// placed if it is None, to the node
+    pub(crate) fn check_move(
+        &self,
+        fu: &FailoverUnit,
+        role: ReplicaRole,
+        from: Option<NodeId>,
+        node_id: NodeId,
+    ) -> Result<(), NodeRejectReason> {
+        match self.rejections(fu, role, from, node_id, true).pop() {
+            Some(reason) => Err(reason),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the reasons the node cannot host the replica, stopping at the first one if `first_only` is set
+    fn rejections(
+        &self,
+        fu: &FailoverUnit,
+        role: ReplicaRole,
+        from: Option<NodeId>,
+        node_id: NodeId,
+        first_only: bool,
+    ) -> Vec<NodeRejectReason> {
+        let mut reasons = vec![];
+        let mut reject = |reason: NodeRejectReason| {
+            reasons.push(reason);
+            first_only
+        };
+        let Some(node) = self.snapshot.nodes.get(&node_id) else {
+            reject(NodeRejectReason::NodeNotFound);
+            return reasons;
+        };
+        if !node.is_up() && reject(NodeRejectReason::NodeDown) {
+            return reasons;
+        }
+        if node.is_up()
+            && !node.accepts_new_replicas()
+            && reject(NodeRejectReason::NodeDeactivating)
+        {
+            return reasons;
+        }
+        let service = self.snapshot.service_of(fu);
+        if service.is_some_and(|service| !service.allows_node(node))
+            && reject(NodeRejectReason::PlacementConstraintNotSatisfied)
+        {
+            return reasons;
+        }
+        if self
+            .snapshot
+            .service_type_of(fu)
+            .is_some_and(|service_type| service_type.is_blocked_on(node_id))
+            && reject(NodeRejectReason::BlockListed)
+        {
+            return reasons;
+        }
+        if self.snapshot.is_upgrading(fu, node_id)
+            && reject(NodeRejectReason::UpgradeDomainUpgrading)
+        {
+            return reasons;
+        }
+        if self.has_replica_on(fu, node_id) {
+            if reject(NodeRejectReason::ReplicaAlreadyOnNode) {
+                return reasons;
+            }
+        } else if self.fault_domain_in_use(fu, from, node)
+            && reject(NodeRejectReason::FaultDomainAlreadyUsed)
+        {
+            return reasons;
+        }
+        let parent_service = service.and_then(|service| service.affinitized_service());
+        if let Some(parent_service) = parent_service {
+            let hosts_parent = self
+                .snapshot
+                .failover_units_of_service(parent_service)
+                .any(|parent_fu| self.has_replica_on(parent_fu, node_id));
+            if self.snapshot.services.contains_key(parent_service)
+                && !hosts_parent
+                && reject(NodeRejectReason::AffinityParentAbsent)
+            {
+                return reasons;
+            }
+        }
+        for (metric_name, load) in self.snapshot.replica_loads(fu, role) {
+            if let Some(capacity) = node.capacity(&metric_name) {
+                if self.node_load(node_id, &metric_name) as u64 + load as u64 > capacity as u64
+                    && reject(NodeRejectReason::CapacityExceeded(metric_name))
+                {
+                    return reasons;
+                }
+            }
+        }
+        // the replica only adds the reservation of its package if the node does not host the package yet
+        if let Some((key, service_package)) = self.snapshot.package_of(fu) {
+            for (metric_name, _) in service_package.rg_loads() {
+                let Some(capacity) = node.capacity(metric_name) else {
+                    continue;
+                };
+                let load = self.rg_node_load(node_id, metric_name, None);
+                let new_load =
+                    self.rg_node_load(node_id, metric_name, Some((&key, service_package)));
+                if new_load > load
+                    && new_load > capacity
+                    && reject(NodeRejectReason::CapacityExceeded(String::from(
+                        metric_name,
+                    )))
+                {
+                    return reasons;
+                }
+            }
+        }
+
+        reasons
+    }
+
+    /// Whether another replica of the failover unit, other than the one moving away from the `from` node, is hosted
+    /// in the fault domain of the node. Nodes without a fault domain do not share it with any other node.
+    fn fault_domain_in_use(&self, fu: &FailoverUnit, from: Option<NodeId>, node: &Node) -> bool {
+        if node.fault_domain().is_empty() {
+            return false;
+        }
+        let removed = self.removed_locations.get(&fu.id());
+        let added = self.added_locations.get(&fu.id());
+        fu.active_replicas()
+            .map(|replica| replica.location())
+            .filter(|location| removed.is_none_or(|removed| !removed.contains(location)))
+            .chain(added.into_iter().flatten().copied())
+            .filter(|location| Some(*location) != from && *location != node.node_id())
+            .filter_map(|location| self.snapshot.nodes.get(&location))
+            .any(|other| other.fault_domain() == node.fault_domain())
+    }
+
+    /// Picks the least loaded node (summed over the metrics of the replica) that can take the replica.
+    /// `source` is the node the replica currently lives on, if any.
+    pub(crate) fn select_target(
+        &self,
+        fu: &FailoverUnit,
+        role: ReplicaRole,
+        source: Option<NodeId>,
+    ) -> Option<NodeId> {
+        let metrics = self.snapshot.replica_loads(fu, role);
+        self.snapshot
+            .nodes
+            .keys()
+            .copied()
+            .filter(|candidate| Some(*candidate) != source)
+            .filter(|candidate| self.check_move(fu, role, source, *candidate).is_ok())
+            .min_by_key(|candidate| {
+                metrics
+                    .iter()
+                    .map(|(metric_name, _)| self.node_load(*candidate, metric_name) as u64)
+                    .sum::<u64>()
+            })
+    }
+
+    /// Records a planned movement of a replica of the failover unit. `from` is None for a new replica and `to` is
+    /// None for a dropped replica.
+    pub(crate) fn apply(
+        &mut self,
+        fu: &FailoverUnit,
+        role: ReplicaRole,
+        from: Option<NodeId>,
+        to: Option<NodeId>,
+    ) {
+        self.move_load(fu, role, from, to);
+        if let Some(from) = from {
+            self.removed_locations
+                .entry(fu.id())
+                .or_default()
+                .insert(from);
+            if let Some(added) = self.added_locations.get_mut(&fu.id()) {
+                added.remove(&from);
+            }
+        }
+        if let Some(to) = to {
+            self.added_locations.entry(fu.id()).or_default().insert(to);
+            if let Some(removed) = self.removed_locations.get_mut(&fu.id()) {
+                removed.remove(&to);
+            }
+        }
+    }
+
+    /// Moves the load of the primary to a secondary replica swapping roles with it, without changing any replica location
+    pub(crate) fn apply_swap(&mut self, fu: &FailoverUnit, from: NodeId, to: NodeId) {
+        self.move_load(fu, ReplicaRole::Primary, Some(from), Some(to));
+        self.move_load(fu, ReplicaRole::Secondary, Some(to), Some(from));
+    }
+
+    fn move_load(
+        &mut self,
+        fu: &FailoverUnit,
+        role: ReplicaRole,
+        from: Option<NodeId>,
+        to: Option<NodeId>,
+    ) {
+        if let Some((key, service_package)) = self.snapshot.package_of(fu) {
+            for (node_id, replicas) in [(from, -1), (to, 1)] {
+                if let Some(node_id) = node_id {
+                    self.package_delta
+                        .entry(node_id)
+                        .or_default()
+                        .entry(key.clone())
+                        .or_default()
+                        .add(replicas, service_package);
+                }
+            }
+        }
+        for (metric_name, load) in self.snapshot.replica_loads(fu, role) {
+            if let Some(from) = from {
+                *self
+                    .load_delta
+                    .entry((from, metric_name.clone()))
+                    .or_default() -= load as i64;
+            }
+            if let Some(to) = to {
+                *self.load_delta.entry((to, metric_name)).or_default() += load as i64;
+            }
+        }
+    }
+}
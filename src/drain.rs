@@ -0,0 +1,115 @@
+//! This module plans the movements required by node deactivations.
+//!
+//! Depending on the [NodeDeactivationIntent] of the node, the planner either swaps the primaries away from the node
+//! (Restart) or generates an ordered list of moves that fully drains the node (RemoveData / RemoveNode).
+//! Secondaries are moved first so that the primaries, which are the most expensive to move, are moved last.
+
+use uuid::Uuid;
+
+use crate::{
+    constraint::PlacementChecker,
+    failoverunit::failover_unit::{FailoverUnit, ReplicaRole},
+    node::{node_description::NodeDeactivationIntent, node_id::NodeId},
+    promotion::select_new_primary,
+    solver::Movement,
+    ClusterSnapshot,
+};
+
+/// The movements planned for a deactivating node
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DrainPlan {
+    /// Ordered movements to execute
+    pub movements: Vec<Movement>,
+    /// Failover units whose replica cannot be moved away from the node without violating a constraint
+    pub blocked: Vec<Uuid>,
+}
+
+impl DrainPlan {
+    /// Whether executing the movements leaves the node in the state required by its deactivation intent
+    pub fn is_complete(&self) -> bool {
+        self.blocked.is_empty()
+    }
+}
+
+pub(crate) struct DrainPlanner<'a> {
+    snapshot: &'a ClusterSnapshot,
+    checker: PlacementChecker<'a>,
+}
+
+impl<'a> DrainPlanner<'a> {
+    pub(crate) fn new(snapshot: &'a ClusterSnapshot) -> Self {
+        DrainPlanner {
+            snapshot,
+            checker: PlacementChecker::new(snapshot),
+        }
+    }
+
+    /// Plans the movements for the node according to its deactivation intent. Movements planned by previous calls
+    /// on the same planner are taken into account so that draining several nodes never overcommits a target node.
+    pub(crate) fn plan(&mut self, node_id: NodeId) -> DrainPlan {
+        let intent = match self.snapshot.nodes.get(&node_id) {
+            Some(node) => node.deactivation_intent(),
+            None => return DrainPlan::default(),
+        };
+
+        match intent {
+            NodeDeactivationIntent::None | NodeDeactivationIntent::Pause => DrainPlan::default(),
+            NodeDeactivationIntent::Restart => self.plan_primary_swaps(node_id),
+            NodeDeactivationIntent::RemoveData | NodeDeactivationIntent::RemoveNode => {
+                self.plan_moves(node_id)
+            }
+        }
+    }
+
+    fn plan_primary_swaps(&mut self, node_id: NodeId) -> DrainPlan {
+        let mut plan = DrainPlan::default();
+        let fus_with_primary = self
+            .snapshot
+            .replicas_on(node_id)
+            .filter(|(_, replica)| replica.is_primary())
+            .map(|(fu, _)| fu);
+        for fu in fus_with_primary {
+            match select_new_primary(self.snapshot, fu, |candidate| candidate == node_id) {
+                Some(new_primary) => {
+                    self.checker.apply_swap(fu, node_id, new_primary);
+                    plan.movements.push(Movement::SwapPrimary {
+                        fu_id: fu.id(),
+                        from: node_id,
+                        to: new_primary,
+                    });
+                }
+                None => plan.blocked.push(fu.id()),
+            }
+        }
+
+        plan
+    }
+
+    fn plan_moves(&mut self, node_id: NodeId) -> DrainPlan {
+        let mut replicas = self
+            .snapshot
+            .replicas_on(node_id)
+            .map(|(fu, replica)| (fu, replica.role()))
+            .collect::<Vec<(&FailoverUnit, ReplicaRole)>>();
+        // secondaries first, then primaries; failover units are already visited in id order
+        replicas.sort_by_key(|(_, role)| *role == ReplicaRole::Primary);
+
+        let mut plan = DrainPlan::default();
+        for (fu, role) in replicas {
+            match self.checker.select_target(fu, role, Some(node_id)) {
+                Some(target) => {
+                    self.checker.apply(fu, role, Some(node_id), Some(target));
+                    plan.movements.push(Movement::MoveReplica {
+                        fu_id: fu.id(),
+                        role,
+                        from: node_id,
+                        to: target,
+                    });
+                }
+                None => plan.blocked.push(fu.id()),
+            }
+        }
+
+        plan
+    }
+}
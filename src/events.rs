@@ -0,0 +1,161 @@
+//! This module contains the typed events emitted by the PLB engine while it refreshes, for diagnostics and monitoring.
+//!
+//! The engine hands every event to its [PlbEventSink] as it happens: the updates applied to the cluster snapshot,
+//! the phases skipped, started and finished by the scheduler, the violations found by the searcher, the replicas the
+//! solver could not place, the solutions generated and what became of them. The sink is a no-op unless one is set on the engine.
+
+use std::{cell::RefCell, time::Duration};
+
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    annealing::SearchStatistics,
+    constraint::ConstraintViolation,
+    failoverunit::failover_unit::ReplicaRole,
+    pending::{MovementOutcome, PendingMovement, SolutionId},
+    scheduler::Phase,
+    solver::{Movement, Solution},
+    validation::RejectedMovement,
+};
+
+/// The kind of entity an update applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntityKind {
+    Node,
+    Application,
+    ServiceType,
+    Service,
+    FailoverUnit,
+    Load,
+    Upgrade,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlbEvent {
+    /// A queued update was applied to the cluster snapshot at the start of a refresh
+    UpdateApplied {
+        kind: EntityKind,
+        /// Name or id of the entity
+        key: String,
+        /// Whether the update removed the entity from the snapshot
+        deleted: bool,
+    },
+    /// A queued update could not be applied to the cluster snapshot, e.g. the deletion of an entity it does not hold
+    UpdateRejected {
+        kind: EntityKind,
+        key: String,
+        reason: String,
+    },
+    /// The scheduler did not run the phase during the refresh
+    PhaseSkipped {
+        phase: Phase,
+        next_run_time: OffsetDateTime,
+    },
+    PhaseStarted {
+        phase: Phase,
+        /// Name of the search strategy running the phase
+        strategy: String,
+    },
+    PhaseFinished {
+        phase: Phase,
+        strategy: String,
+        /// Wall-clock duration of the search
+        duration: Duration,
+        /// Number of movements generated by the phase
+        movements: usize,
+    },
+    /// A hard constraint broken by the cluster snapshot, found by the constraint check phase
+    ViolationDetected(ConstraintViolation),
+    /// The load reported for the replicas of the failover unit with the role is above the maximum load of the metric,
+    /// and was clamped to it. The secondary role stands for every non-primary replica.
+    LoadClamped {
+        fu_id: Uuid,
+        metric_name: String,
+        role: ReplicaRole,
+        reported: u32,
+        maximum: u32,
+    },
+    /// The load reported for the replicas of the failover unit with the role grew by more than the spike factor of the
+    /// configuration since its previous report
+    LoadSpike {
+        fu_id: Uuid,
+        metric_name: String,
+        role: ReplicaRole,
+        previous: u32,
+        load: u32,
+    },
+    /// No node can take the new replica of the failover unit
+    ReplicaUnplaceable { fu_id: Uuid },
+    /// A movement generated by the search strategy failed the validation of the plan and was left out
+    SolutionDropped {
+        phase: Phase,
+        rejected: RejectedMovement,
+    },
+    SolutionEmitted {
+        phase: Phase,
+        id: SolutionId,
+        solution: Solution,
+    },
+    /// FM acknowledged a pending solution
+    SolutionAcknowledged {
+        id: SolutionId,
+        outcome: MovementOutcome,
+    },
+    /// FM did not acknowledge the solution in time, it is no longer considered in flight
+    PendingMovementExpired(PendingMovement),
+    /// The follow-ups of the pending solution were discarded without being handed out: the solution or one of its
+    /// follow-ups no longer applies to the failover unit reported by FM
+    FollowUpsDropped {
+        id: SolutionId,
+        movements: Vec<Movement>,
+    },
+    /// The incrementally maintained indexes of the cluster snapshot did not match the ones rebuilt from scratch, and
+    /// were rebuilt. Only checked in debug builds and in builds with the `verify-indexes` feature.
+    IndexesOutOfSync { reason: String },
+    /// The search of the phase stopped before running all its iterations
+    SearchAbortedOnTimeBudget {
+        phase: Phase,
+        statistics: SearchStatistics,
+    },
+}
+
+/// Receives the events of the PLB engine. Events are emitted from the refresh loop, so a sink should return quickly.
+pub trait PlbEventSink {
+    fn on_event(&self, event: &PlbEvent);
+}
+
+/// The default sink, dropping every event
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopEventSink;
+
+impl PlbEventSink for NoopEventSink {
+    fn on_event(&self, _event: &PlbEvent) {}
+}
+
+/// Keeps every event in memory, in the order they are emitted. Meant for tests.
+#[derive(Debug, Default)]
+pub struct InMemoryEventSink {
+    events: RefCell<Vec<PlbEvent>>,
+}
+
+impl InMemoryEventSink {
+    pub fn new() -> Self {
+        InMemoryEventSink::default()
+    }
+
+    pub fn events(&self) -> Vec<PlbEvent> {
+        self.events.borrow().clone()
+    }
+
+    /// Returns the events collected so far and clears them
+    pub fn take(&self) -> Vec<PlbEvent> {
+        self.events.take()
+    }
+}
+
+impl PlbEventSink for InMemoryEventSink {
+    fn on_event(&self, event: &PlbEvent) {
+        self.events.borrow_mut().push(event.clone());
+    }
+}
@@ -0,0 +1,628 @@
+//! This module contains an exact solver for small clusters, used to measure how far the heuristic search strategies
+//! are from the optimal placement.
+//!
+//! The solver enumerates the placements of every active replica hosted on a node that is up with a depth first
+//! branch and bound search. A replica can stay where it is or move to any node passing the hard constraints, and the
+//! node capacities are never exceeded. The cheapest placement in terms of the cluster score is returned. The roles
+//! of the replicas are kept, primary swaps are not searched, so the optimum is only over the replica moves.
+//!
+//! The constraints depending on the other replicas are checked against the replicas placed so far: the fault domains
+//! used by the other replicas of the failover unit, including the ones on nodes that are down, and the nodes hosting
+//! a replica of the parent service of an affinity. The replicas of a parent service are placed before the replicas of
+//! the services affinitized with it.
+//!
+//! Partial placements are pruned with a lower bound: the load of the replicas still to be placed is spread as a
+//! continuous quantity over the least loaded nodes, which is the most balanced any completion can be. The bound is
+//! only computed for the metrics whose nodes all have the same capacity, the other metrics contribute 0 to it.
+//! The search is exponential in the number of replicas, so it is limited to small service domains.
+
+use std::collections::BTreeSet;
+
+use anyhow::{anyhow, Result};
+use uuid::Uuid;
+
+use crate::{
+    balance::LoadTable,
+    constraint::{NodeRejectReason, PlacementChecker},
+    failoverunit::failover_unit::ReplicaRole,
+    node::node_id::NodeId,
+    solver::Movement,
+    ClusterSnapshot,
+};
+
+/// Tolerance used when comparing scores
+const SCORE_EPSILON: f64 = 1e-12;
+
+/// Size limits of the exact solver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExactSolverLimits {
+    /// Maximum number of nodes that are up
+    pub max_nodes: usize,
+    /// Maximum number of active replicas hosted on the nodes that are up
+    pub max_replicas: usize,
+    /// Maximum number of partial placements explored. A search reaching it returns the best placement found so far,
+    /// which is not proven to be optimal.
+    pub max_explored: u64,
+}
+
+impl Default for ExactSolverLimits {
+    fn default() -> Self {
+        ExactSolverLimits {
+            max_nodes: 8,
+            max_replicas: 30,
+            max_explored: 10_000_000,
+        }
+    }
+}
+
+/// The best placement found by the exact solver
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExactSolution {
+    /// The movements leading from the current placement to the best placement
+    pub movements: Vec<Movement>,
+    /// The cluster score of the best placement
+    pub score: f64,
+    /// Number of partial placements explored
+    pub explored: u64,
+    /// Whether the whole search space was covered, so that the placement is proven to be optimal
+    pub proven_optimal: bool,
+}
+
+/// How a heuristic solution compares to the optimal one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OptimalityGap {
+    /// The cluster score after the heuristic movements
+    pub heuristic_score: f64,
+    /// The cluster score of the best placement found by the exact solver
+    pub optimal_score: f64,
+    /// `heuristic_score - optimal_score`. It can be negative if the optimum is not proven, if the heuristic movements
+    /// break a hard constraint the exact solver respects, or if they swap primaries, which the exact solver does not
+    /// search.
+    pub gap: f64,
+    /// Whether the optimal score is proven to be optimal
+    pub proven_optimal: bool,
+}
+
+/// A replica to place
+struct Slot {
+    fu_id: Uuid,
+    role: ReplicaRole,
+    /// Index of the node currently hosting the replica
+    origin: usize,
+    /// Load of the replica per metric index
+    loads: Vec<i64>,
+    /// Whether the node of each index can host the replica, not considering the capacities, the other replicas of the
+    /// failover unit and the affinity
+    allowed: Vec<bool>,
+    /// Index of the service of the replica in [Search::hosted]
+    service: usize,
+    /// Index of the parent service the service is affinitized with in [Search::hosted], if it is in the snapshot
+    parent: Option<usize>,
+    /// Number of affinity parents above the service
+    affinity_depth: usize,
+}
+
+struct Metric {
+    weight: f64,
+    /// Capacity per node index, if the node has one
+    capacities: Vec<Option<u32>>,
+    /// The scale shared by all the nodes when normalizing the loads, if the nodes have the same capacity
+    uniform_scale: Option<f64>,
+}
+
+impl Metric {
+    fn normalized(&self, node: usize, load: i64) -> f64 {
+        match self.capacities[node] {
+            Some(capacity) if capacity > 0 => load as f64 / capacity as f64,
+            _ => load as f64,
+        }
+    }
+}
+
+/// Coefficient of variation of the normalized node loads, the same way [LoadTable] computes it
+fn imbalance(levels: &[f64]) -> f64 {
+    let count = levels.len() as f64;
+    let sum = levels.iter().sum::<f64>();
+    if count == 0.0 || sum <= 0.0 {
+        return 0.0;
+    }
+    let mean = sum / count;
+    let variance =
+        (levels.iter().map(|level| level * level).sum::<f64>() / count - mean * mean).max(0.0);
+    variance.sqrt() / mean
+}
+
+/// Lowest imbalance reachable by spreading `remaining` over the levels as a continuous quantity
+fn water_filled_imbalance(mut levels: Vec<f64>, remaining: f64) -> f64 {
+    levels.sort_by(f64::total_cmp);
+    let mut remaining = remaining;
+    let mut level = levels[0];
+    let mut filled = 1;
+    loop {
+        let next = levels.get(filled).copied().unwrap_or(f64::INFINITY);
+        let needed = (next - level) * filled as f64;
+        if needed >= remaining {
+            level += remaining / filled as f64;
+            break;
+        }
+        remaining -= needed;
+        level = next;
+        filled += 1;
+    }
+    for filled_level in levels.iter_mut().take(filled) {
+        *filled_level = level;
+    }
+
+    imbalance(&levels)
+}
+
+pub(crate) struct ExactSolver<'a> {
+    snapshot: &'a ClusterSnapshot,
+    limits: ExactSolverLimits,
+}
+
+impl<'a> ExactSolver<'a> {
+    pub(crate) fn new(snapshot: &'a ClusterSnapshot, limits: &ExactSolverLimits) -> Self {
+        ExactSolver {
+            snapshot,
+            limits: limits.clone(),
+        }
+    }
+
+    /// Finds the placement with the lowest cluster score. Returns an error if the cluster exceeds the limits.
+    pub(crate) fn solve(&self) -> Result<ExactSolution> {
+        let mut search = Search::new(self.snapshot, &self.limits)?;
+        search.run();
+
+        let movements = search
+            .best
+            .iter()
+            .enumerate()
+            .filter(|(index, node)| **node != search.slots[*index].origin)
+            .map(|(index, node)| {
+                let slot = &search.slots[index];
+                Movement::MoveReplica {
+                    fu_id: slot.fu_id,
+                    role: slot.role,
+                    from: search.nodes[slot.origin],
+                    to: search.nodes[*node],
+                }
+            })
+            .collect::<Vec<Movement>>();
+
+        // the score is recomputed the way the search strategies compute it
+        let mut loads = LoadTable::new(self.snapshot);
+        for movement in movements.iter() {
+            loads.apply_movement(self.snapshot, movement);
+        }
+
+        Ok(ExactSolution {
+            movements,
+            score: loads.score(),
+            explored: search.explored,
+            proven_optimal: !search.aborted,
+        })
+    }
+
+    /// Compares the cluster score after the movements of a heuristic with the optimal score
+    pub(crate) fn optimality_gap(&self, movements: &[Movement]) -> Result<OptimalityGap> {
+        let optimum = self.solve()?;
+        let mut loads = LoadTable::new(self.snapshot);
+        for movement in movements {
+            loads.apply_movement(self.snapshot, movement);
+        }
+        let heuristic_score = loads.score();
+
+        Ok(OptimalityGap {
+            heuristic_score,
+            optimal_score: optimum.score,
+            gap: heuristic_score - optimum.score,
+            proven_optimal: optimum.proven_optimal,
+        })
+    }
+}
+
+/// The state of the branch and bound search
+struct Search {
+    max_explored: u64,
+    nodes: Vec<NodeId>,
+    metrics: Vec<Metric>,
+    /// Replicas in the order they are placed, the heaviest first
+    slots: Vec<Slot>,
+    /// Load per metric and node index of the replicas placed so far
+    loads: Vec<Vec<i64>>,
+    /// Load per metric of the replicas still to be placed
+    remaining: Vec<i64>,
+    /// Domain index of each node index: the nodes of a fault domain share the same domain, a node without a fault
+    /// domain has its own
+    domains: Vec<usize>,
+    /// Failover unit and domain index pairs hosting a replica placed so far or a replica on a node that is down
+    occupied: BTreeSet<(Uuid, usize)>,
+    /// Number of replicas placed so far per service index and node index
+    hosted: Vec<Vec<u32>>,
+    /// Node index of each placed replica
+    assignment: Vec<usize>,
+    best: Vec<usize>,
+    best_score: f64,
+    explored: u64,
+    aborted: bool,
+}
+
+impl Search {
+    fn new(snapshot: &ClusterSnapshot, limits: &ExactSolverLimits) -> Result<Self> {
+        let nodes = snapshot
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.is_up())
+            .map(|(node_id, _)| *node_id)
+            .collect::<Vec<NodeId>>();
+        if nodes.len() > limits.max_nodes {
+            return Err(anyhow!(
+                "{} nodes are up, the exact solver supports at most {}",
+                nodes.len(),
+                limits.max_nodes
+            ));
+        }
+        let mut fault_domains = vec![];
+        let domains = nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node_id)| {
+                let fault_domain = snapshot.nodes[node_id].fault_domain();
+                if fault_domain.is_empty() {
+                    return nodes.len() + index;
+                }
+                match fault_domains
+                    .iter()
+                    .position(|domain| *domain == fault_domain)
+                {
+                    Some(domain) => domain,
+                    None => {
+                        fault_domains.push(fault_domain);
+                        fault_domains.len() - 1
+                    }
+                }
+            })
+            .collect::<Vec<usize>>();
+
+        let table = LoadTable::new(snapshot);
+        let metric_names = table.metrics.keys().cloned().collect::<Vec<String>>();
+        let metrics = table
+            .metrics
+            .values()
+            .map(|metric_loads| {
+                let capacities = nodes
+                    .iter()
+                    .map(|node_id| metric_loads.capacities.get(node_id).copied())
+                    .collect::<Vec<Option<u32>>>();
+                let scales = capacities
+                    .iter()
+                    .map(|capacity| match capacity {
+                        Some(capacity) if *capacity > 0 => *capacity,
+                        _ => 1,
+                    })
+                    .collect::<BTreeSet<u32>>();
+                let uniform_scale = match (scales.len(), scales.first()) {
+                    (1, Some(scale)) => Some(*scale as f64),
+                    _ => None,
+                };
+                Metric {
+                    weight: metric_loads.weight,
+                    capacities,
+                    uniform_scale,
+                }
+            })
+            .collect::<Vec<Metric>>();
+
+        // the replicas on the nodes that are down keep using their fault domain
+        let mut occupied = BTreeSet::new();
+        for fu in snapshot.failover_units.values() {
+            for replica in fu.active_replicas() {
+                let down_fault_domain = snapshot
+                    .nodes
+                    .get(&replica.location())
+                    .filter(|node| !node.is_up())
+                    .map(|node| node.fault_domain());
+                if let Some(domain) = down_fault_domain.and_then(|fault_domain| {
+                    fault_domains
+                        .iter()
+                        .position(|domain| *domain == fault_domain)
+                }) {
+                    occupied.insert((fu.id(), domain));
+                }
+            }
+        }
+
+        // the failover units whose service is not in the snapshot share the index after the last service
+        let service_names = snapshot
+            .services
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<&str>>();
+        let service_index =
+            |service_name: &str| service_names.iter().position(|name| *name == service_name);
+        let parent_of = |service_name: &str| {
+            snapshot
+                .services
+                .get(service_name)
+                .and_then(|service| service.affinitized_service())
+                .filter(|parent_service| snapshot.services.contains_key(*parent_service))
+        };
+        // bounded by the number of services in case the affinities form a cycle
+        let affinity_depth = |service_name: &str| {
+            let mut depth = 0;
+            let mut current = parent_of(service_name);
+            while let Some(parent_service) = current {
+                if depth == service_names.len() {
+                    break;
+                }
+                depth += 1;
+                current = parent_of(parent_service);
+            }
+            depth
+        };
+
+        let checker = PlacementChecker::new(snapshot);
+        let mut slots = vec![];
+        for fu in snapshot.failover_units.values() {
+            let service_name = snapshot
+                .service_of(fu)
+                .map(|service| service.servcie_name())
+                .unwrap_or_default();
+            for replica in fu.active_replicas() {
+                let Some(origin) = nodes
+                    .iter()
+                    .position(|node_id| *node_id == replica.location())
+                else {
+                    continue;
+                };
+                let mut loads = vec![0; metrics.len()];
+                for (metric_name, load) in snapshot.replica_loads(fu, replica.role()) {
+                    if let Some(index) = metric_names.iter().position(|name| *name == metric_name) {
+                        loads[index] = load as i64;
+                    }
+                }
+                let allowed = nodes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, node_id)| {
+                        index == origin
+                            || matches!(
+                                checker.check(fu, replica.role(), *node_id),
+                                Ok(())
+                                    | Err(NodeRejectReason::CapacityExceeded(_))
+                                    | Err(NodeRejectReason::ReplicaAlreadyOnNode)
+                                    | Err(NodeRejectReason::FaultDomainAlreadyUsed)
+                                    | Err(NodeRejectReason::AffinityParentAbsent)
+                            )
+                    })
+                    .collect();
+                slots.push(Slot {
+                    fu_id: fu.id(),
+                    role: replica.role(),
+                    origin,
+                    loads,
+                    allowed,
+                    service: service_index(service_name).unwrap_or(service_names.len()),
+                    parent: parent_of(service_name).and_then(service_index),
+                    affinity_depth: affinity_depth(service_name),
+                });
+            }
+        }
+        if slots.len() > limits.max_replicas {
+            return Err(anyhow!(
+                "{} replicas are hosted on the nodes that are up, the exact solver supports at most {}",
+                slots.len(),
+                limits.max_replicas
+            ));
+        }
+
+        // the parents of an affinity are placed first, then placing the heaviest replicas first tightens the bound early
+        let weight_of = |slot: &Slot| -> f64 {
+            slot.loads
+                .iter()
+                .zip(metrics.iter())
+                .map(|(load, metric)| metric.weight * *load as f64)
+                .sum()
+        };
+        slots.sort_by(|slot1, slot2| {
+            slot1
+                .affinity_depth
+                .cmp(&slot2.affinity_depth)
+                .then(weight_of(slot2).total_cmp(&weight_of(slot1)))
+                .then(Ord::cmp(
+                    &(slot1.fu_id, slot1.origin),
+                    &(slot2.fu_id, slot2.origin),
+                ))
+        });
+
+        let remaining = (0..metrics.len())
+            .map(|metric| slots.iter().map(|slot| slot.loads[metric]).sum())
+            .collect();
+        let best = slots.iter().map(|slot| slot.origin).collect();
+
+        let mut search = Search {
+            max_explored: limits.max_explored,
+            loads: vec![vec![0; nodes.len()]; metrics.len()],
+            hosted: vec![vec![0; nodes.len()]; service_names.len() + 1],
+            nodes,
+            metrics,
+            slots,
+            remaining,
+            domains,
+            occupied,
+            assignment: vec![],
+            best,
+            best_score: f64::INFINITY,
+            explored: 0,
+            aborted: false,
+        };
+        // the current placement is the first candidate, as long as it respects the capacities
+        if search.fits(&search.best) {
+            search.best_score = search.score(&search.loads_of(&search.best));
+        }
+
+        Ok(search)
+    }
+
+    fn run(&mut self) {
+        if !self.nodes.is_empty() {
+            self.explore();
+        }
+    }
+
+    fn explore(&mut self) {
+        if self.explored >= self.max_explored {
+            self.aborted = true;
+            return;
+        }
+        self.explored += 1;
+
+        let depth = self.assignment.len();
+        if depth == self.slots.len() {
+            let score = self.score(&self.loads);
+            if score < self.best_score - SCORE_EPSILON {
+                self.best_score = score;
+                self.best = self.assignment.clone();
+            }
+            return;
+        }
+        if self.lower_bound() >= self.best_score - SCORE_EPSILON {
+            return;
+        }
+
+        for node in self.candidates(depth) {
+            let fu_id = self.slots[depth].fu_id;
+            self.place(depth, node, 1);
+            self.occupied.insert((fu_id, self.domains[node]));
+            self.assignment.push(node);
+            self.explore();
+            self.assignment.pop();
+            self.occupied.remove(&(fu_id, self.domains[node]));
+            self.place(depth, node, -1);
+            if self.aborted {
+                return;
+            }
+        }
+    }
+
+    /// The nodes that can take the replica given the replicas placed so far: the current node first, then the least
+    /// loaded nodes
+    fn candidates(&self, depth: usize) -> Vec<usize> {
+        let slot = &self.slots[depth];
+        let mut candidates = (0..self.nodes.len())
+            .filter(|node| {
+                slot.allowed[*node]
+                    && !self.occupied.contains(&(slot.fu_id, self.domains[*node]))
+                    && slot
+                        .parent
+                        .is_none_or(|parent| self.hosted[parent][*node] > 0)
+                    && self
+                        .metrics
+                        .iter()
+                        .enumerate()
+                        .all(|(metric_index, metric)| {
+                            metric.capacities[*node].is_none_or(|capacity| {
+                                self.loads[metric_index][*node] + slot.loads[metric_index]
+                                    <= capacity as i64
+                            })
+                        })
+            })
+            .collect::<Vec<usize>>();
+        candidates.sort_by(|node1, node2| {
+            (*node1 != slot.origin)
+                .cmp(&(*node2 != slot.origin))
+                .then(
+                    self.weighted_load(*node1)
+                        .total_cmp(&self.weighted_load(*node2)),
+                )
+                .then(node1.cmp(node2))
+        });
+
+        candidates
+    }
+
+    fn weighted_load(&self, node: usize) -> f64 {
+        self.metrics
+            .iter()
+            .enumerate()
+            .map(|(metric_index, metric)| {
+                metric.weight * metric.normalized(node, self.loads[metric_index][node])
+            })
+            .sum()
+    }
+
+    fn place(&mut self, depth: usize, node: usize, sign: i64) {
+        let service = self.slots[depth].service;
+        self.hosted[service][node] = self.hosted[service][node].saturating_add_signed(sign as i32);
+        for metric_index in 0..self.metrics.len() {
+            let load = self.slots[depth].loads[metric_index];
+            self.loads[metric_index][node] += sign * load;
+            self.remaining[metric_index] -= sign * load;
+        }
+    }
+
+    fn levels(&self, metric_index: usize, loads: &[i64]) -> Vec<f64> {
+        let metric = &self.metrics[metric_index];
+        loads
+            .iter()
+            .enumerate()
+            .map(|(node, load)| metric.normalized(node, *load))
+            .collect()
+    }
+
+    /// Score of a complete placement given its load per metric and node index
+    fn score(&self, loads: &[Vec<i64>]) -> f64 {
+        self.metrics
+            .iter()
+            .enumerate()
+            .map(|(metric_index, metric)| {
+                metric.weight * imbalance(&self.levels(metric_index, &loads[metric_index]))
+            })
+            .sum()
+    }
+
+    fn lower_bound(&self) -> f64 {
+        self.metrics
+            .iter()
+            .enumerate()
+            .map(|(metric_index, metric)| match metric.uniform_scale {
+                Some(scale) => {
+                    metric.weight
+                        * water_filled_imbalance(
+                            self.levels(metric_index, &self.loads[metric_index]),
+                            self.remaining[metric_index] as f64 / scale,
+                        )
+                }
+                None => 0.0,
+            })
+            .sum()
+    }
+
+    fn loads_of(&self, assignment: &[usize]) -> Vec<Vec<i64>> {
+        let mut loads = vec![vec![0; self.nodes.len()]; self.metrics.len()];
+        for (slot, node) in self.slots.iter().zip(assignment) {
+            for (metric_index, load) in slot.loads.iter().enumerate() {
+                loads[metric_index][*node] += load;
+            }
+        }
+
+        loads
+    }
+
+    fn fits(&self, assignment: &[usize]) -> bool {
+        let loads = self.loads_of(assignment);
+        self.metrics
+            .iter()
+            .enumerate()
+            .all(|(metric_index, metric)| {
+                metric
+                    .capacities
+                    .iter()
+                    .zip(loads[metric_index].iter())
+                    .all(|(capacity, load)| {
+                        capacity.is_none_or(|capacity| *load <= capacity as i64)
+                    })
+            })
+    }
+}
@@ -1,11 +1,13 @@
 use std::collections::HashMap;
 
+use anyhow::{anyhow, Result};
 use uuid::Uuid;
 
-use crate::node::{node_id::NodeId};
+use crate::{node::node_id::NodeId, solver::Movement};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ReplicaRole {
     None = 1024,
     Primary = 1025,
@@ -17,11 +19,12 @@ pub enum ReplicaRole {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Replica {
-    replica_id: u128,
-    fu_id: Uuid,
-    role: ReplicaRole,
-    location: NodeId,
+    pub(crate) replica_id: u128,
+    pub(crate) fu_id: Uuid,
+    pub(crate) role: ReplicaRole,
+    pub(crate) location: NodeId,
 }
 
 impl Replica {
@@ -33,6 +36,31 @@ impl Replica {
             location,
         }
     }
+
+    pub fn replica_id(&self) -> u128 {
+        self.replica_id
+    }
+
+    pub fn fu_id(&self) -> Uuid {
+        self.fu_id
+    }
+
+    pub fn role(&self) -> ReplicaRole {
+        self.role
+    }
+
+    pub fn location(&self) -> NodeId {
+        self.location
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.role == ReplicaRole::Primary
+    }
+
+    /// Dropped replicas and replicas without a role do not occupy their node
+    pub fn is_active(&self) -> bool {
+        !matches!(self.role, ReplicaRole::None | ReplicaRole::Dropped)
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -48,12 +76,162 @@ impl FailoverUnit {
     pub fn replia_diff(&self) -> i32 {
         self.failover_unit_description.replica_diff
     }
+
+    pub fn service_name(&self) -> &str {
+        &self.failover_unit_description.service_name
+    }
+
+    /// Iterates over the replicas that currently occupy a node
+    pub fn active_replicas(&self) -> impl Iterator<Item = &Replica> {
+        self.failover_unit_description
+            .replicas
+            .values()
+            .filter(|replica| replica.is_active())
+    }
+
+    pub fn primary(&self) -> Option<&Replica> {
+        self.active_replicas().find(|replica| replica.is_primary())
+    }
+
+    /// Whether the failover unit already has an active replica on the given node
+    pub fn has_replica_on(&self, node_id: NodeId) -> bool {
+        self.active_replicas()
+            .any(|replica| replica.location == node_id)
+    }
 }
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct FailoverUnitDescription {
     pub(crate) id: Uuid,
     pub(crate) service_name: String,
     pub(crate) replicas: HashMap<Uuid, Replica>,
     pub(crate) replica_diff: i32,
 }
+
+impl FailoverUnitDescription {
+    pub fn new(
+        id: Uuid,
+        service_name: &str,
+        replicas: HashMap<Uuid, Replica>,
+        replica_diff: i32,
+    ) -> Self {
+        FailoverUnitDescription {
+            id,
+            service_name: String::from(service_name),
+            replicas,
+            replica_diff,
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Applies a movement generated by PLB to the replicas, the way FM reports the failover unit once the movement
+    /// is done. Adding or dropping a replica also brings the replica difference closer to 0.
+    pub fn apply_movement(&mut self, movement: &Movement) -> Result<()> {
+        if movement.fu_id() != self.id {
+            return Err(anyhow!(
+                "Movement of partition {} applied to partition {}",
+                movement.fu_id(),
+                self.id
+            ));
+        }
+
+        match *movement {
+            Movement::AddReplica { node, .. } => {
+                if self.active_replica_on(node).is_some() {
+                    return Err(anyhow!(
+                        "Partition {} already has a replica on {:?}",
+                        self.id,
+                        node
+                    ));
+                }
+                let replica_id = self
+                    .replicas
+                    .values()
+                    .map(|replica| replica.replica_id + 1)
+                    .max()
+                    .unwrap_or_default();
+                self.replicas.insert(
+                    Uuid::from_u128(replica_id),
+                    Replica::new(replica_id, self.id, ReplicaRole::Secondary, node),
+                );
+                if self.replica_diff > 0 {
+                    self.replica_diff -= 1;
+                }
+            }
+            Movement::DropReplica { node, .. } => {
+                let key = self.replica_key_on(node)?;
+                self.replicas.remove(&key);
+                if self.replica_diff < 0 {
+                    self.replica_diff += 1;
+                }
+            }
+            Movement::MoveReplica { from, to, .. } => {
+                if self.active_replica_on(to).is_some() {
+                    return Err(anyhow!(
+                        "Partition {} already has a replica on {:?}",
+                        self.id,
+                        to
+                    ));
+                }
+                let key = self.replica_key_on(from)?;
+                if let Some(replica) = self.replicas.get_mut(&key) {
+                    replica.location = to;
+                }
+            }
+            Movement::SwapPrimary { from, to, .. } => {
+                let primary = self.replica_key_on(from)?;
+                let secondary = self.replica_key_on(to)?;
+                if self.replicas[&primary].role != ReplicaRole::Primary
+                    || self.replicas[&secondary].role != ReplicaRole::Secondary
+                {
+                    return Err(anyhow!(
+                        "Partition {} has no primary on {:?} and secondary on {:?}",
+                        self.id,
+                        from,
+                        to
+                    ));
+                }
+                if let Some(replica) = self.replicas.get_mut(&primary) {
+                    replica.role = ReplicaRole::Secondary;
+                }
+                if let Some(replica) = self.replicas.get_mut(&secondary) {
+                    replica.role = ReplicaRole::Primary;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the replicas already reflect the movement: the added or moved replica is on its target node, the dropped
+    /// or moved replica is gone from its source node, and the swapped primary is on its target node
+    pub(crate) fn reflects_movement(&self, movement: &Movement) -> bool {
+        match *movement {
+            Movement::AddReplica { node, .. } => self.active_replica_on(node).is_some(),
+            Movement::DropReplica { node, .. } => self.active_replica_on(node).is_none(),
+            Movement::MoveReplica { from, to, .. } => {
+                self.active_replica_on(to).is_some() && self.active_replica_on(from).is_none()
+            }
+            Movement::SwapPrimary { to, .. } => self
+                .active_replica_on(to)
+                .is_some_and(|key| self.replicas[&key].role == ReplicaRole::Primary),
+        }
+    }
+
+    fn active_replica_on(&self, node_id: NodeId) -> Option<Uuid> {
+        self.replicas
+            .iter()
+            .find(|(_, replica)| replica.is_active() && replica.location == node_id)
+            .map(|(key, _)| *key)
+    }
+
+    fn replica_key_on(&self, node_id: NodeId) -> Result<Uuid> {
+        self.active_replica_on(node_id)
+            .ok_or_else(|| anyhow!("Partition {} has no replica on {:?}", self.id, node_id))
+    }
+}
@@ -0,0 +1,372 @@
+//! This module forecasts the load of the metrics of type [BuiltInType::Predicted].
+//!
+//! Every load reported for a failover unit is kept, per metric and for the primary and secondary replicas, in a
+//! bounded time series stamped with the time of the refresh that applied it. The forecast of a series is recomputed
+//! when a load is reported, and replaces the last reported load of the predicted metrics everywhere PLB reads a
+//! replica load: node loads, capacity checks and balancing. A failover unit without any report since the engine
+//! started keeps its last reported load, or the default load of the metric.
+//!
+//! [BuiltInType::Predicted]: crate::service::built_in_type::BuiltInType::Predicted
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{
+    failoverunit::failover_unit::ReplicaRole, load::load_or_move_cost::LoadOrMoveCostDescription,
+};
+
+/// How a series of loads is extrapolated
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ForecastMethod {
+    /// Smoothed level of the series, each load weighing `alpha` in (0, 1] against the level before it
+    ExponentialSmoothing { alpha: f64 },
+    /// Least-squares line through the series, extrapolated `horizon` after the last load
+    LinearTrend { horizon: Duration },
+}
+
+/// Settings of the load forecasting
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoadForecastConfig {
+    pub method: ForecastMethod,
+    /// Maximum number of loads kept per failover unit, metric and role
+    pub history_size: usize,
+}
+
+impl Default for LoadForecastConfig {
+    fn default() -> Self {
+        LoadForecastConfig {
+            method: ForecastMethod::ExponentialSmoothing { alpha: 0.5 },
+            history_size: 32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct LoadSeries {
+    samples: VecDeque<(OffsetDateTime, u32)>,
+    forecast: u32,
+}
+
+/// The series of reported loads, keyed by metric name and whether the loads are the primary ones
+type FailoverUnitHistory = BTreeMap<(String, bool), LoadSeries>;
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "Vec<LoadSeriesRecord>", from = "Vec<LoadSeriesRecord>")
+)]
+pub(crate) struct LoadHistory {
+    failover_units: HashMap<Uuid, FailoverUnitHistory>,
+}
+
+/// A series of the load history, flattened for serialization
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct LoadSeriesRecord {
+    fu_id: Uuid,
+    metric_name: String,
+    is_primary: bool,
+    samples: VecDeque<(OffsetDateTime, u32)>,
+    forecast: u32,
+}
+
+#[cfg(feature = "serde")]
+impl From<LoadHistory> for Vec<LoadSeriesRecord> {
+    fn from(history: LoadHistory) -> Self {
+        let mut records = history
+            .failover_units
+            .into_iter()
+            .flat_map(|(fu_id, series)| {
+                series
+                    .into_iter()
+                    .map(
+                        move |((metric_name, is_primary), series)| LoadSeriesRecord {
+                            fu_id,
+                            metric_name,
+                            is_primary,
+                            samples: series.samples,
+                            forecast: series.forecast,
+                        },
+                    )
+            })
+            .collect::<Vec<LoadSeriesRecord>>();
+        records.sort_by_key(|record| record.fu_id);
+        records
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Vec<LoadSeriesRecord>> for LoadHistory {
+    fn from(records: Vec<LoadSeriesRecord>) -> Self {
+        let mut history = LoadHistory::default();
+        for record in records {
+            history
+                .failover_units
+                .entry(record.fu_id)
+                .or_default()
+                .insert(
+                    (record.metric_name, record.is_primary),
+                    LoadSeries {
+                        samples: record.samples,
+                        forecast: record.forecast,
+                    },
+                );
+        }
+        history
+    }
+}
+
+impl LoadHistory {
+    /// Appends the loads reported at `now` to their series. A second report within the same refresh replaces the
+    /// first one.
+    pub(crate) fn record(
+        &mut self,
+        load_desc: &LoadOrMoveCostDescription,
+        now: OffsetDateTime,
+        config: &LoadForecastConfig,
+    ) {
+        let history = self.failover_units.entry(load_desc.fu_id).or_default();
+        let reported = load_desc
+            .primary_loads
+            .iter()
+            .map(|(metric_name, load)| ((metric_name, true), load))
+            .chain(
+                load_desc
+                    .secondary_loads
+                    .iter()
+                    .map(|(metric_name, load)| ((metric_name, false), load)),
+            );
+        for ((metric_name, is_primary), load) in reported {
+            let series = history
+                .entry((metric_name.clone(), is_primary))
+                .or_default();
+            if series.samples.back().is_some_and(|(time, _)| *time == now) {
+                series.samples.pop_back();
+            }
+            series.samples.push_back((now, *load));
+            while series.samples.len() > config.history_size.max(1) {
+                series.samples.pop_front();
+            }
+            series.forecast = forecast(&series.samples, config.method);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, fu_id: Uuid) {
+        self.failover_units.remove(&fu_id);
+    }
+
+    /// Trims the series and recomputes their forecast after a change of the settings
+    pub(crate) fn reforecast(&mut self, config: &LoadForecastConfig) {
+        for series in self
+            .failover_units
+            .values_mut()
+            .flat_map(|history| history.values_mut())
+        {
+            while series.samples.len() > config.history_size.max(1) {
+                series.samples.pop_front();
+            }
+            series.forecast = forecast(&series.samples, config.method);
+        }
+    }
+
+    /// The forecast load of a replica of the failover unit with the given role, if it reported any load for the metric
+    pub(crate) fn forecast(
+        &self,
+        fu_id: Uuid,
+        metric_name: &str,
+        role: ReplicaRole,
+    ) -> Option<u32> {
+        self.series(fu_id, metric_name, role)
+            .map(|series| series.forecast)
+    }
+
+    /// The loads reported for a replica of the failover unit with the given role, oldest first
+    pub(crate) fn samples(
+        &self,
+        fu_id: Uuid,
+        metric_name: &str,
+        role: ReplicaRole,
+    ) -> Vec<(OffsetDateTime, u32)> {
+        self.series(fu_id, metric_name, role)
+            .map(|series| series.samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    fn series(&self, fu_id: Uuid, metric_name: &str, role: ReplicaRole) -> Option<&LoadSeries> {
+        self.failover_units
+            .get(&fu_id)?
+            .get(&(String::from(metric_name), role == ReplicaRole::Primary))
+    }
+}
+
+/// Extrapolates the loads, rounded to the closest non-negative integer
+fn forecast(samples: &VecDeque<(OffsetDateTime, u32)>, method: ForecastMethod) -> u32 {
+    let Some((first_time, first_load)) = samples.front().copied() else {
+        return 0;
+    };
+    let forecast = match method {
+        ForecastMethod::ExponentialSmoothing { alpha } => {
+            let alpha = alpha.clamp(0.0, 1.0);
+            samples
+                .iter()
+                .skip(1)
+                .fold(first_load as f64, |level, (_, load)| {
+                    alpha * *load as f64 + (1.0 - alpha) * level
+                })
+        }
+        ForecastMethod::LinearTrend { horizon } => {
+            let points = samples
+                .iter()
+                .map(|(time, load)| ((*time - first_time).as_seconds_f64(), *load as f64))
+                .collect::<Vec<(f64, f64)>>();
+            let count = points.len() as f64;
+            let mean_time = points.iter().map(|(time, _)| time).sum::<f64>() / count;
+            let mean_load = points.iter().map(|(_, load)| load).sum::<f64>() / count;
+            let variance = points
+                .iter()
+                .map(|(time, _)| (time - mean_time).powi(2))
+                .sum::<f64>();
+            let slope = if variance > 0.0 {
+                points
+                    .iter()
+                    .map(|(time, load)| (time - mean_time) * (load - mean_load))
+                    .sum::<f64>()
+                    / variance
+            } else {
+                0.0
+            };
+            let last_time = points[points.len() - 1].0;
+            mean_load + slope * (last_time + horizon.as_seconds_f64() - mean_time)
+        }
+    };
+
+    forecast.round().clamp(0.0, u32::MAX as f64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(loads: &[u32]) -> VecDeque<(OffsetDateTime, u32)> {
+        let start = OffsetDateTime::UNIX_EPOCH;
+        loads
+            .iter()
+            .enumerate()
+            .map(|(index, load)| (start + Duration::minutes(index as i64), *load))
+            .collect()
+    }
+
+    #[test]
+    fn test_exponential_smoothing() {
+        let method = ForecastMethod::ExponentialSmoothing { alpha: 0.5 };
+        assert_eq!(0, forecast(&series(&[]), method));
+        assert_eq!(10, forecast(&series(&[10]), method));
+        // 10, then 15, then 27.5
+        assert_eq!(28, forecast(&series(&[10, 20, 40]), method));
+        let method = ForecastMethod::ExponentialSmoothing { alpha: 1.0 };
+        assert_eq!(40, forecast(&series(&[10, 20, 40]), method));
+    }
+
+    #[test]
+    fn test_linear_trend() {
+        let method = ForecastMethod::LinearTrend {
+            horizon: Duration::minutes(2),
+        };
+        assert_eq!(10, forecast(&series(&[10]), method));
+        assert_eq!(50, forecast(&series(&[10, 20, 30]), method));
+        assert_eq!(20, forecast(&series(&[20, 20, 20]), method));
+        // a falling trend never forecasts a negative load
+        assert_eq!(0, forecast(&series(&[30, 20, 10]), method));
+    }
+
+    #[test]
+    fn test_history() {
+        let config = LoadForecastConfig {
+            method: ForecastMethod::ExponentialSmoothing { alpha: 1.0 },
+            history_size: 2,
+        };
+        let fu_id = Uuid::from_u128(1);
+        let mut history = LoadHistory::default();
+        let now = OffsetDateTime::UNIX_EPOCH;
+        for (minutes, load) in [(0, 10), (1, 20), (1, 25), (2, 30)] {
+            history.record(
+                &LoadOrMoveCostDescription::new(fu_id)
+                    .with_primary_load("CPU", load)
+                    .with_secondary_load("CPU", load / 5),
+                now + Duration::minutes(minutes),
+                &config,
+            );
+        }
+
+        assert_eq!(
+            vec![
+                (now + Duration::minutes(1), 25),
+                (now + Duration::minutes(2), 30)
+            ],
+            history.samples(fu_id, "CPU", ReplicaRole::Primary)
+        );
+        assert_eq!(
+            Some(30),
+            history.forecast(fu_id, "CPU", ReplicaRole::Primary)
+        );
+        assert_eq!(
+            Some(6),
+            history.forecast(fu_id, "CPU", ReplicaRole::Secondary)
+        );
+        assert_eq!(
+            None,
+            history.forecast(fu_id, "Memory", ReplicaRole::Primary)
+        );
+
+        history.reforecast(&LoadForecastConfig {
+            method: ForecastMethod::ExponentialSmoothing { alpha: 0.5 },
+            history_size: 1,
+        });
+        assert_eq!(1, history.samples(fu_id, "CPU", ReplicaRole::Primary).len());
+        assert_eq!(
+            Some(30),
+            history.forecast(fu_id, "CPU", ReplicaRole::Primary)
+        );
+
+        history.remove(fu_id);
+        assert_eq!(None, history.forecast(fu_id, "CPU", ReplicaRole::Primary));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_history_serialization() {
+        let fu_id = Uuid::from_u128(1);
+        let now = OffsetDateTime::UNIX_EPOCH;
+        let mut history = LoadHistory::default();
+        for minutes in 0..3 {
+            history.record(
+                &LoadOrMoveCostDescription::new(fu_id)
+                    .with_primary_load("CPU", 10 * minutes as u32)
+                    .with_secondary_load("Memory", 5),
+                now + Duration::minutes(minutes),
+                &LoadForecastConfig::default(),
+            );
+        }
+
+        let json = serde_json::to_string(&history).unwrap();
+        let deserialized = serde_json::from_str::<LoadHistory>(&json).unwrap();
+        for (metric_name, role) in [
+            ("CPU", ReplicaRole::Primary),
+            ("Memory", ReplicaRole::Secondary),
+        ] {
+            assert_eq!(
+                history.samples(fu_id, metric_name, role),
+                deserialized.samples(fu_id, metric_name, role)
+            );
+            assert_eq!(
+                history.forecast(fu_id, metric_name, role),
+                deserialized.forecast(fu_id, metric_name, role)
+            );
+        }
+    }
+}
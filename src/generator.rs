@@ -0,0 +1,456 @@
+//! This module generates synthetic clusters from a set of parameters, to exercise the engine at scale from the tests,
+//! the simulator and the benchmarks.
+//!
+//! Nodes are laid out over the fault domains (FD) and upgrade domains (UD) in rows: node `i` belongs to FD
+//! `i % fault_domain_count` and to UD `(i / fault_domain_count) % upgrade_domain_count`, so every UD spans all the
+//! FDs. The replicas of a partition are placed on the next nodes of distinct FDs starting from a random node, and only
+//! share an FD when there are more replicas than FDs. Loads are drawn per partition and per metric
+//! from the configured distributions. The generation only depends on the seed.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use uuid::Uuid;
+
+use crate::{
+    application::application_description::ApplicationDescription,
+    failoverunit::failover_unit::{FailoverUnitDescription, Replica, ReplicaRole},
+    load::load_or_move_cost::LoadOrMoveCostDescription,
+    node::{node_description::NodeDescription, node_id::NodeId, node_instance::NodeInstance},
+    random::SeededRng,
+    service::{service_description::ServiceDescription, service_metric::ServiceMetric},
+    servicetype::service_type_description::ServiceTypeDescription,
+    PlacementAndLoadBalancing,
+};
+
+/// How the load of a metric is distributed over the partitions
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadDistribution {
+    /// Every partition has the same load
+    Constant(u32),
+    /// Loads uniformly distributed in [min, max]
+    Uniform { min: u32, max: u32 },
+    /// Loads normally distributed, clamped to 0
+    Normal { mean: f64, std_dev: f64 },
+    /// Loads in [1, max] where the probability of a load `k` is proportional to `1 / k^exponent`: most partitions
+    /// are light and a few are very heavy
+    Zipf { max: u32, exponent: f64 },
+}
+
+/// A metric reported by every generated service
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricSpec {
+    pub name: String,
+    pub weight: f64,
+    /// Capacity of every node for the metric, if any
+    pub node_capacity: Option<u32>,
+    /// Distribution of the primary load
+    pub primary_load: LoadDistribution,
+    /// Load of a secondary replica as a ratio of the primary load of the partition
+    pub secondary_load_ratio: f64,
+}
+
+/// Parameters of a generated cluster
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterSpec {
+    pub node_count: usize,
+    pub fault_domain_count: usize,
+    pub upgrade_domain_count: usize,
+    pub application_count: usize,
+    pub services_per_application: usize,
+    pub partitions_per_service: usize,
+    /// Target number of replicas of every partition, one of them being the primary
+    pub replicas_per_partition: usize,
+    /// Fraction of the partitions generated without any replica, waiting for the placement phase
+    pub unplaced_fraction: f64,
+    pub metrics: Vec<MetricSpec>,
+    pub seed: u64,
+}
+
+impl Default for ClusterSpec {
+    fn default() -> Self {
+        ClusterSpec {
+            node_count: 10,
+            fault_domain_count: 5,
+            upgrade_domain_count: 5,
+            application_count: 2,
+            services_per_application: 5,
+            partitions_per_service: 10,
+            replicas_per_partition: 3,
+            unplaced_fraction: 0.0,
+            metrics: vec![MetricSpec {
+                name: String::from("CPU"),
+                weight: 1.0,
+                node_capacity: Some(10_000),
+                primary_load: LoadDistribution::Zipf {
+                    max: 100,
+                    exponent: 1.0,
+                },
+                secondary_load_ratio: 0.5,
+            }],
+            seed: 0,
+        }
+    }
+}
+
+/// The descriptions of a generated cluster
+#[derive(Debug, Clone, Default)]
+pub struct GeneratedCluster {
+    pub nodes: Vec<NodeDescription>,
+    pub applications: Vec<ApplicationDescription>,
+    pub service_types: Vec<ServiceTypeDescription>,
+    pub services: Vec<ServiceDescription>,
+    pub failover_units: Vec<FailoverUnitDescription>,
+    pub loads: Vec<LoadOrMoveCostDescription>,
+}
+
+impl GeneratedCluster {
+    /// Number of replicas placed on the nodes
+    pub fn replica_count(&self) -> usize {
+        self.failover_units
+            .iter()
+            .map(|fu_desc| fu_desc.replicas.len())
+            .sum()
+    }
+
+    /// Creates a PLB engine whose cluster snapshot is the generated cluster
+    pub fn into_plb(self) -> Result<PlacementAndLoadBalancing> {
+        PlacementAndLoadBalancing::new(
+            self.nodes,
+            self.applications,
+            self.service_types,
+            self.services,
+            self.failover_units,
+            self.loads,
+        )
+    }
+}
+
+/// Draws loads from a [LoadDistribution]
+struct LoadSampler {
+    distribution: LoadDistribution,
+    /// Cumulative probabilities of the loads 1..=max, for the Zipf distribution
+    zipf_cdf: Vec<f64>,
+}
+
+impl LoadSampler {
+    fn new(distribution: &LoadDistribution) -> Self {
+        let zipf_cdf = match distribution {
+            LoadDistribution::Zipf { max, exponent } => {
+                let weights = (1..=(*max).max(1))
+                    .map(|load| 1.0 / (load as f64).powf(*exponent))
+                    .collect::<Vec<f64>>();
+                let total = weights.iter().sum::<f64>();
+                weights
+                    .iter()
+                    .scan(0.0, |cumulated, weight| {
+                        *cumulated += weight / total;
+                        Some(*cumulated)
+                    })
+                    .collect()
+            }
+            _ => vec![],
+        };
+
+        LoadSampler {
+            distribution: distribution.clone(),
+            zipf_cdf,
+        }
+    }
+
+    fn sample(&self, rng: &mut SeededRng) -> u32 {
+        match self.distribution {
+            LoadDistribution::Constant(load) => load,
+            LoadDistribution::Uniform { min, max } => {
+                let (min, max) = (min.min(max), min.max(max));
+                min + rng.next_index((max - min) as usize + 1) as u32
+            }
+            LoadDistribution::Normal { mean, std_dev } => {
+                // Box-Muller transform, 1 - u keeps the logarithm finite
+                let (u1, u2) = (1.0 - rng.next_f64(), rng.next_f64());
+                let standard = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                (mean + std_dev * standard).round().max(0.0) as u32
+            }
+            LoadDistribution::Zipf { .. } => {
+                let probability = rng.next_f64();
+                let index = self
+                    .zipf_cdf
+                    .partition_point(|cumulated| *cumulated < probability);
+                index.min(self.zipf_cdf.len() - 1) as u32 + 1
+            }
+        }
+    }
+}
+
+impl ClusterSpec {
+    /// Generates the cluster described by the parameters
+    pub fn generate(&self) -> GeneratedCluster {
+        let mut rng = SeededRng::new(self.seed);
+        let fault_domain_count = self.fault_domain_count.max(1);
+        let upgrade_domain_count = self.upgrade_domain_count.max(1);
+
+        let nodes = (0..self.node_count)
+            .map(|index| {
+                let capacities = self
+                    .metrics
+                    .iter()
+                    .filter_map(|metric| Some((metric.name.clone(), metric.node_capacity?)))
+                    .collect::<HashMap<String, u32>>();
+                NodeDescription::new(
+                    NodeInstance::new(NodeId::new(index as u128), 0),
+                    true,
+                    HashMap::new(),
+                    capacities,
+                )
+                .with_fault_domain(&format!("fd:/{}", index % fault_domain_count))
+                .with_upgrade_domain(&format!(
+                    "UD{}",
+                    (index / fault_domain_count) % upgrade_domain_count
+                ))
+            })
+            .collect::<Vec<NodeDescription>>();
+
+        let samplers = self
+            .metrics
+            .iter()
+            .map(|metric| LoadSampler::new(&metric.primary_load))
+            .collect::<Vec<LoadSampler>>();
+        let mut cluster = GeneratedCluster {
+            nodes,
+            ..Default::default()
+        };
+        let mut fu_index = 0u128;
+        for app_index in 0..self.application_count {
+            let app_name = format!("fabric:/App{}", app_index);
+            let service_type_name = format!("App{}.Type", app_index);
+            cluster
+                .applications
+                .push(ApplicationDescription::new(&app_name));
+            cluster.service_types.push(ServiceTypeDescription::new(
+                &service_type_name,
+                Default::default(),
+            ));
+
+            for service_index in 0..self.services_per_application {
+                let service_name = format!("{}/Service{}", app_name, service_index);
+                let service_desc = self.metrics.iter().fold(
+                    ServiceDescription::new(&service_type_name, &service_name)
+                        .with_application_name(&app_name),
+                    |service_desc, metric| {
+                        service_desc.with_metric(ServiceMetric::new(
+                            &metric.name,
+                            metric.weight,
+                            0,
+                            0,
+                        ))
+                    },
+                );
+                cluster.services.push(service_desc);
+
+                for _ in 0..self.partitions_per_service {
+                    fu_index += 1;
+                    let fu_id = Uuid::from_u128(fu_index);
+                    let placed = rng.next_f64() >= self.unplaced_fraction;
+                    cluster.failover_units.push(self.generate_failover_unit(
+                        &mut rng,
+                        fu_id,
+                        &service_name,
+                        placed,
+                    ));
+
+                    let mut load_desc = LoadOrMoveCostDescription::new(fu_id);
+                    for (metric, sampler) in self.metrics.iter().zip(samplers.iter()) {
+                        let primary_load = sampler.sample(&mut rng);
+                        let secondary_load =
+                            (primary_load as f64 * metric.secondary_load_ratio).round() as u32;
+                        load_desc = load_desc
+                            .with_primary_load(&metric.name, primary_load)
+                            .with_secondary_load(&metric.name, secondary_load);
+                    }
+                    cluster.loads.push(load_desc);
+                }
+            }
+        }
+
+        cluster
+    }
+
+    fn generate_failover_unit(
+        &self,
+        rng: &mut SeededRng,
+        fu_id: Uuid,
+        service_name: &str,
+        placed: bool,
+    ) -> FailoverUnitDescription {
+        let replica_count = self.replicas_per_partition.min(self.node_count);
+        if !placed || replica_count == 0 {
+            return FailoverUnitDescription::new(
+                fu_id,
+                service_name,
+                HashMap::new(),
+                self.replicas_per_partition as i32,
+            );
+        }
+
+        let first_node = rng.next_index(self.node_count);
+        let fault_domain_count = self.fault_domain_count.max(1);
+        let mut nodes = Vec::with_capacity(replica_count);
+        let mut used_fault_domains = HashSet::new();
+        // nodes of distinct FDs first, then any other node if there are more replicas than FDs
+        for distinct_fault_domains in [true, false] {
+            for offset in 0..self.node_count {
+                if nodes.len() == replica_count
+                    || (distinct_fault_domains && used_fault_domains.len() == fault_domain_count)
+                {
+                    break;
+                }
+                let index = (first_node + offset) % self.node_count;
+                if nodes.contains(&index)
+                    || (distinct_fault_domains
+                        && used_fault_domains.contains(&(index % fault_domain_count)))
+                {
+                    continue;
+                }
+                used_fault_domains.insert(index % fault_domain_count);
+                nodes.push(index);
+            }
+        }
+        let replicas = nodes
+            .into_iter()
+            .enumerate()
+            .map(|(replica_index, node_index)| {
+                let role = match replica_index {
+                    0 => ReplicaRole::Primary,
+                    _ => ReplicaRole::Secondary,
+                };
+                let node_id = NodeId::new(node_index as u128);
+                (
+                    Uuid::from_u128(replica_index as u128),
+                    Replica::new(replica_index as u128, fu_id, role, node_id),
+                )
+            })
+            .collect();
+
+        FailoverUnitDescription::new(
+            fu_id,
+            service_name,
+            replicas,
+            (self.replicas_per_partition - replica_count) as i32,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generation_is_reproducible() {
+        let spec = ClusterSpec {
+            seed: 11,
+            unplaced_fraction: 0.2,
+            ..Default::default()
+        };
+        let (cluster1, cluster2) = (spec.generate(), spec.generate());
+
+        assert_eq!(
+            format!("{:?}", cluster1.loads),
+            format!("{:?}", cluster2.loads)
+        );
+        assert_eq!(cluster1.replica_count(), cluster2.replica_count());
+        assert_eq!(100, cluster1.failover_units.len());
+        assert!(cluster1.replica_count() < 300);
+    }
+
+    #[test]
+    fn test_layout() {
+        let spec = ClusterSpec {
+            node_count: 20,
+            fault_domain_count: 5,
+            upgrade_domain_count: 2,
+            ..Default::default()
+        };
+        let cluster = spec.generate();
+        let distinct_fault_domains = |cluster: &GeneratedCluster| {
+            cluster.failover_units.iter().all(|fu_desc| {
+                let fault_domains = fu_desc
+                    .replicas
+                    .values()
+                    .map(|replica| &cluster.nodes[replica.location.id_value as usize].fault_domain)
+                    .collect::<HashSet<_>>();
+                fault_domains.len() == fu_desc.replicas.len()
+            })
+        };
+
+        let domains = cluster
+            .nodes
+            .iter()
+            .map(|node| (node.fault_domain.clone(), node.upgrade_domain.clone()))
+            .collect::<HashSet<_>>();
+        // every upgrade domain spans every fault domain
+        assert_eq!(10, domains.len());
+        assert_eq!(300, cluster.replica_count());
+        assert!(distinct_fault_domains(&cluster));
+
+        // the replicas wrapping around the last node skip the FDs already used
+        let cluster = ClusterSpec {
+            node_count: 6,
+            ..spec
+        }
+        .generate();
+        assert!(distinct_fault_domains(&cluster));
+    }
+
+    /// Run with `cargo test --release -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_generation_at_scale() {
+        let spec = ClusterSpec {
+            node_count: 10_000,
+            fault_domain_count: 10,
+            upgrade_domain_count: 10,
+            application_count: 10,
+            services_per_application: 10,
+            partitions_per_service: 3_334,
+            ..Default::default()
+        };
+        let start = std::time::Instant::now();
+        let cluster = spec.generate();
+        let elapsed = start.elapsed();
+
+        assert_eq!(10_000, cluster.nodes.len());
+        assert_eq!(1_000_200, cluster.replica_count());
+        assert!(
+            elapsed < std::time::Duration::from_secs(10),
+            "generation took {:?}",
+            elapsed
+        );
+    }
+
+    #[test]
+    fn test_load_distributions() {
+        let mut rng = SeededRng::new(3);
+        let zipf = LoadSampler::new(&LoadDistribution::Zipf {
+            max: 50,
+            exponent: 1.2,
+        });
+        let loads = (0..10_000)
+            .map(|_| zipf.sample(&mut rng))
+            .collect::<Vec<u32>>();
+        assert!(loads.iter().all(|load| (1..=50).contains(load)));
+        // the lightest load is the most frequent one
+        let ones = loads.iter().filter(|load| **load == 1).count();
+        let fifties = loads.iter().filter(|load| **load == 50).count();
+        assert!(ones > 10 * fifties);
+
+        let normal = LoadSampler::new(&LoadDistribution::Normal {
+            mean: 100.0,
+            std_dev: 10.0,
+        });
+        let mean = (0..10_000)
+            .map(|_| normal.sample(&mut rng) as f64)
+            .sum::<f64>()
+            / 10_000.0;
+        assert!((mean - 100.0).abs() < 1.0);
+    }
+}
@@ -0,0 +1,98 @@
+//! This module accounts for the resource governance metrics of the service packages.
+//!
+//! A service running in a [ServicePackageDescription] with CPU cores or memory limits reports the limits as the load
+//! of the resource governance metrics. Unlike the other metrics, the load is not added up per replica: the replicas
+//! of the services of an application running in the same package on a node share one activation of the package, and
+//! so one reservation of its limits. The load of a node for a resource governance metric is then the sum of the limits
+//! of the distinct packages it hosts a replica of, computed from the replicas of the node rather than indexed.
+//!
+//! The node capacities for the resource governance metrics are hard limits: a replica is not placed on a node where the
+//! reservation of its package, if the node does not host the package yet, would exceed them.
+//!
+//! [ServicePackageDescription]: crate::service::service_package::ServicePackageDescription
+
+use std::collections::BTreeMap;
+
+use crate::{
+    failoverunit::failover_unit::FailoverUnit, node::node_id::NodeId,
+    service::service_package::ServicePackageDescription, ClusterSnapshot,
+};
+
+/// Identifies a service package within its application
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct PackageKey {
+    application_name: String,
+    package_name: String,
+}
+
+/// The replicas of a package hosted by a node, and the reservation of the package
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PackageUsage {
+    pub(crate) replicas: i64,
+    /// The limits of the package per resource governance metric. When the services of a package disagree on them, the
+    /// greatest limit is reserved.
+    pub(crate) loads: BTreeMap<String, u32>,
+}
+
+impl PackageUsage {
+    pub(crate) fn add(&mut self, replicas: i64, service_package: &ServicePackageDescription) {
+        self.replicas += replicas;
+        for (metric_name, load) in service_package.rg_loads() {
+            let reserved = self.loads.entry(String::from(metric_name)).or_default();
+            *reserved = (*reserved).max(load);
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: &PackageUsage) {
+        self.replicas += other.replicas;
+        for (metric_name, load) in &other.loads {
+            let reserved = self.loads.entry(metric_name.clone()).or_default();
+            *reserved = (*reserved).max(*load);
+        }
+    }
+}
+
+/// The resource governance load of a node hosting the packages: the limits of the packages with at least one replica
+pub(crate) fn reserved_load<'a>(
+    packages: impl IntoIterator<Item = &'a PackageUsage>,
+    metric_name: &str,
+) -> u32 {
+    packages
+        .into_iter()
+        .filter(|usage| usage.replicas > 0)
+        .filter_map(|usage| usage.loads.get(metric_name))
+        .fold(0u32, |total, load| total.saturating_add(*load))
+}
+
+impl ClusterSnapshot {
+    /// The resource governed package the replicas of the failover unit run in, if any
+    pub(crate) fn package_of(
+        &self,
+        fu: &FailoverUnit,
+    ) -> Option<(PackageKey, &ServicePackageDescription)> {
+        let service = self.service_of(fu)?;
+        let service_package = service.service_package()?;
+        let key = PackageKey {
+            application_name: String::from(service.application_name()),
+            package_name: String::from(service_package.name()),
+        };
+        Some((key, service_package))
+    }
+
+    /// The resource governed packages with an active replica on the node
+    pub(crate) fn packages_on(&self, node_id: NodeId) -> BTreeMap<PackageKey, PackageUsage> {
+        let mut packages = BTreeMap::<PackageKey, PackageUsage>::new();
+        for (fu, _) in self.replicas_on(node_id) {
+            if let Some((key, service_package)) = self.package_of(fu) {
+                packages.entry(key).or_default().add(1, service_package);
+            }
+        }
+
+        packages
+    }
+
+    /// The load of the node for the resource governance metric
+    pub(crate) fn rg_node_load(&self, node_id: NodeId, metric_name: &str) -> u32 {
+        reserved_load(self.packages_on(node_id).values(), metric_name)
+    }
+}
@@ -0,0 +1,400 @@
+//! This module contains the secondary indexes of the cluster snapshot, so that the replicas hosted by a node, the
+//! failover units of a service, the services of an application or of a service type and the aggregated load of a node
+//! can be found without scanning the whole snapshot.
+//!
+//! The indexes are maintained incrementally: every change to the snapshot goes through the `insert_*` and `remove_*`
+//! methods of [ClusterSnapshot], which update the indexes with the difference. In debug builds and with the
+//! `verify-indexes` feature, every refresh checks that the indexes match the ones rebuilt from scratch, and rebuilds them
+//! if they do not.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{anyhow, Result};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    application::application::Application,
+    failoverunit::failover_unit::{FailoverUnit, Replica},
+    forecast::LoadForecastConfig,
+    load::load_or_move_cost::LoadOrMoveCost,
+    node::{node::Node, node_id::NodeId},
+    service::{service::Service, service_package::is_rg_metric_name},
+    servicetype::service_type::ServiceType,
+    ClusterSnapshot,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SnapshotIndexes {
+    /// Failover units with an active replica on each node
+    node_replicas: BTreeMap<NodeId, BTreeSet<Uuid>>,
+    /// Failover units of each service
+    service_failover_units: BTreeMap<String, BTreeSet<Uuid>>,
+    /// Services of each application, services without an application are not indexed
+    application_services: BTreeMap<String, BTreeSet<String>>,
+    /// Services of each service type
+    service_type_services: BTreeMap<String, BTreeSet<String>>,
+    /// Aggregated load of the active replicas hosted by each node, per metric. Zero loads are not stored.
+    node_loads: BTreeMap<NodeId, BTreeMap<String, u64>>,
+}
+
+/// Adds the value to the set of the key
+fn index_insert<K: Ord, V: Ord>(index: &mut BTreeMap<K, BTreeSet<V>>, key: K, value: V) {
+    index.entry(key).or_default().insert(value);
+}
+
+/// Removes the value from the set of the key, and the key once its set is empty
+fn index_remove<K: Ord, V: Ord>(index: &mut BTreeMap<K, BTreeSet<V>>, key: &K, value: &V) {
+    if let Some(values) = index.get_mut(key) {
+        values.remove(value);
+        if values.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+impl SnapshotIndexes {
+    fn add_service(&mut self, service: &Service) {
+        let service_name = String::from(service.servcie_name());
+        if !service.application_name().is_empty() {
+            index_insert(
+                &mut self.application_services,
+                String::from(service.application_name()),
+                service_name.clone(),
+            );
+        }
+        index_insert(
+            &mut self.service_type_services,
+            String::from(service.service_type_name()),
+            service_name,
+        );
+    }
+
+    fn remove_service(&mut self, service: &Service) {
+        let service_name = String::from(service.servcie_name());
+        index_remove(
+            &mut self.application_services,
+            &String::from(service.application_name()),
+            &service_name,
+        );
+        index_remove(
+            &mut self.service_type_services,
+            &String::from(service.service_type_name()),
+            &service_name,
+        );
+    }
+
+    fn add_failover_unit(&mut self, fu: &FailoverUnit) {
+        for replica in fu.active_replicas() {
+            index_insert(&mut self.node_replicas, replica.location(), fu.id());
+        }
+        index_insert(
+            &mut self.service_failover_units,
+            String::from(fu.service_name()),
+            fu.id(),
+        );
+    }
+
+    fn remove_failover_unit(&mut self, fu: &FailoverUnit) {
+        for replica in fu.active_replicas() {
+            index_remove(&mut self.node_replicas, &replica.location(), &fu.id());
+        }
+        index_remove(
+            &mut self.service_failover_units,
+            &String::from(fu.service_name()),
+            &fu.id(),
+        );
+    }
+
+    fn add_loads(&mut self, loads: &[(NodeId, String, u32)]) {
+        for (node_id, metric_name, load) in loads.iter().filter(|(_, _, load)| *load > 0) {
+            *self
+                .node_loads
+                .entry(*node_id)
+                .or_default()
+                .entry(metric_name.clone())
+                .or_default() += u64::from(*load);
+        }
+    }
+
+    fn remove_loads(&mut self, loads: &[(NodeId, String, u32)]) {
+        for (node_id, metric_name, load) in loads.iter().filter(|(_, _, load)| *load > 0) {
+            let Some(metric_loads) = self.node_loads.get_mut(node_id) else {
+                continue;
+            };
+            if let Some(node_load) = metric_loads.get_mut(metric_name) {
+                *node_load = node_load.saturating_sub(u64::from(*load));
+                if *node_load == 0 {
+                    metric_loads.remove(metric_name);
+                }
+            }
+            if metric_loads.is_empty() {
+                self.node_loads.remove(node_id);
+            }
+        }
+    }
+}
+
+impl ClusterSnapshot {
+    /// Rebuilds every index from the content of the snapshot
+    pub(crate) fn rebuild_indexes(&mut self) {
+        self.indexes = self.build_indexes();
+    }
+
+    fn build_indexes(&self) -> SnapshotIndexes {
+        let mut indexes = SnapshotIndexes::default();
+        for service in self.services.values() {
+            indexes.add_service(service);
+        }
+        for fu in self.failover_units.values() {
+            indexes.add_failover_unit(fu);
+            indexes.add_loads(&self.load_contributions(fu.id()));
+        }
+
+        indexes
+    }
+
+    /// Checks that the incrementally maintained indexes match the ones rebuilt from scratch
+    pub(crate) fn verify_indexes(&self) -> Result<()> {
+        let expected = self.build_indexes();
+        let actual = &self.indexes;
+        let mismatch = if actual.node_replicas != expected.node_replicas {
+            Some("node replicas")
+        } else if actual.service_failover_units != expected.service_failover_units {
+            Some("service failover units")
+        } else if actual.application_services != expected.application_services {
+            Some("application services")
+        } else if actual.service_type_services != expected.service_type_services {
+            Some("service type services")
+        } else if actual.node_loads != expected.node_loads {
+            Some("node loads")
+        } else {
+            None
+        };
+
+        match mismatch {
+            Some(index_name) => Err(anyhow!(
+                "The {} index of the cluster snapshot is out of sync",
+                index_name
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the (node, metric, load) triples added by the active replicas of the failover unit
+    fn load_contributions(&self, fu_id: Uuid) -> Vec<(NodeId, String, u32)> {
+        let Some(fu) = self.failover_units.get(&fu_id) else {
+            return vec![];
+        };
+        fu.active_replicas()
+            .flat_map(|replica| {
+                self.replica_loads(fu, replica.role())
+                    .into_iter()
+                    .map(|(metric_name, load)| (replica.location(), metric_name, load))
+            })
+            .collect()
+    }
+
+    /// Applies a change that can affect the load of the failover units: their contribution to the node loads is
+    /// removed before the change and added back after it
+    fn with_load_contributions<R>(
+        &mut self,
+        fu_ids: &[Uuid],
+        change: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        for fu_id in fu_ids {
+            let loads = self.load_contributions(*fu_id);
+            self.indexes.remove_loads(&loads);
+        }
+        let result = change(self);
+        for fu_id in fu_ids {
+            let loads = self.load_contributions(*fu_id);
+            self.indexes.add_loads(&loads);
+        }
+
+        result
+    }
+
+    pub(crate) fn insert_node(&mut self, node: Node) -> Option<Node> {
+        self.nodes.insert(node.node_id(), node)
+    }
+
+    pub(crate) fn remove_node(&mut self, node_id: NodeId) -> Option<Node> {
+        self.nodes.remove(&node_id)
+    }
+
+    pub(crate) fn insert_application(&mut self, app: Application) -> Option<Application> {
+        self.apps.insert(String::from(app.app_name()), app)
+    }
+
+    pub(crate) fn remove_application(&mut self, app_name: &str) -> Option<Application> {
+        self.apps.remove(app_name)
+    }
+
+    pub(crate) fn insert_service_type(&mut self, service_type: ServiceType) -> Option<ServiceType> {
+        self.service_types
+            .insert(String::from(service_type.service_type_name()), service_type)
+    }
+
+    pub(crate) fn remove_service_type(&mut self, service_type_name: &str) -> Option<ServiceType> {
+        self.service_types.remove(service_type_name)
+    }
+
+    /// Inserts or replaces the service. The metrics of the service define the load of its failover units.
+    pub(crate) fn insert_service(&mut self, service: Service) -> Option<Service> {
+        let service_name = String::from(service.servcie_name());
+        let fu_ids = self.failover_unit_ids_of_service(&service_name);
+        self.with_load_contributions(&fu_ids, |snapshot| {
+            let previous = snapshot.services.insert(service_name.clone(), service);
+            if let Some(previous) = previous.as_ref() {
+                snapshot.indexes.remove_service(previous);
+            }
+            snapshot
+                .indexes
+                .add_service(&snapshot.services[&service_name]);
+            previous
+        })
+    }
+
+    pub(crate) fn remove_service(&mut self, service_name: &str) -> Option<Service> {
+        let fu_ids = self.failover_unit_ids_of_service(service_name);
+        self.with_load_contributions(&fu_ids, |snapshot| {
+            let previous = snapshot.services.remove(service_name);
+            if let Some(previous) = previous.as_ref() {
+                snapshot.indexes.remove_service(previous);
+            }
+            previous
+        })
+    }
+
+    /// Inserts or replaces the failover unit
+    pub(crate) fn insert_failover_unit(&mut self, fu: FailoverUnit) -> Option<FailoverUnit> {
+        let fu_id = fu.id();
+        self.with_load_contributions(&[fu_id], |snapshot| {
+            let previous = snapshot.failover_units.insert(fu_id, fu);
+            if let Some(previous) = previous.as_ref() {
+                snapshot.indexes.remove_failover_unit(previous);
+            }
+            snapshot
+                .indexes
+                .add_failover_unit(&snapshot.failover_units[&fu_id]);
+            previous
+        })
+    }
+
+    /// Removes the failover unit together with its reported load
+    pub(crate) fn remove_failover_unit(&mut self, fu_id: Uuid) -> Option<FailoverUnit> {
+        self.with_load_contributions(&[fu_id], |snapshot| {
+            snapshot.loads.remove(&fu_id);
+            snapshot.load_history.remove(fu_id);
+            let previous = snapshot.failover_units.remove(&fu_id);
+            if let Some(previous) = previous.as_ref() {
+                snapshot.indexes.remove_failover_unit(previous);
+            }
+            previous
+        })
+    }
+
+    /// Inserts or replaces the load reported for a failover unit at `now`, and appends it to the load history
+    pub(crate) fn insert_load(
+        &mut self,
+        load: LoadOrMoveCost,
+        now: OffsetDateTime,
+        config: &LoadForecastConfig,
+    ) -> Option<LoadOrMoveCost> {
+        let fu_id = load.id();
+        self.with_load_contributions(&[fu_id], |snapshot| {
+            snapshot
+                .load_history
+                .record(&load.load_description, now, config);
+            snapshot.loads.insert(fu_id, load)
+        })
+    }
+
+    /// Recomputes the forecast loads after a change of the forecasting settings
+    pub(crate) fn reforecast_loads(&mut self, config: &LoadForecastConfig) {
+        self.load_history.reforecast(config);
+        self.rebuild_indexes();
+    }
+
+    fn failover_unit_ids_of_service(&self, service_name: &str) -> Vec<Uuid> {
+        self.indexes
+            .service_failover_units
+            .get(service_name)
+            .map(|fu_ids| fu_ids.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the failover units with an active replica on the node together with that replica, in failover unit
+    /// id order
+    pub(crate) fn replicas_on(
+        &self,
+        node_id: NodeId,
+    ) -> impl Iterator<Item = (&FailoverUnit, &Replica)> + '_ {
+        self.indexes
+            .node_replicas
+            .get(&node_id)
+            .into_iter()
+            .flatten()
+            .filter_map(move |fu_id| {
+                let fu = self.failover_units.get(fu_id)?;
+                let replica = fu
+                    .active_replicas()
+                    .find(|replica| replica.location() == node_id)?;
+                Some((fu, replica))
+            })
+    }
+
+    /// Returns the failover units of the service, in id order
+    pub(crate) fn failover_units_of_service<'a>(
+        &'a self,
+        service_name: &str,
+    ) -> impl Iterator<Item = &'a FailoverUnit> + 'a {
+        self.indexes
+            .service_failover_units
+            .get(service_name)
+            .into_iter()
+            .flatten()
+            .filter_map(|fu_id| self.failover_units.get(fu_id))
+    }
+
+    /// Returns the services of the application, in name order
+    pub(crate) fn services_of_application<'a>(
+        &'a self,
+        app_name: &str,
+    ) -> impl Iterator<Item = &'a Service> + 'a {
+        self.indexes
+            .application_services
+            .get(app_name)
+            .into_iter()
+            .flatten()
+            .filter_map(|service_name| self.services.get(service_name))
+    }
+
+    /// Returns the services of the service type, in name order
+    pub(crate) fn services_of_service_type<'a>(
+        &'a self,
+        service_type_name: &str,
+    ) -> impl Iterator<Item = &'a Service> + 'a {
+        self.indexes
+            .service_type_services
+            .get(service_type_name)
+            .into_iter()
+            .flatten()
+            .filter_map(|service_name| self.services.get(service_name))
+    }
+
+    /// Returns the aggregated load of all the replicas hosted on the node for the metric. The load of the resource
+    /// governance metrics is not indexed but reserved per service package, see [crate::governance].
+    pub(crate) fn node_load(&self, node_id: NodeId, metric_name: &str) -> u32 {
+        if is_rg_metric_name(metric_name) {
+            return self.rg_node_load(node_id, metric_name);
+        }
+        self.indexes
+            .node_loads
+            .get(&node_id)
+            .and_then(|loads| loads.get(metric_name))
+            .map(|load| u32::try_from(*load).unwrap_or(u32::MAX))
+            .unwrap_or_default()
+    }
+}
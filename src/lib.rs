@@ -1,36 +1,89 @@
 use std::{
     cell::RefCell,
-    collections::{BTreeMap, HashSet, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
     rc::Rc,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
+pub mod annealing;
 pub mod application;
+pub(crate) mod balance;
+pub mod capacity;
+pub mod config;
+pub mod constraint;
+pub mod drain;
+pub mod events;
+pub mod exact;
 pub mod failoverunit;
+pub mod forecast;
+pub mod generator;
+pub(crate) mod governance;
+pub(crate) mod index;
 pub mod load;
+pub mod metrics;
 pub mod node;
+pub mod pending;
+pub(crate) mod promotion;
+pub(crate) mod random;
+pub mod report;
 pub mod scheduler;
+pub mod score;
 pub mod searcher;
 pub mod service;
 pub mod servicetype;
+#[cfg(feature = "serde")]
+pub mod snapshot;
 pub mod solver;
+pub mod strategy;
+#[cfg(feature = "serde")]
+pub mod trace;
+pub mod upgrade;
+pub mod validation;
+pub mod whatif;
 
 use application::{application::Application, application_description::ApplicationDescription};
-use failoverunit::failover_unit::{FailoverUnit, FailoverUnitDescription};
+use failoverunit::failover_unit::{FailoverUnit, FailoverUnitDescription, ReplicaRole};
 use load::load_or_move_cost::{LoadOrMoveCost, LoadOrMoveCostDescription};
 use node::node_id::NodeId;
-use node::{node::Node, node_description::NodeDescription};
+use node::{
+    node::Node,
+    node_description::{DomainId, NodeDescription},
+};
 use scheduler::PLBScheduler;
-use service::{service::Service, service_description::ServiceDescription};
+use service::{
+    built_in_type::BuiltInType, service::Service, service_description::ServiceDescription,
+    service_metric::ServiceMetric,
+};
 use servicetype::{service_type::ServiceType, service_type_description::ServiceTypeDescription};
 
 use std::cmp::Ordering;
 
-use anyhow::Result;
-use searcher::Searcher;
-use solver::{Solution, Solver};
+use annealing::SearchStatistics;
+use anyhow::{anyhow, Result};
+use balance::LoadTable;
+use capacity::CapacityReport;
+use config::PLBConfig;
+use constraint::PlacementExplanation;
+use drain::{DrainPlan, DrainPlanner};
+use events::{EntityKind, NoopEventSink, PlbEvent, PlbEventSink};
+use exact::{ExactSolution, ExactSolver, ExactSolverLimits, OptimalityGap};
+use forecast::LoadHistory;
+use index::SnapshotIndexes;
+use metrics::PlbMetrics;
+use pending::{MovementOutcome, PendingMovement, PendingMovements, SolutionId};
+use report::{PhaseReport, PhaseSkipReason, RefreshReport, SkippedPhase, UpdateCounts};
+use scheduler::Phase;
+use score::ClusterScore;
+use solver::Movement;
+use strategy::{create_strategy, SearchStrategy, SnapshotView};
 use time::OffsetDateTime;
+#[cfg(feature = "serde")]
+use trace::TraceRecord;
+use upgrade::{UpgradeDomainReadiness, UpgradePlanner, UpgradeScope};
 use uuid::Uuid;
+use validation::{PlanValidation, PlanValidator, RejectedMovement};
+use whatif::{TopologyChange, WhatIfReport, WhatIfSimulator};
 
 #[derive(Default)]
 struct UpdateQueue {
@@ -40,9 +93,37 @@ struct UpdateQueue {
     service_update_queue: VecDeque<Service>,
     failover_unit_update_queue: VecDeque<FailoverUnit>,
     load_update_queue: VecDeque<LoadOrMoveCost>,
+    /// Upgrade domain being upgraded per scope, None once the upgrade of the scope is completed
+    upgrade_update_queue: VecDeque<(UpgradeScope, Option<DomainId>)>,
+    /// Entities to remove from the snapshot, processed after all the updates
+    deletion_queue: VecDeque<Deletion>,
 }
 
-#[derive(Default)]
+impl UpdateQueue {
+    /// Number of updates waiting for the next refresh
+    fn len(&self) -> usize {
+        self.node_update_queue.len()
+            + self.app_update_queue.len()
+            + self.service_type_update_queue.len()
+            + self.service_update_queue.len()
+            + self.failover_unit_update_queue.len()
+            + self.load_update_queue.len()
+            + self.upgrade_update_queue.len()
+            + self.deletion_queue.len()
+    }
+}
+
+/// An entity removed from the cluster
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Deletion {
+    Node(NodeId),
+    Application(String),
+    ServiceType(String),
+    Service(String),
+    FailoverUnit(Uuid),
+}
+
+#[derive(Clone, Default)]
 pub struct ClusterSnapshot {
     nodes: BTreeMap<NodeId, Node>,
     apps: BTreeMap<String, Application>,
@@ -50,44 +131,27 @@ pub struct ClusterSnapshot {
     services: BTreeMap<String, Service>,
     failover_units: BTreeMap<Uuid, FailoverUnit>,
     loads: BTreeMap<Uuid, LoadOrMoveCost>,
+    /// The upgrade domain currently being upgraded for each upgrade in progress
+    upgrades: BTreeMap<UpgradeScope, DomainId>,
+    /// Secondary indexes, kept in sync by the insert and remove methods
+    indexes: SnapshotIndexes,
+    /// Loads reported since the engine started, to forecast the predicted metrics
+    load_history: LoadHistory,
+    /// Metrics of every service not declaring a metric with the same name, see [PLBConfig::default_metrics]
+    default_metrics: Vec<ServiceMetric>,
 }
 
-/// Similar to the C++ implementation. This is the main entry point of the entire PLB engine.
-/// It consists of all the required data structures to basically does 3 things:
-///     1. Listen to update cluster info API calls
-///     2. Run the PLB refresh loop
-///     3. Schedule searches and solutions if required
-
-#[derive(Default)]
-pub struct PlacementAndLoadBalancing {
-    /// PLB takes snapshot of the cluster information before refreshing
-    /// During the PLB refresh this cluster snapshot can not be modified
-    cluster_snapshot: Rc<RefCell<ClusterSnapshot>>,
-    /// PLB cannot operate on stale operation, but also cannot be interrupted by the new update when searching for solutions
-    /// This update queue is guarded by a single mutex and is read by PLB on the start of the refresh
-    plb_update_queue: Arc<Mutex<UpdateQueue>>,
-    scheduler: PLBScheduler,
-    searcher: Searcher,
-    solver: Solver,
-}
-
-impl PlacementAndLoadBalancing {
-    /// Initializes the PlacementAndLoadBalancing object with the required cluster information:
-    ///     - Nodes
-    ///     - Applications
-    ///     - Service types
-    ///     - Services
-    ///     - Failover units
-    ///     - Loads or move costs
-    pub fn new(
+impl ClusterSnapshot {
+    /// Builds the snapshot from the descriptions, checked like the updates: returns an error if a service is invalid,
+    /// and clamps the loads to the maximum load of their metric
+    pub(crate) fn from_descriptions(
         nodes: Vec<NodeDescription>,
         apps: Vec<ApplicationDescription>,
         service_types: Vec<ServiceTypeDescription>,
         services: Vec<ServiceDescription>,
         failover_units: Vec<FailoverUnitDescription>,
         loads: Vec<LoadOrMoveCostDescription>,
-    ) -> Self {
-        // copy the cluster information to cluster snapshot, without going through the update queue
+    ) -> Result<Self> {
         let node_map = nodes
             .into_iter()
             .map(|node_desc| {
@@ -107,7 +171,6 @@ impl PlacementAndLoadBalancing {
                     app_desc.app_name.clone(),
                     Application {
                         application_desc: app_desc,
-                        services: HashSet::new(),
                     },
                 )
             })
@@ -126,14 +189,13 @@ impl PlacementAndLoadBalancing {
         let service_map = services
             .into_iter()
             .map(|service_desc| {
-                (
-                    service_desc.service_name.clone(),
-                    Service {
-                        service_description: service_desc,
-                    },
-                )
+                let service = Service::new(service_desc);
+                service.validate().map_err(|err| {
+                    anyhow!("Invalid service {}: {}", service.servcie_name(), err)
+                })?;
+                Ok((String::from(service.servcie_name()), service))
             })
-            .collect::<BTreeMap<String, Service>>();
+            .collect::<Result<BTreeMap<String, Service>>>()?;
 
         let fu_map = failover_units
             .into_iter()
@@ -159,23 +221,190 @@ impl PlacementAndLoadBalancing {
             })
             .collect::<BTreeMap<Uuid, LoadOrMoveCost>>();
 
+        let mut cluster_snapshot = ClusterSnapshot {
+            nodes: node_map,
+            apps: app_map,
+            service_types: service_type_map,
+            services: service_map,
+            failover_units: fu_map,
+            loads: BTreeMap::new(),
+            upgrades: BTreeMap::new(),
+            indexes: SnapshotIndexes::default(),
+            load_history: LoadHistory::default(),
+            default_metrics: vec![],
+        };
+        for mut load in load_map.into_values() {
+            // there is no previous load to detect a spike against
+            cluster_snapshot.check_load_report(&mut load, 0.0);
+            cluster_snapshot.loads.insert(load.id(), load);
+        }
+        cluster_snapshot.rebuild_indexes();
+
+        Ok(cluster_snapshot)
+    }
+
+    pub(crate) fn service_of(&self, fu: &FailoverUnit) -> Option<&Service> {
+        self.services.get(fu.service_name())
+    }
+
+    pub(crate) fn service_type_of(&self, fu: &FailoverUnit) -> Option<&ServiceType> {
+        self.service_of(fu)
+            .and_then(|service| self.service_types.get(service.service_type_name()))
+    }
+
+    /// Returns the metrics of the service: the ones it declares, the resource governance metrics of its service package
+    /// it does not declare, then the default metrics it does not declare
+    pub(crate) fn service_metrics<'a>(
+        &'a self,
+        service: &'a Service,
+    ) -> impl Iterator<Item = &'a ServiceMetric> + 'a {
+        let declared = service.metrics().iter().chain(service.rg_metrics());
+        declared
+            .clone()
+            .chain(self.default_metrics.iter().filter(move |default| {
+                declared
+                    .clone()
+                    .all(|metric| metric.name() != default.name())
+            }))
+    }
+
+    /// Replaces the default metrics of the services, and recomputes the node loads if they change
+    pub(crate) fn set_default_metrics(&mut self, default_metrics: &[ServiceMetric]) {
+        if self.default_metrics != default_metrics {
+            self.default_metrics = default_metrics.to_vec();
+            self.rebuild_indexes();
+        }
+    }
+
+    /// Returns the load of a replica of the failover unit with the given role for every metric of its service, but the
+    /// resource governance ones reserved per service package, see [governance].
+    /// The built-in metrics counting replicas are computed from the role, the forecast load is used for the predicted
+    /// metrics, the reported load if there is one, otherwise the default load of the metric.
+    pub(crate) fn replica_loads(&self, fu: &FailoverUnit, role: ReplicaRole) -> Vec<(String, u32)> {
+        let Some(service) = self.service_of(fu) else {
+            return vec![];
+        };
+        let reported = self.loads.get(&fu.id());
+        self.service_metrics(service)
+            .filter(|metric| !metric.is_rg_metric())
+            .map(|metric| {
+                let forecast = match metric.built_in_type {
+                    BuiltInType::Predicted => {
+                        self.load_history.forecast(fu.id(), metric.name(), role)
+                    }
+                    _ => None,
+                };
+                let load = metric
+                    .count_load(role)
+                    .or(forecast)
+                    .or_else(|| reported.and_then(|load| load.load(metric.name(), role)))
+                    .unwrap_or_else(|| metric.default_load(role));
+                (String::from(metric.name()), load)
+            })
+            .collect()
+    }
+
+    /// Returns the names of all the metrics of the services, in order
+    pub(crate) fn metric_names(&self) -> BTreeSet<String> {
+        self.services
+            .values()
+            .flat_map(|service| {
+                self.service_metrics(service)
+                    .map(|metric| String::from(metric.name()))
+            })
+            .collect()
+    }
+}
+
+/// Similar to the C++ implementation. This is the main entry point of the entire PLB engine.
+/// It consists of all the required data structures to basically does 3 things:
+///     1. Listen to update cluster info API calls
+///     2. Run the PLB refresh loop
+///     3. Schedule searches and solutions if required
+pub struct PlacementAndLoadBalancing {
+    /// PLB takes snapshot of the cluster information before refreshing
+    /// During the PLB refresh this cluster snapshot can not be modified
+    cluster_snapshot: Rc<RefCell<ClusterSnapshot>>,
+    /// PLB cannot operate on stale operation, but also cannot be interrupted by the new update when searching for solutions
+    /// This update queue is guarded by a single mutex and is read by PLB on the start of the refresh
+    plb_update_queue: Arc<Mutex<UpdateQueue>>,
+    scheduler: PLBScheduler,
+    config: PLBConfig,
+    /// The search strategy of each phase, created from the configuration when the phase first runs
+    strategies: HashMap<Phase, Box<dyn SearchStrategy>>,
+    /// Statistics of the searches run during the last refresh
+    search_statistics: Vec<SearchStatistics>,
+    /// Movements generated by the last refresh, in the order of the solutions
+    movements: Vec<Movement>,
+    /// Receives the events of the engine, none are reported if unset
+    event_sink: Option<Rc<dyn PlbEventSink>>,
+    /// Counters and gauges updated by every refresh
+    metrics: PlbMetrics,
+    /// Solutions handed to FM and not acknowledged yet, counted in the snapshot the searches run on
+    pending: PendingMovements,
+    /// Trace of the calls made to the engine, when recording
+    #[cfg(feature = "serde")]
+    recorder: RefCell<Option<trace::TraceRecorder>>,
+}
+
+impl Default for PlacementAndLoadBalancing {
+    fn default() -> Self {
+        Self::with_snapshot(ClusterSnapshot::default())
+    }
+}
+
+impl PlacementAndLoadBalancing {
+    /// Initializes the PlacementAndLoadBalancing object with the required cluster information:
+    ///     - Nodes
+    ///     - Applications
+    ///     - Service types
+    ///     - Services
+    ///     - Failover units
+    ///     - Loads or move costs
+    ///
+    /// The descriptions are checked like the updates: returns an error if a service is invalid, see
+    /// [PlacementAndLoadBalancing::update_service], and clamps the loads to the maximum load of their metric.
+    pub fn new(
+        nodes: Vec<NodeDescription>,
+        apps: Vec<ApplicationDescription>,
+        service_types: Vec<ServiceTypeDescription>,
+        services: Vec<ServiceDescription>,
+        failover_units: Vec<FailoverUnitDescription>,
+        loads: Vec<LoadOrMoveCostDescription>,
+    ) -> Result<Self> {
+        // copy the cluster information to cluster snapshot, without going through the update queue
+        Ok(Self::with_snapshot(ClusterSnapshot::from_descriptions(
+            nodes,
+            apps,
+            service_types,
+            services,
+            failover_units,
+            loads,
+        )?))
+    }
+
+    fn with_snapshot(mut cluster_snapshot: ClusterSnapshot) -> Self {
+        let config = PLBConfig::default();
+        cluster_snapshot.set_default_metrics(&config.default_metrics);
         PlacementAndLoadBalancing {
-            cluster_snapshot: Rc::new(RefCell::new(ClusterSnapshot {
-                nodes: node_map,
-                apps: app_map,
-                service_types: service_type_map,
-                services: service_map,
-                failover_units: fu_map,
-                loads: load_map,
-            })),
+            cluster_snapshot: Rc::new(RefCell::new(cluster_snapshot)),
             plb_update_queue: Arc::new(Mutex::new(UpdateQueue::default())),
             scheduler: PLBScheduler::new(OffsetDateTime::now_utc()),
-            searcher: Searcher::default(),
-            solver: Solver::default(),
+            config,
+            strategies: HashMap::new(),
+            search_statistics: vec![],
+            movements: vec![],
+            event_sink: None,
+            metrics: PlbMetrics::default(),
+            pending: PendingMovements::default(),
+            #[cfg(feature = "serde")]
+            recorder: RefCell::new(None),
         }
     }
 
     pub fn update_node(&mut self, node_desc: NodeDescription) {
+        #[cfg(feature = "serde")]
+        self.record(|| TraceRecord::UpdateNode(node_desc.clone()));
         let update_queue_clone = Arc::clone(&self.plb_update_queue);
         update_queue_clone
             .lock()
@@ -186,11 +415,28 @@ impl PlacementAndLoadBalancing {
             });
     }
 
-    pub fn delete_node(&mut self, _node_id: NodeId) -> Result<()> {
-        unimplemented!()
+    /// Removes the node from the cluster on the next refresh. Returns an error if the node is neither in the cluster
+    /// snapshot nor pending an update.
+    pub fn delete_node(&mut self, node_id: NodeId) -> Result<()> {
+        let known = self.cluster_snapshot.borrow().nodes.contains_key(&node_id)
+            || self
+                .plb_update_queue
+                .lock()
+                .unwrap()
+                .node_update_queue
+                .iter()
+                .any(|node| node.node_id() == node_id);
+        if !known {
+            return Err(anyhow!("Node {:?} is not in the cluster", node_id));
+        }
+
+        self.queue_deletion(Deletion::Node(node_id));
+        Ok(())
     }
 
     pub fn update_application(&self, app_desc: ApplicationDescription) {
+        #[cfg(feature = "serde")]
+        self.record(|| TraceRecord::UpdateApplication(app_desc.clone()));
         let update_queue_clone = Arc::clone(&self.plb_update_queue);
         update_queue_clone
             .lock()
@@ -198,45 +444,116 @@ impl PlacementAndLoadBalancing {
             .app_update_queue
             .push_back(Application {
                 application_desc: app_desc,
-                services: HashSet::new(),
             });
     }
 
-    pub fn delete_application(&mut self) -> Result<()> {
-        unimplemented!()
+    /// Removes the application from the cluster on the next refresh. Returns an error if the application is neither
+    /// in the cluster snapshot nor pending an update.
+    pub fn delete_application(&mut self, app_name: &str) -> Result<()> {
+        let known = self.cluster_snapshot.borrow().apps.contains_key(app_name)
+            || self
+                .plb_update_queue
+                .lock()
+                .unwrap()
+                .app_update_queue
+                .iter()
+                .any(|app| app.app_name() == app_name);
+        if !known {
+            return Err(anyhow!("Application {} is not in the cluster", app_name));
+        }
+
+        self.queue_deletion(Deletion::Application(String::from(app_name)));
+        Ok(())
     }
 
     pub fn update_service_type(&mut self, service_type_desc: ServiceTypeDescription) {
+        #[cfg(feature = "serde")]
+        self.record(|| TraceRecord::UpdateServiceType(service_type_desc.clone()));
         let update_queue_clone = Arc::clone(&self.plb_update_queue);
         update_queue_clone
             .lock()
             .unwrap()
             .service_type_update_queue
-            .push_back(ServiceType {
-                service_type_desc,
-            });
+            .push_back(ServiceType { service_type_desc });
     }
 
-    pub fn delete_service_type(&mut self) -> Result<()> {
-        unimplemented!()
+    /// Removes the service type from the cluster on the next refresh. Returns an error if the service type is
+    /// neither in the cluster snapshot nor pending an update.
+    pub fn delete_service_type(&mut self, service_type_name: &str) -> Result<()> {
+        let known = self
+            .cluster_snapshot
+            .borrow()
+            .service_types
+            .contains_key(service_type_name)
+            || self
+                .plb_update_queue
+                .lock()
+                .unwrap()
+                .service_type_update_queue
+                .iter()
+                .any(|service_type| service_type.service_type_name() == service_type_name);
+        if !known {
+            return Err(anyhow!(
+                "Service type {} is not in the cluster",
+                service_type_name
+            ));
+        }
+
+        self.queue_deletion(Deletion::ServiceType(String::from(service_type_name)));
+        Ok(())
     }
 
-    pub fn update_service(&mut self, service_desc: ServiceDescription) {
+    /// Adds or replaces the service on the next refresh. Returns an error, and emits an update rejection, if the
+    /// placement constraints of the service do not parse or the default load of one of its metrics is above its
+    /// maximum load.
+    pub fn update_service(&mut self, service_desc: ServiceDescription) -> Result<()> {
+        let service = Service::new(service_desc);
+        if let Err(err) = service.validate() {
+            let key = String::from(service.servcie_name());
+            self.emit_rejected(EntityKind::Service, key, &err.to_string());
+            return Err(anyhow!(
+                "Invalid service {}: {}",
+                service.servcie_name(),
+                err
+            ));
+        }
+        #[cfg(feature = "serde")]
+        self.record(|| TraceRecord::UpdateService(service.service_description.clone()));
         let update_queue_clone = Arc::clone(&self.plb_update_queue);
         update_queue_clone
             .lock()
             .unwrap()
             .service_update_queue
-            .push_back(Service {
-                service_description: service_desc,
-            });
+            .push_back(service);
+        Ok(())
     }
 
-    pub fn delete_service(&mut self) -> Result<()> {
-        unimplemented!()
+    /// Removes the service from the cluster on the next refresh. Its failover units are deleted separately.
+    /// Returns an error if the service is neither in the cluster snapshot nor pending an update.
+    pub fn delete_service(&mut self, service_name: &str) -> Result<()> {
+        let known = self
+            .cluster_snapshot
+            .borrow()
+            .services
+            .contains_key(service_name)
+            || self
+                .plb_update_queue
+                .lock()
+                .unwrap()
+                .service_update_queue
+                .iter()
+                .any(|service| service.servcie_name() == service_name);
+        if !known {
+            return Err(anyhow!("Service {} is not in the cluster", service_name));
+        }
+
+        self.queue_deletion(Deletion::Service(String::from(service_name)));
+        Ok(())
     }
 
     pub fn update_failover_unit(&mut self, fu_desc: FailoverUnitDescription) {
+        #[cfg(feature = "serde")]
+        self.record(|| TraceRecord::UpdateFailoverUnit(fu_desc.clone()));
         let update_queue_clone = Arc::clone(&self.plb_update_queue);
         update_queue_clone
             .lock()
@@ -247,11 +564,45 @@ impl PlacementAndLoadBalancing {
             });
     }
 
-    pub fn delete_failover_unit(&mut self) -> Result<()> {
-        unimplemented!()
+    /// Removes the failover unit and its reported load from the cluster on the next refresh. Returns an error if the
+    /// failover unit is neither in the cluster snapshot nor pending an update.
+    pub fn delete_failover_unit(&mut self, fu_id: Uuid) -> Result<()> {
+        let known = self
+            .cluster_snapshot
+            .borrow()
+            .failover_units
+            .contains_key(&fu_id)
+            || self
+                .plb_update_queue
+                .lock()
+                .unwrap()
+                .failover_unit_update_queue
+                .iter()
+                .any(|fu| fu.id() == fu_id);
+        if !known {
+            return Err(anyhow!("Partition {} is not in the cluster", fu_id));
+        }
+
+        self.queue_deletion(Deletion::FailoverUnit(fu_id));
+        Ok(())
+    }
+
+    fn queue_deletion(&mut self, deletion: Deletion) {
+        #[cfg(feature = "serde")]
+        self.record(|| deletion.to_record());
+        let update_queue_clone = Arc::clone(&self.plb_update_queue);
+        update_queue_clone
+            .lock()
+            .unwrap()
+            .deletion_queue
+            .push_back(deletion);
     }
 
+    /// Reports the load of a failover unit, applied on the next refresh. The loads above the maximum load of their
+    /// metric are clamped to it, see [load::load_check].
     pub fn update_load_or_move_cost(&mut self, load_desc: LoadOrMoveCostDescription) {
+        #[cfg(feature = "serde")]
+        self.record(|| TraceRecord::UpdateLoad(load_desc.clone()));
         let update_queue_clone = Arc::clone(&self.plb_update_queue);
         update_queue_clone
             .lock()
@@ -262,13 +613,247 @@ impl PlacementAndLoadBalancing {
             });
     }
 
+    pub fn config(&self) -> &PLBConfig {
+        &self.config
+    }
+
+    /// Replaces the engine configuration. The search strategies are recreated from it on the next refresh.
+    pub fn set_config(&mut self, config: PLBConfig) {
+        #[cfg(feature = "serde")]
+        self.record(|| TraceRecord::SetConfig(config.clone()));
+        let mut snapshot = self.cluster_snapshot.borrow_mut();
+        if config.load_forecast != self.config.load_forecast {
+            snapshot.reforecast_loads(&config.load_forecast);
+        }
+        snapshot.set_default_metrics(&config.default_metrics);
+        drop(snapshot);
+        self.config = config;
+        self.strategies.clear();
+    }
+
+    /// Overrides the search strategy of a phase with a custom one, until the configuration is replaced
+    pub fn set_search_strategy(&mut self, phase: Phase, strategy: Box<dyn SearchStrategy>) {
+        #[cfg(feature = "serde")]
+        self.record(|| TraceRecord::SetSearchStrategy {
+            phase,
+            name: String::from(strategy.name()),
+        });
+        self.strategies.insert(phase, strategy);
+    }
+
+    /// Sets the sink receiving the events of the engine from the next refresh on
+    pub fn set_event_sink(&mut self, event_sink: Rc<dyn PlbEventSink>) {
+        self.event_sink = Some(event_sink);
+    }
+
+    fn emit(&self, event: PlbEvent) {
+        if let Some(event_sink) = &self.event_sink {
+            event_sink.on_event(&event);
+        }
+    }
+
+    /// Reports how FM carried out a solution generated by a refresh. The movement is no longer considered in flight:
+    /// once it succeeded, FM is expected to report the new state of the failover unit with an update before the next
+    /// refresh, and a failed movement may be proposed again. Returns an error if the solution is not pending, e.g. it
+    /// expired, in which case the acknowledgment is not recorded.
+    ///
+    /// The follow-ups of a movement, like the drop of a make-before-break move, are handed out by the next refresh once
+    /// the movement succeeded, and discarded if it failed. FM must carry out the solutions of a refresh in order and
+    /// acknowledge each one once it is done.
+    pub fn acknowledge_solution(&mut self, id: SolutionId, outcome: MovementOutcome) -> Result<()> {
+        let acknowledged = match outcome {
+            MovementOutcome::Succeeded => self.pending.succeed(id),
+            MovementOutcome::Failed => self.pending.remove(id),
+        };
+        if acknowledged.is_none() {
+            return Err(anyhow!("Solution {} is not pending", id));
+        }
+        #[cfg(feature = "serde")]
+        self.record(|| TraceRecord::AcknowledgeSolution { id, outcome });
+
+        self.metrics.observe_acknowledgment(outcome, &self.pending);
+        self.emit(PlbEvent::SolutionAcknowledged { id, outcome });
+        Ok(())
+    }
+
+    /// Returns the solutions handed to FM and not acknowledged yet, in the order they were generated
+    pub fn pending_movements(&self) -> Vec<PendingMovement> {
+        self.pending.iter().cloned().collect()
+    }
+
+    /// Returns the metrics of the engine, with the current depth of the update queue
+    pub fn metrics(&self) -> PlbMetrics {
+        let mut metrics = self.metrics.clone();
+        metrics.update_queue_depth = self.plb_update_queue.lock().unwrap().len() as u64;
+        metrics
+    }
+
+    /// Renders the metrics of the engine in the Prometheus text exposition format
+    pub fn render_metrics(&self) -> String {
+        self.metrics().render()
+    }
+
+    /// Returns the statistics of the searches run during the last refresh
+    pub fn last_search_statistics(&self) -> &[SearchStatistics] {
+        &self.search_statistics
+    }
+
+    /// Returns the movements generated by the last refresh, in the same order as the solutions it returned
+    pub fn last_movements(&self) -> &[Movement] {
+        &self.movements
+    }
+
+    /// Returns the description of the failover unit as known by the cluster snapshot
+    pub fn failover_unit_description(&self, fu_id: Uuid) -> Option<FailoverUnitDescription> {
+        self.cluster_snapshot
+            .borrow()
+            .failover_units
+            .get(&fu_id)
+            .map(|fu| fu.failover_unit_description.clone())
+    }
+
+    /// Returns the aggregated load of every node of the cluster snapshot, for every metric
+    pub fn node_loads(&self) -> BTreeMap<NodeId, BTreeMap<String, u32>> {
+        let snapshot = self.cluster_snapshot.borrow();
+        let metric_names = snapshot.metric_names();
+        snapshot
+            .nodes
+            .keys()
+            .map(|node_id| {
+                let loads = metric_names
+                    .iter()
+                    .map(|metric_name| {
+                        (
+                            metric_name.clone(),
+                            snapshot.node_load(*node_id, metric_name),
+                        )
+                    })
+                    .collect();
+                (*node_id, loads)
+            })
+            .collect()
+    }
+
+    /// Returns the failover units with an active replica on the node and the role of that replica, in failover unit
+    /// id order
+    pub fn replicas_on_node(&self, node_id: NodeId) -> Vec<(Uuid, ReplicaRole)> {
+        self.cluster_snapshot
+            .borrow()
+            .replicas_on(node_id)
+            .map(|(fu, replica)| (fu.id(), replica.role()))
+            .collect()
+    }
+
+    /// Returns the loads reported for a replica of the failover unit with the given role since the engine started,
+    /// oldest first, with the time of the refresh that applied them. At most [LoadForecastConfig::history_size] loads
+    /// are kept.
+    ///
+    /// [LoadForecastConfig::history_size]: forecast::LoadForecastConfig::history_size
+    pub fn load_history(
+        &self,
+        fu_id: Uuid,
+        metric_name: &str,
+        role: ReplicaRole,
+    ) -> Vec<(OffsetDateTime, u32)> {
+        self.cluster_snapshot
+            .borrow()
+            .load_history
+            .samples(fu_id, metric_name, role)
+    }
+
+    /// Returns the forecast load of a replica of the failover unit with the given role, see [forecast]. None if the
+    /// failover unit has not reported any load for the metric since the engine started.
+    pub fn forecast_load(&self, fu_id: Uuid, metric_name: &str, role: ReplicaRole) -> Option<u32> {
+        self.cluster_snapshot
+            .borrow()
+            .load_history
+            .forecast(fu_id, metric_name, role)
+    }
+
+    /// Returns the ids of the failover units of the service, in order
+    pub fn failover_units_of_service(&self, service_name: &str) -> Vec<Uuid> {
+        self.cluster_snapshot
+            .borrow()
+            .failover_units_of_service(service_name)
+            .map(|fu| fu.id())
+            .collect()
+    }
+
+    /// Returns the names of the services of the application, in order
+    pub fn services_of_application(&self, app_name: &str) -> Vec<String> {
+        self.cluster_snapshot
+            .borrow()
+            .services_of_application(app_name)
+            .map(|service| String::from(service.servcie_name()))
+            .collect()
+    }
+
+    /// Returns the names of the services of the service type, in order
+    pub fn services_of_service_type(&self, service_type_name: &str) -> Vec<String> {
+        self.cluster_snapshot
+            .borrow()
+            .services_of_service_type(service_type_name)
+            .map(|service| String::from(service.servcie_name()))
+            .collect()
+    }
+
+    /// Tells PLB that the upgrade domain is being upgraded for the given scope. Until the upgrade domain is completed,
+    /// primaries are swapped out of it, singleton partitions are relocated and no new replica is placed in it.
+    /// Starting the next upgrade domain of the same scope replaces the previous one.
+    pub fn start_upgrade_domain(&mut self, scope: UpgradeScope, upgrade_domain: &str) {
+        #[cfg(feature = "serde")]
+        self.record(|| TraceRecord::StartUpgradeDomain {
+            scope: scope.clone(),
+            upgrade_domain: DomainId::from(upgrade_domain),
+        });
+        let update_queue_clone = Arc::clone(&self.plb_update_queue);
+        update_queue_clone
+            .lock()
+            .unwrap()
+            .upgrade_update_queue
+            .push_back((scope, Some(DomainId::from(upgrade_domain))));
+    }
+
+    /// Tells PLB that the upgrade of the given scope is completed
+    pub fn complete_upgrade(&mut self, scope: UpgradeScope) {
+        #[cfg(feature = "serde")]
+        self.record(|| TraceRecord::CompleteUpgrade(scope.clone()));
+        let update_queue_clone = Arc::clone(&self.plb_update_queue);
+        update_queue_clone
+            .lock()
+            .unwrap()
+            .upgrade_update_queue
+            .push_back((scope, None));
+    }
+
+    /// Reports, for every failover unit affected by the upgrade of the scope, whether it lets the upgrade domain
+    /// proceed. Returns an error if no upgrade is in progress for the scope.
+    pub fn upgrade_readiness(&self, scope: &UpgradeScope) -> Result<UpgradeDomainReadiness> {
+        let snapshot = self.cluster_snapshot.borrow();
+        UpgradePlanner::new(&snapshot)
+            .readiness(scope)
+            .ok_or_else(|| anyhow!("No upgrade in progress for {:?}", scope))
+    }
+
     /// Refresh the PLB data structures from the pending update queues.
     /// It also triggers PLBSchedular to schedule any searcher stages if any stages are due at the current timestamp of the refresh (now)
-    pub fn refresh(&mut self, now: OffsetDateTime) -> Result<Vec<Solution>> {
+    /// Returns the report of the refresh, with the solutions generated by each phase.
+    pub fn refresh(&mut self, now: OffsetDateTime) -> Result<RefreshReport> {
+        let refresh_start = Instant::now();
         // Update PLB internal data structures to sync with the latest cluster information
-        {
+        let updates = {
             let update_queue_clone = Arc::clone(&self.plb_update_queue);
             let mut update_queue = update_queue_clone.lock().unwrap();
+            let updates = UpdateCounts {
+                nodes: update_queue.node_update_queue.len(),
+                applications: update_queue.app_update_queue.len(),
+                service_types: update_queue.service_type_update_queue.len(),
+                services: update_queue.service_update_queue.len(),
+                failover_units: update_queue.failover_unit_update_queue.len(),
+                loads: update_queue.load_update_queue.len(),
+                upgrades: update_queue.upgrade_update_queue.len(),
+                deletions: update_queue.deletion_queue.len(),
+            };
 
             // Copy over the updates to the PLB structure for snapshot
             self.process_node_updates(&mut update_queue.node_update_queue);
@@ -276,71 +861,262 @@ impl PlacementAndLoadBalancing {
             self.process_service_type_updates(&mut update_queue.service_type_update_queue);
             self.process_service_updates(&mut update_queue.service_update_queue);
             self.process_failover_unit_updates(&mut update_queue.failover_unit_update_queue);
-            self.process_load_updates(&mut update_queue.load_update_queue);
+            self.process_load_updates(&mut update_queue.load_update_queue, now);
+            self.process_upgrade_updates(&mut update_queue.upgrade_update_queue);
+            self.process_deletions(&mut update_queue.deletion_queue);
+            updates
+        };
+        if cfg!(any(debug_assertions, feature = "verify-indexes")) {
+            let verified = self.cluster_snapshot.borrow().verify_indexes();
+            if let Err(err) = verified {
+                self.emit(PlbEvent::IndexesOutOfSync {
+                    reason: err.to_string(),
+                });
+                self.cluster_snapshot.borrow_mut().rebuild_indexes();
+            }
         }
+        let violations = self.cluster_snapshot.borrow().constraint_violations();
+        let expired_movements = self
+            .pending
+            .expire(now, self.config.pending_movement_timeout);
 
         // Let scheduler decide what phases will be run in this refresh
         // TODO: this should be run on each service domain, but for simplicity we can pack everything into one service domain
-        let phases = self.scheduler.get_current_phases(now);
+        let events = self.event_sink.as_deref().unwrap_or(&NoopEventSink);
+        for expired in &expired_movements {
+            events.on_event(&PlbEvent::PendingMovementExpired(expired.clone()));
+        }
+        // the released follow-ups are validated again against the failover units reported by FM, a follow-up failing
+        // the validation is dropped with the rest of its sequence. The issued ones count as in flight for the phases
+        // below.
+        let mut follow_ups = vec![];
+        {
+            let released = self.pending.take_released();
+            let snapshot = self.cluster_snapshot.borrow();
+            let mut validator = PlanValidator::new(&snapshot).with_pending(&self.pending);
+            for (phase, movements) in released {
+                let Some(first) = movements.first() else {
+                    continue;
+                };
+                match validator.validate(first) {
+                    Ok(_) => follow_ups.extend(self.pending.issue_sequence(phase, movements, now)),
+                    Err(violation) => events.on_event(&PlbEvent::SolutionDropped {
+                        phase,
+                        rejected: RejectedMovement {
+                            index: 0,
+                            movement: first.clone(),
+                            violation,
+                        },
+                    }),
+                }
+            }
+        }
+        for follow_up in &follow_ups {
+            events.on_event(&PlbEvent::SolutionEmitted {
+                phase: follow_up.phase,
+                id: follow_up.id,
+                solution: follow_up.movement.to_solution(),
+            });
+        }
+        let phases = self.scheduler.get_current_phases(now, events);
+        let skipped_phases = Phase::ALL
+            .into_iter()
+            .filter(|phase| !phases.contains(phase))
+            .map(|phase| SkippedPhase {
+                phase,
+                reason: PhaseSkipReason::IntervalNotElapsed {
+                    next_run_time: self.scheduler.next_run_time(phase),
+                },
+            })
+            .collect();
 
-        let mut solutions = vec![];
-        // For each phase generated by the scheduler,
-        //  1. active PLB searcher to search for any actions
-        //  2. activate solver to generate any solutions
-        for phase in phases {
-            self.searcher = Searcher::new(&self.cluster_snapshot);
-            self.solver = Solver::new(&self.cluster_snapshot);
-            let actions = self.searcher.generate_actions(phase);
-            solutions = self.solver.generate_solutions(actions);
+        self.search_statistics.clear();
+        self.movements = follow_ups
+            .iter()
+            .map(|follow_up| follow_up.movement.clone())
+            .collect();
+        // The searches see the movements in flight as done, so that they are not proposed again
+        let mut originals = vec![];
+        if !phases.is_empty() {
+            let projection = self
+                .cluster_snapshot
+                .borrow_mut()
+                .apply_pending_movements(&self.pending);
+            // the follow-ups that no longer apply are never handed out
+            for id in projection.inapplicable_follow_ups {
+                let movements = self.pending.drop_follow_ups(id);
+                events.on_event(&PlbEvent::FollowUpsDropped { id, movements });
+            }
+            for id in projection.reflected {
+                self.pending.succeed(id);
+            }
+            // a movement that no longer applies did not happen, its follow-ups must not be carried out
+            for id in projection.inapplicable {
+                let movements = self.pending.drop_follow_ups(id);
+                self.pending.remove(id);
+                if !movements.is_empty() {
+                    events.on_event(&PlbEvent::FollowUpsDropped { id, movements });
+                }
+            }
+            originals = projection.originals;
         }
+        // For each phase generated by the scheduler, run the search strategy configured for the phase. The movements of
+        // all the phases are validated together, as FM carries them out together.
+        let snapshot = self.cluster_snapshot.borrow();
+        let mut validator = PlanValidator::new(&snapshot).with_pending(&self.pending);
+        let mut loads = LoadTable::new(&snapshot);
+        let mut phase_reports = vec![];
+        for phase in phases {
+            let config = &self.config;
+            let strategy = self
+                .strategies
+                .entry(phase)
+                .or_insert_with(|| create_strategy(config.strategy_for(phase), config));
+            events.on_event(&PlbEvent::PhaseStarted {
+                phase,
+                strategy: String::from(strategy.name()),
+            });
+            let score_before = ClusterScore::new(&loads, &config.metric_activity_thresholds);
+            let phase_start = Instant::now();
+            let mut outcome =
+                strategy.search(SnapshotView::new(&snapshot).with_event_sink(events), phase);
+            let duration = phase_start.elapsed();
+            let validation = validator.validate_plan(outcome.movements);
+            for movement in &validation.accepted {
+                loads.apply_movement(&snapshot, movement);
+            }
+            // the follow-ups wait for the movement before them to succeed
+            let mut deferred = BTreeMap::<usize, Vec<Movement>>::new();
+            outcome.movements = vec![];
+            for (index, movement) in validation.accepted.into_iter().enumerate() {
+                if validation.follow_ups.contains(&index) && !outcome.movements.is_empty() {
+                    deferred
+                        .entry(outcome.movements.len() - 1)
+                        .or_default()
+                        .push(movement);
+                } else {
+                    outcome.movements.push(movement);
+                }
+            }
+            let score_after = ClusterScore::new(&loads, &config.metric_activity_thresholds);
+            for rejected in &validation.rejected {
+                events.on_event(&PlbEvent::SolutionDropped {
+                    phase,
+                    rejected: rejected.clone(),
+                });
+            }
+            events.on_event(&PlbEvent::PhaseFinished {
+                phase,
+                strategy: String::from(strategy.name()),
+                duration,
+                movements: outcome.movements.len(),
+            });
+            if let Some(statistics) = &outcome.statistics {
+                if statistics.aborted_on_time_budget {
+                    events.on_event(&PlbEvent::SearchAbortedOnTimeBudget {
+                        phase,
+                        statistics: statistics.clone(),
+                    });
+                }
+            }
+            let solutions = outcome
+                .movements
+                .iter()
+                .map(Movement::to_solution)
+                .collect::<Vec<_>>();
+            let solution_ids = outcome
+                .movements
+                .iter()
+                .map(|movement| self.pending.issue(phase, movement.clone(), now))
+                .collect::<Vec<SolutionId>>();
+            for (index, movements) in &deferred {
+                self.pending.defer(solution_ids[*index], movements.clone());
+            }
+            for (id, solution) in solution_ids.iter().zip(&solutions) {
+                events.on_event(&PlbEvent::SolutionEmitted {
+                    phase,
+                    id: *id,
+                    solution: solution.clone(),
+                });
+            }
 
-        // TODO: give action generated by the solver back to FM (this will just be printing out the solution to the console for now)
-        println!("Solutions generated: {:?}", solutions);
+            self.movements.extend(outcome.movements.iter().cloned());
+            self.search_statistics.extend(outcome.statistics.clone());
+            phase_reports.push(PhaseReport {
+                phase,
+                strategy: String::from(strategy.name()),
+                duration,
+                statistics: outcome.statistics,
+                solutions,
+                solution_ids,
+                deferred: deferred.into_values().flatten().collect(),
+                movements: outcome.movements,
+                dropped: validation.rejected,
+                score_before,
+                score_after,
+            });
+        }
+        drop(validator);
+        drop(snapshot);
+        self.cluster_snapshot
+            .borrow_mut()
+            .restore_failover_units(originals);
+        let report = RefreshReport {
+            now,
+            updates,
+            violations,
+            phases: phase_reports,
+            skipped_phases,
+            expired_movements,
+            follow_ups,
+            duration: refresh_start.elapsed(),
+        };
+        self.metrics
+            .observe_refresh(&self.cluster_snapshot.borrow(), &report, &self.pending);
+        #[cfg(feature = "serde")]
+        self.record(|| TraceRecord::Refresh {
+            now,
+            solutions: report.solutions(),
+        });
 
-        Ok(solutions)
+        Ok(report)
     }
 
     fn process_node_updates(&mut self, node_updates: &mut VecDeque<Node>) {
-        while !node_updates.is_empty() {
-            let node_update = node_updates.pop_front().unwrap();
-            let node_id = node_update.node_id();
-            self.cluster_snapshot
-                .borrow_mut()
-                .nodes
-                .insert(node_id, node_update);
+        while let Some(node_update) = node_updates.pop_front() {
+            let key = node_update.node_id().to_string();
+            self.cluster_snapshot.borrow_mut().insert_node(node_update);
+            self.emit_applied(EntityKind::Node, key, false);
         }
     }
 
     fn process_app_updates(&mut self, app_updates: &mut VecDeque<Application>) {
-        while !app_updates.is_empty() {
-            let app_update = app_updates.pop_front().unwrap();
-            let app_name = app_update.app_name();
+        while let Some(app_update) = app_updates.pop_front() {
+            let key = String::from(app_update.app_name());
             self.cluster_snapshot
                 .borrow_mut()
-                .apps
-                .insert(String::from(app_name), app_update);
+                .insert_application(app_update);
+            self.emit_applied(EntityKind::Application, key, false);
         }
     }
 
     fn process_service_type_updates(&mut self, service_type_updates: &mut VecDeque<ServiceType>) {
-        while !service_type_updates.is_empty() {
-            let service_type_update = service_type_updates.pop_front().unwrap();
-            let service_type_name = service_type_update.service_type_name();
+        while let Some(service_type_update) = service_type_updates.pop_front() {
+            let key = String::from(service_type_update.service_type_name());
             self.cluster_snapshot
                 .borrow_mut()
-                .service_types
-                .insert(String::from(service_type_name), service_type_update);
+                .insert_service_type(service_type_update);
+            self.emit_applied(EntityKind::ServiceType, key, false);
         }
     }
 
     fn process_service_updates(&mut self, service_updates: &mut VecDeque<Service>) {
-        while !service_updates.is_empty() {
-            let service_update = service_updates.pop_front().unwrap();
-            let service_name = service_update.servcie_name();
+        while let Some(service_update) = service_updates.pop_front() {
+            let key = String::from(service_update.servcie_name());
             self.cluster_snapshot
                 .borrow_mut()
-                .services
-                .insert(String::from(service_name), service_update);
+                .insert_service(service_update);
+            self.emit_applied(EntityKind::Service, key, false);
         }
     }
 
@@ -348,27 +1124,110 @@ impl PlacementAndLoadBalancing {
         &mut self,
         failover_unit_updates: &mut VecDeque<FailoverUnit>,
     ) {
-        while !failover_unit_updates.is_empty() {
-            let failover_unit_update = failover_unit_updates.pop_front().unwrap();
-            let fu_id = failover_unit_update.id();
+        while let Some(failover_unit_update) = failover_unit_updates.pop_front() {
+            let key = failover_unit_update.id().to_string();
             self.cluster_snapshot
                 .borrow_mut()
-                .failover_units
-                .insert(fu_id, failover_unit_update);
+                .insert_failover_unit(failover_unit_update);
+            self.emit_applied(EntityKind::FailoverUnit, key, false);
         }
     }
 
-    fn process_load_updates(&mut self, load_updates: &mut VecDeque<LoadOrMoveCost>) {
-        while !load_updates.is_empty() {
-            let load_update = load_updates.pop_front().unwrap();
-            let fu_id = load_update.id();
-            self.cluster_snapshot
-                .borrow_mut()
-                .loads
-                .insert(fu_id, load_update);
+    fn process_load_updates(
+        &mut self,
+        load_updates: &mut VecDeque<LoadOrMoveCost>,
+        now: OffsetDateTime,
+    ) {
+        while let Some(mut load_update) = load_updates.pop_front() {
+            let key = load_update.id().to_string();
+            let mut snapshot = self.cluster_snapshot.borrow_mut();
+            let events =
+                snapshot.check_load_report(&mut load_update, self.config.load_spike_factor);
+            snapshot.insert_load(load_update, now, &self.config.load_forecast);
+            drop(snapshot);
+            for event in events {
+                self.emit(event);
+            }
+            self.emit_applied(EntityKind::Load, key, false);
+        }
+    }
+
+    fn process_upgrade_updates(
+        &mut self,
+        upgrade_updates: &mut VecDeque<(UpgradeScope, Option<DomainId>)>,
+    ) {
+        while let Some((scope, upgrade_domain)) = upgrade_updates.pop_front() {
+            let key = format!("{:?}", scope);
+            let mut snapshot = self.cluster_snapshot.borrow_mut();
+            let applied = match upgrade_domain {
+                Some(upgrade_domain) => {
+                    snapshot.upgrades.insert(scope, upgrade_domain);
+                    true
+                }
+                None => snapshot.upgrades.remove(&scope).is_some(),
+            };
+            drop(snapshot);
+            if applied {
+                self.emit_applied(EntityKind::Upgrade, key, false);
+            } else {
+                self.emit_rejected(
+                    EntityKind::Upgrade,
+                    key,
+                    "No upgrade in progress for the scope",
+                );
+            }
+        }
+    }
+
+    fn process_deletions(&mut self, deletions: &mut VecDeque<Deletion>) {
+        while let Some(deletion) = deletions.pop_front() {
+            let mut snapshot = self.cluster_snapshot.borrow_mut();
+            let (kind, key, removed) = match deletion {
+                Deletion::Node(node_id) => (
+                    EntityKind::Node,
+                    node_id.to_string(),
+                    snapshot.remove_node(node_id).is_some(),
+                ),
+                Deletion::Application(app_name) => {
+                    let removed = snapshot.remove_application(&app_name).is_some();
+                    (EntityKind::Application, app_name, removed)
+                }
+                Deletion::ServiceType(service_type_name) => {
+                    let removed = snapshot.remove_service_type(&service_type_name).is_some();
+                    (EntityKind::ServiceType, service_type_name, removed)
+                }
+                Deletion::Service(service_name) => {
+                    let removed = snapshot.remove_service(&service_name).is_some();
+                    (EntityKind::Service, service_name, removed)
+                }
+                Deletion::FailoverUnit(fu_id) => (
+                    EntityKind::FailoverUnit,
+                    fu_id.to_string(),
+                    snapshot.remove_failover_unit(fu_id).is_some(),
+                ),
+            };
+            drop(snapshot);
+            if removed {
+                self.emit_applied(kind, key, true);
+            } else {
+                // the entity was already removed by an earlier deletion of the queue
+                self.emit_rejected(kind, key, "Not in the cluster snapshot");
+            }
         }
     }
 
+    fn emit_applied(&self, kind: EntityKind, key: String, deleted: bool) {
+        self.emit(PlbEvent::UpdateApplied { kind, key, deleted });
+    }
+
+    fn emit_rejected(&self, kind: EntityKind, key: String, reason: &str) {
+        self.emit(PlbEvent::UpdateRejected {
+            kind,
+            key,
+            reason: String::from(reason),
+        });
+    }
+
     /// Given a failover unit and 2 candicate secondary replicas, return the comparision result for promoting to primary
     /// A negative return value means Node 1 is preferred; a positive return value means Node 2 is preferred; 0 return value means
     /// 2 candidate nodes are equally preferred.
@@ -382,47 +1241,175 @@ impl PlacementAndLoadBalancing {
         node1: NodeId,
         node2: NodeId,
     ) -> i32 {
-        match node1.cmp(node2) {
+        match promotion::compare_node_for_promotion(node1, node2) {
             Ordering::Less => -1,
             Ordering::Equal => 0,
             Ordering::Greater => 1,
         }
     }
+
+    /// Finds the placement of the current cluster snapshot with the lowest cluster score, respecting every hard
+    /// constraint. This is an exhaustive search meant to validate the heuristics on small clusters, it returns an
+    /// error if the cluster exceeds the limits.
+    pub fn solve_exact(&self, limits: &ExactSolverLimits) -> Result<ExactSolution> {
+        ExactSolver::new(&self.cluster_snapshot.borrow(), limits).solve()
+    }
+
+    /// Compares the cluster score reached by the movements of a heuristic with the optimal score of the current
+    /// cluster snapshot
+    pub fn optimality_gap(
+        &self,
+        movements: &[Movement],
+        limits: &ExactSolverLimits,
+    ) -> Result<OptimalityGap> {
+        ExactSolver::new(&self.cluster_snapshot.borrow(), limits).optimality_gap(movements)
+    }
+
+    /// Runs the search strategy for the phase against the current cluster snapshot, without generating any solution,
+    /// and compares the cluster score it reaches with the optimal score
+    pub fn evaluate_strategy(
+        &self,
+        strategy: &mut dyn SearchStrategy,
+        phase: Phase,
+        limits: &ExactSolverLimits,
+    ) -> Result<OptimalityGap> {
+        let snapshot = self.cluster_snapshot.borrow();
+        let outcome = strategy.search(SnapshotView::new(&snapshot), phase);
+        ExactSolver::new(&snapshot, limits).optimality_gap(&outcome.movements)
+    }
+
+    /// Plans the movements required by the deactivation intent of the node against the current cluster snapshot:
+    /// primaries are swapped out for Restart, and the node is fully drained for RemoveData and RemoveNode.
+    /// Pending updates are not applied until the next refresh.
+    pub fn plan_node_drain(&self, node_id: NodeId) -> Result<DrainPlan> {
+        let snapshot = self.cluster_snapshot.borrow();
+        if !snapshot.nodes.contains_key(&node_id) {
+            return Err(anyhow!("Node {:?} is not in the cluster snapshot", node_id));
+        }
+
+        Ok(DrainPlanner::new(&snapshot).plan(node_id))
+    }
+
+    /// Validates a plan against the current cluster snapshot: the movements are applied in order to a scratch copy of
+    /// the snapshot and checked against every hard constraint and the quorum of the failover units, the movements in
+    /// flight counting as in motion. The rejected movements are left out, see [PlanValidation::into_result] to turn any
+    /// rejection into an error. Pending updates are not applied until the next refresh.
+    pub fn validate_plan(&self, movements: &[Movement]) -> PlanValidation {
+        PlanValidator::new(&self.cluster_snapshot.borrow())
+            .with_pending(&self.pending)
+            .validate_plan(movements.to_vec())
+    }
+
+    /// Scores how balanced the load of the current cluster snapshot is, see [score]. Pending updates are not applied
+    /// until the next refresh, and the movements in flight are not considered done.
+    pub fn cluster_score(&self) -> ClusterScore {
+        ClusterScore::new(
+            &LoadTable::new(&self.cluster_snapshot.borrow()),
+            &self.config.metric_activity_thresholds,
+        )
+    }
+
+    /// Simulates what PLB would do after the hypothetical changes, see [whatif]. The simulation runs on a copy of the
+    /// current cluster snapshot with the movements in flight done, and leaves the live state untouched. Pending updates
+    /// are not applied until the next refresh. Returns an error if a change refers to an entity that is not in the
+    /// cluster snapshot.
+    pub fn what_if(&self, changes: &[TopologyChange]) -> Result<WhatIfReport> {
+        Ok(WhatIfSimulator::new(
+            &self.cluster_snapshot.borrow(),
+            &self.pending,
+            &self.config,
+            changes,
+        )?
+        .run())
+    }
+
+    /// Reports the free capacity of the current cluster snapshot per node, fault domain, node type and metric, see
+    /// [capacity], and how many nodes of the given shape to add for the replicas the placement cannot place. The node
+    /// id of the shape is ignored. Pending updates are not applied until the next refresh.
+    pub fn capacity_report(&self, node_shape: &NodeDescription) -> Result<CapacityReport> {
+        let snapshot = self.cluster_snapshot.borrow();
+        let mut report = CapacityReport::new(&snapshot);
+        (report.unplaceable_replicas, report.additional_nodes) =
+            capacity::plan_additional_nodes(&snapshot, &self.pending, &self.config, node_shape)?;
+
+        Ok(report)
+    }
+
+    /// Explains why a new replica of the failover unit can or cannot be placed on each node of the current cluster
+    /// snapshot, with every hard constraint the node fails. Pending updates are not applied until the next refresh.
+    pub fn explain_placement(&self, fu_id: Uuid) -> Result<PlacementExplanation> {
+        let snapshot = self.cluster_snapshot.borrow();
+        let fu = snapshot
+            .failover_units
+            .get(&fu_id)
+            .ok_or_else(|| anyhow!("Failover unit {} is not in the cluster snapshot", fu_id))?;
+
+        Ok(snapshot.explain_placement(fu))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::vec_deque;
-    use std::collections::HashMap;
-
-    use crate::scheduler::Phase;
+    use crate::annealing::AnnealingConfig;
+    use crate::application::application_capacities_description::ApplicationCapacitiesDescription;
+    use crate::capacity::MetricCapacity;
+    use crate::config::SearchStrategyKind;
+    use crate::constraint::{ConstraintViolation, NodeRejectReason};
+    use crate::events::InMemoryEventSink;
+    use crate::exact::ExactSolverLimits;
+    use crate::forecast::{ForecastMethod, LoadForecastConfig};
+    use crate::generator::ClusterSpec;
+    use crate::node::node_description::NodeDeactivationIntent;
+    use crate::node::node_instance::NodeInstance;
+    use crate::scheduler::MIN_BALANCING_INTERVAL;
     use crate::scheduler::MIN_PLACEMENT_INTERVAL;
+    use crate::service::service_metric::ServiceMetric;
+    use crate::service::service_package::{
+        ServicePackageDescription, CPU_CORES_METRIC, MEMORY_IN_MB_METRIC,
+    };
+    use crate::solver::Solution;
+    use crate::strategy::GreedyStrategy;
+    use crate::upgrade::UpgradeReadiness;
+    use crate::validation::PlanViolation;
+
+    use std::collections::HashSet;
 
     use self::failoverunit::failover_unit::Replica;
 
     use super::*;
 
     fn create_empty_plb() -> PlacementAndLoadBalancing {
-        PlacementAndLoadBalancing::new(vec![], vec![], vec![], vec![], vec![], vec![])
+        PlacementAndLoadBalancing::new(vec![], vec![], vec![], vec![], vec![], vec![]).unwrap()
     }
 
     fn create_node_desc(node_id: u128) -> NodeDescription {
-        let mut node_desc = NodeDescription::default();
-        node_desc.node_instance.id = NodeId { id_value: node_id };
-        node_desc
+        NodeDescription::new(
+            NodeInstance::new(NodeId::new(node_id), 0),
+            true,
+            HashMap::new(),
+            HashMap::new(),
+        )
+    }
+
+    fn create_node_desc_with_capacity(
+        node_id: u128,
+        metric_name: &str,
+        capacity: u32,
+    ) -> NodeDescription {
+        NodeDescription::new(
+            NodeInstance::new(NodeId::new(node_id), 0),
+            true,
+            HashMap::new(),
+            HashMap::from([(String::from(metric_name), capacity)]),
+        )
     }
 
     fn create_service_type_desc(service_type_name: &str) -> ServiceTypeDescription {
-        let mut service_type_desc = ServiceTypeDescription::default();
-        service_type_desc.name = String::from(service_type_name);
-        service_type_desc
+        ServiceTypeDescription::new(service_type_name, HashSet::new())
     }
 
     fn create_service_desc(service_type_name: &str, service_name: &str) -> ServiceDescription {
-        let mut service_desc = ServiceDescription::default();
-        service_desc.service_type_name = String::from(service_type_name);
-        service_desc.service_name = String::from(service_name);
-        service_desc
+        ServiceDescription::new(service_type_name, service_name)
     }
 
     fn create_fu_desc(
@@ -431,12 +1418,31 @@ mod tests {
         replicas: HashMap<Uuid, Replica>,
         replica_diff: i32,
     ) -> FailoverUnitDescription {
-        let mut fu_desc = FailoverUnitDescription::default();
-        fu_desc.id = fu_id;
-        fu_desc.service_name = String::from(service_name);
-        fu_desc.replicas = replicas;
-        fu_desc.replica_diff = replica_diff;
-        fu_desc
+        FailoverUnitDescription::new(fu_id, service_name, replicas, replica_diff)
+    }
+
+    /// Creates the replica set of a failover unit from a list of (role, node id) pairs
+    fn create_replicas(fu_id: Uuid, replicas: &[(ReplicaRole, u128)]) -> HashMap<Uuid, Replica> {
+        replicas
+            .iter()
+            .enumerate()
+            .map(|(replica_id, (role, node_id))| {
+                (
+                    Uuid::from_u128(replica_id as u128),
+                    Replica::new(replica_id as u128, fu_id, *role, NodeId::new(*node_id)),
+                )
+            })
+            .collect()
+    }
+
+    /// The movements moving the primary of a singleton failover unit make-before-break
+    fn make_before_break(fu_id: Uuid, from: u128, to: u128) -> Vec<Movement> {
+        let (from, to) = (NodeId::new(from), NodeId::new(to));
+        vec![
+            Movement::AddReplica { fu_id, node: to },
+            Movement::SwapPrimary { fu_id, from, to },
+            Movement::DropReplica { fu_id, node: from },
+        ]
     }
 
     #[test]
@@ -450,7 +1456,8 @@ mod tests {
         plb.update_node(create_node_desc(2));
 
         plb.update_service_type(create_service_type_desc("Worker.ISO"));
-        plb.update_service(create_service_desc("Worker.ISO", "LogicalServier"));
+        plb.update_service(create_service_desc("Worker.ISO", "LogicalServier"))
+            .unwrap();
         plb.update_failover_unit(create_fu_desc(
             Uuid::from_u128(1),
             "LogicalServer",
@@ -462,7 +1469,10 @@ mod tests {
         plb.scheduler
             .set_last_phase_time(initial_time, Phase::Placement);
 
-        let solutions = plb.refresh(initial_time + MIN_PLACEMENT_INTERVAL).unwrap();
+        let solutions = plb
+            .refresh(initial_time + MIN_PLACEMENT_INTERVAL)
+            .unwrap()
+            .solutions();
 
         assert_eq!(1, solutions.len());
         assert_eq!(
@@ -474,4 +1484,2605 @@ mod tests {
             solutions[0]
         );
     }
+
+    #[test]
+    fn test_placement_skips_deactivating_nodes() {
+        let mut plb = create_empty_plb();
+
+        plb.update_node(create_node_desc(0));
+        plb.update_node(create_node_desc(1));
+        plb.update_node(
+            create_node_desc(2).with_deactivation_intent(NodeDeactivationIntent::Pause),
+        );
+
+        plb.update_service_type(create_service_type_desc("Worker.ISO"));
+        plb.update_service(create_service_desc("Worker.ISO", "LogicalServer"))
+            .unwrap();
+        plb.update_failover_unit(create_fu_desc(
+            Uuid::from_u128(1),
+            "LogicalServer",
+            HashMap::new(),
+            1,
+        ));
+
+        let initial_time = OffsetDateTime::now_utc();
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::Placement);
+
+        let solutions = plb
+            .refresh(initial_time + MIN_PLACEMENT_INTERVAL)
+            .unwrap()
+            .solutions();
+
+        assert_eq!(
+            vec![Movement::AddReplica {
+                fu_id: Uuid::from_u128(1),
+                node: NodeId::new(1)
+            }
+            .to_solution()],
+            solutions
+        );
+    }
+
+    #[test]
+    fn test_restart_swaps_primaries_out() {
+        let fu_id = Uuid::from_u128(1);
+        let plb = PlacementAndLoadBalancing::new(
+            vec![
+                create_node_desc(0).with_deactivation_intent(NodeDeactivationIntent::Restart),
+                create_node_desc(1),
+                create_node_desc(2),
+            ],
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![create_service_desc("Worker.ISO", "LogicalServer")],
+            vec![create_fu_desc(
+                fu_id,
+                "LogicalServer",
+                create_replicas(
+                    fu_id,
+                    &[
+                        (ReplicaRole::Primary, 0),
+                        (ReplicaRole::Secondary, 1),
+                        (ReplicaRole::Secondary, 2),
+                    ],
+                ),
+                0,
+            )],
+            vec![],
+        )
+        .unwrap();
+
+        let plan = plb.plan_node_drain(NodeId::new(0)).unwrap();
+
+        assert!(plan.is_complete());
+        assert_eq!(
+            vec![Movement::SwapPrimary {
+                fu_id,
+                from: NodeId::new(0),
+                to: NodeId::new(1)
+            }],
+            plan.movements
+        );
+    }
+
+    #[test]
+    fn test_remove_node_drain_respects_capacity() {
+        // Node 0 is removed and hosts a secondary of partition 1 and the primary of partition 2.
+        // Node 1 has room for only one of them, node 2 already hosts a replica of both partitions.
+        let fu1 = Uuid::from_u128(1);
+        let fu2 = Uuid::from_u128(2);
+        let plb = PlacementAndLoadBalancing::new(
+            vec![
+                create_node_desc_with_capacity(0, "CPU", 100)
+                    .with_deactivation_intent(NodeDeactivationIntent::RemoveNode),
+                create_node_desc_with_capacity(1, "CPU", 10),
+                create_node_desc_with_capacity(2, "CPU", 100),
+                create_node_desc_with_capacity(3, "CPU", 100),
+            ],
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![create_service_desc("Worker.ISO", "LogicalServer")
+                .with_metric(ServiceMetric::new("CPU", 1.0, 8, 6))],
+            vec![
+                create_fu_desc(
+                    fu1,
+                    "LogicalServer",
+                    create_replicas(
+                        fu1,
+                        &[(ReplicaRole::Primary, 2), (ReplicaRole::Secondary, 0)],
+                    ),
+                    0,
+                ),
+                create_fu_desc(
+                    fu2,
+                    "LogicalServer",
+                    create_replicas(
+                        fu2,
+                        &[(ReplicaRole::Primary, 0), (ReplicaRole::Secondary, 2)],
+                    ),
+                    0,
+                ),
+            ],
+            vec![],
+        )
+        .unwrap();
+
+        let plan = plb.plan_node_drain(NodeId::new(0)).unwrap();
+
+        assert!(plan.is_complete());
+        assert_eq!(
+            vec![
+                Movement::MoveReplica {
+                    fu_id: fu1,
+                    role: ReplicaRole::Secondary,
+                    from: NodeId::new(0),
+                    to: NodeId::new(1)
+                },
+                Movement::MoveReplica {
+                    fu_id: fu2,
+                    role: ReplicaRole::Primary,
+                    from: NodeId::new(0),
+                    to: NodeId::new(3)
+                },
+            ],
+            plan.movements
+        );
+    }
+
+    #[test]
+    fn test_upgrade_domain_walk() {
+        // Partition 1 has its primary in UD0, partition 2 is a singleton in UD0 and partition 3 needs a new
+        // replica while the only node without one of its replicas is in UD0
+        let fu1 = Uuid::from_u128(1);
+        let fu2 = Uuid::from_u128(2);
+        let fu3 = Uuid::from_u128(3);
+        let mut plb = PlacementAndLoadBalancing::new(
+            vec![
+                create_node_desc(0).with_upgrade_domain("UD0"),
+                create_node_desc(1).with_upgrade_domain("UD1"),
+                create_node_desc(2).with_upgrade_domain("UD2"),
+            ],
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![create_service_desc("Worker.ISO", "LogicalServer")],
+            vec![
+                create_fu_desc(
+                    fu1,
+                    "LogicalServer",
+                    create_replicas(
+                        fu1,
+                        &[
+                            (ReplicaRole::Primary, 0),
+                            (ReplicaRole::Secondary, 1),
+                            (ReplicaRole::Secondary, 2),
+                        ],
+                    ),
+                    0,
+                ),
+                create_fu_desc(
+                    fu2,
+                    "LogicalServer",
+                    create_replicas(fu2, &[(ReplicaRole::Primary, 0)]),
+                    0,
+                ),
+                create_fu_desc(
+                    fu3,
+                    "LogicalServer",
+                    create_replicas(
+                        fu3,
+                        &[(ReplicaRole::Primary, 1), (ReplicaRole::Secondary, 2)],
+                    ),
+                    1,
+                ),
+            ],
+            vec![],
+        )
+        .unwrap();
+
+        plb.start_upgrade_domain(UpgradeScope::Cluster, "UD0");
+
+        let initial_time = OffsetDateTime::now_utc();
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::Placement);
+        let report = plb.refresh(initial_time + MIN_PLACEMENT_INTERVAL).unwrap();
+
+        // singleton partition 2 moves make-before-break to node 2, which hosts fewer primaries than node 1 once
+        // partition 1 swapped its primary. The swap and the drop of partition 2 wait for the replica to be added.
+        let relocation = make_before_break(fu2, 0, 2);
+        let expected = [
+            Movement::SwapPrimary {
+                fu_id: fu1,
+                from: NodeId::new(0),
+                to: NodeId::new(1),
+            },
+            relocation[0].clone(),
+        ];
+        assert_eq!(
+            expected
+                .iter()
+                .map(Movement::to_solution)
+                .collect::<Vec<Solution>>(),
+            report.solutions()
+        );
+        assert_eq!(&relocation[1..], report.phases[0].deferred);
+
+        let readiness = plb.upgrade_readiness(&UpgradeScope::Cluster).unwrap();
+        assert!(!readiness.is_ready());
+        assert_eq!(
+            BTreeMap::from([
+                (fu1, UpgradeReadiness::PrimarySwapPending),
+                (fu2, UpgradeReadiness::RelocationPending),
+            ]),
+            readiness.failover_units
+        );
+
+        // each step of the relocation is handed out once FM acknowledged the previous one as succeeded and reported the
+        // failover unit with it done
+        let mut id = report.solution_ids()[1];
+        let report_done = |plb: &mut PlacementAndLoadBalancing, movement: &Movement| {
+            let mut fu_desc = plb.failover_unit_description(fu2).unwrap();
+            fu_desc.apply_movement(movement).unwrap();
+            plb.update_failover_unit(fu_desc);
+        };
+        for (index, movement) in relocation.iter().enumerate().skip(1) {
+            let report = plb.refresh(initial_time + MIN_PLACEMENT_INTERVAL).unwrap();
+            assert!(report.solutions().is_empty());
+            plb.acknowledge_solution(id, MovementOutcome::Succeeded)
+                .unwrap();
+            report_done(&mut plb, &relocation[index - 1]);
+            let report = plb.refresh(initial_time + MIN_PLACEMENT_INTERVAL).unwrap();
+            assert_eq!(vec![movement.to_solution()], report.solutions());
+            assert_eq!(vec![movement.clone()], plb.last_movements());
+            id = report.solution_ids()[0];
+        }
+        // the drop is the last step of the sequence
+        plb.acknowledge_solution(id, MovementOutcome::Succeeded)
+            .unwrap();
+        report_done(&mut plb, &relocation[relocation.len() - 1]);
+        assert!(plb
+            .refresh(initial_time + MIN_PLACEMENT_INTERVAL)
+            .unwrap()
+            .solutions()
+            .is_empty());
+
+        plb.complete_upgrade(UpgradeScope::Cluster);
+        plb.refresh(initial_time + MIN_PLACEMENT_INTERVAL).unwrap();
+        assert!(plb.upgrade_readiness(&UpgradeScope::Cluster).is_err());
+    }
+
+    #[test]
+    fn test_overlapping_upgrades() {
+        // an application upgrade of UD1 runs during the cluster upgrade of UD0: partition 1 has its primary in UD1 and
+        // partition 2 needs a new replica while the only nodes without one of its replicas are in UD0 and UD1
+        let fu1 = Uuid::from_u128(1);
+        let fu2 = Uuid::from_u128(2);
+        let mut plb = PlacementAndLoadBalancing::new(
+            vec![
+                create_node_desc(0).with_upgrade_domain("UD0"),
+                create_node_desc(1).with_upgrade_domain("UD1"),
+                create_node_desc(2).with_upgrade_domain("UD2"),
+            ],
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![create_service_desc("Worker.ISO", "LogicalServer").with_application_name("App")],
+            vec![
+                create_fu_desc(
+                    fu1,
+                    "LogicalServer",
+                    create_replicas(
+                        fu1,
+                        &[
+                            (ReplicaRole::Secondary, 0),
+                            (ReplicaRole::Primary, 1),
+                            (ReplicaRole::Secondary, 2),
+                        ],
+                    ),
+                    0,
+                ),
+                create_fu_desc(
+                    fu2,
+                    "LogicalServer",
+                    create_replicas(fu2, &[(ReplicaRole::Primary, 2)]),
+                    1,
+                ),
+            ],
+            vec![],
+        )
+        .unwrap();
+        let app_scope = UpgradeScope::Application(String::from("App"));
+        plb.start_upgrade_domain(UpgradeScope::Cluster, "UD0");
+        plb.start_upgrade_domain(app_scope.clone(), "UD1");
+
+        let initial_time = OffsetDateTime::now_utc();
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::Placement);
+        let solutions = plb
+            .refresh(initial_time + MIN_PLACEMENT_INTERVAL)
+            .unwrap()
+            .solutions();
+
+        // the primary leaves UD1 for the secondary outside of both upgrading domains, and no replica is placed in them
+        assert_eq!(
+            vec![Movement::SwapPrimary {
+                fu_id: fu1,
+                from: NodeId::new(1),
+                to: NodeId::new(2),
+            }
+            .to_solution()],
+            solutions
+        );
+        assert_eq!(
+            BTreeMap::from([(fu1, UpgradeReadiness::PrimarySwapPending)]),
+            plb.upgrade_readiness(&app_scope).unwrap().failover_units
+        );
+        // partition 1 also has a replica in UD0, the cluster upgrade waits for its swap too
+        assert_eq!(
+            BTreeMap::from([(fu1, UpgradeReadiness::PrimarySwapPending)]),
+            plb.upgrade_readiness(&UpgradeScope::Cluster)
+                .unwrap()
+                .failover_units
+        );
+    }
+
+    fn create_unbalanced_plb() -> PlacementAndLoadBalancing {
+        // 6 singleton partitions with a CPU load of 10 are all placed on node 0
+        let fu_descs = (1..=6)
+            .map(|fu_index| {
+                let fu_id = Uuid::from_u128(fu_index);
+                create_fu_desc(
+                    fu_id,
+                    "LogicalServer",
+                    create_replicas(fu_id, &[(ReplicaRole::Primary, 0)]),
+                    0,
+                )
+            })
+            .collect();
+        PlacementAndLoadBalancing::new(
+            vec![
+                create_node_desc_with_capacity(0, "CPU", 100),
+                create_node_desc_with_capacity(1, "CPU", 100),
+                create_node_desc_with_capacity(2, "CPU", 100),
+            ],
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![create_service_desc("Worker.ISO", "LogicalServer")
+                .with_metric(ServiceMetric::new("CPU", 1.0, 10, 10))],
+            fu_descs,
+            vec![],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_cluster_score() {
+        let initial_time = OffsetDateTime::now_utc();
+        let mut plb = create_unbalanced_plb();
+        // the CPU metric only
+        plb.set_config(PLBConfig {
+            balancing_strategy: SearchStrategyKind::Greedy,
+            default_metrics: vec![],
+            ..Default::default()
+        });
+
+        // node loads of 0.6, 0 and 0
+        let score = plb.cluster_score();
+        let cpu = &score.metrics["CPU"];
+        assert_eq!(1.0, cpu.weight);
+        assert!((cpu.std_dev - 0.08_f64.sqrt()).abs() < 1e-9);
+        assert!((cpu.max_avg_ratio - 3.0).abs() < 1e-9);
+        assert!((cpu.active_node_fraction - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(cpu.std_dev, score.std_dev);
+        assert_eq!(cpu.max_avg_ratio, score.max_avg_ratio);
+        assert_eq!(cpu.active_node_fraction, score.active_node_fraction);
+        assert!(score.search_score > 0.0);
+
+        plb.set_config(PLBConfig {
+            balancing_strategy: SearchStrategyKind::Greedy,
+            metric_activity_thresholds: BTreeMap::from([(String::from("CPU"), 60)]),
+            default_metrics: vec![],
+            ..Default::default()
+        });
+        let score = plb.cluster_score();
+        assert_eq!(0.0, score.active_node_fraction);
+
+        // the balancing round reports the score before and after its movements
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::LoadBalancing);
+        let report = plb.refresh(initial_time + MIN_BALANCING_INTERVAL).unwrap();
+        let balancing = report.phase(Phase::LoadBalancing).unwrap();
+        assert_eq!(score.metrics, balancing.score_before.metrics);
+        assert!(balancing.score_after.std_dev < 1e-6);
+        assert!((balancing.score_after.max_avg_ratio - 1.0).abs() < 1e-6);
+        assert!(balancing.score_after.search_score < balancing.score_before.search_score);
+        // the movements are in flight until FM reports them done
+        assert_eq!(score.metrics, plb.cluster_score().metrics);
+    }
+
+    #[test]
+    fn test_what_if() {
+        let mut plb = create_unbalanced_plb();
+        plb.set_config(PLBConfig {
+            placement_strategy: SearchStrategyKind::Greedy,
+            balancing_strategy: SearchStrategyKind::Greedy,
+            constraint_check_strategy: SearchStrategyKind::Greedy,
+            default_metrics: vec![],
+            ..Default::default()
+        });
+        let node_loads = plb.node_loads();
+
+        // decommissioning node 0 moves its 6 singleton replicas make-before-break to the 2 other nodes
+        let report = plb
+            .what_if(&[TopologyChange::RemoveNode(NodeId::new(0))])
+            .unwrap();
+        assert!(report.converged);
+        assert_eq!(18, report.movements.len());
+        assert!(report
+            .movements
+            .iter()
+            .all(|simulated| simulated.phase == Phase::ConstraintCheck));
+        assert_eq!(
+            6,
+            report
+                .movements
+                .iter()
+                .filter(|simulated| matches!(
+                    simulated.movement,
+                    Movement::DropReplica { node, .. } if node == NodeId::new(0)
+                ))
+                .count()
+        );
+        assert_eq!(0, report.unplaceable_replica_count());
+        assert!(report.violations.is_empty());
+        assert_eq!(plb.cluster_score(), report.score_before);
+        // 30 on each of the 2 remaining nodes
+        assert!(report.score_after.std_dev < 1e-6);
+        assert!(report.score_after.std_dev < report.score_before.std_dev);
+
+        // with less capacity left, 2 replicas stay on the removed node
+        let report = plb
+            .what_if(&[
+                TopologyChange::RemoveNode(NodeId::new(0)),
+                TopologyChange::SetNodeCapacity {
+                    node_id: NodeId::new(1),
+                    metric_name: String::from("CPU"),
+                    capacity: 20,
+                },
+                TopologyChange::SetNodeCapacity {
+                    node_id: NodeId::new(2),
+                    metric_name: String::from("CPU"),
+                    capacity: 20,
+                },
+            ])
+            .unwrap();
+        assert_eq!(12, report.movements.len());
+        assert_eq!(2, report.unplaceable_replica_count());
+
+        // 3 replicas per partition fit on 3 nodes, the placement adds one replica per partition and round
+        let report = plb
+            .what_if(&[TopologyChange::SetReplicaCount {
+                service_name: String::from("LogicalServer"),
+                replica_count: 3,
+            }])
+            .unwrap();
+        assert!(report.converged);
+        assert_eq!(
+            12,
+            report
+                .movements
+                .iter()
+                .filter(|simulated| matches!(simulated.movement, Movement::AddReplica { .. }))
+                .count()
+        );
+        assert_eq!(0, report.unplaceable_replica_count());
+        assert!(report.score_after.std_dev < 1e-6);
+
+        // a 4th node takes its share of the load
+        let report = plb
+            .what_if(&[TopologyChange::AddNode(create_node_desc_with_capacity(
+                3, "CPU", 100,
+            ))])
+            .unwrap();
+        assert!(report
+            .movements
+            .iter()
+            .any(|simulated| simulated.phase == Phase::LoadBalancing));
+        assert!(report.score_after.max_avg_ratio < report.score_before.max_avg_ratio);
+
+        assert!(plb
+            .what_if(&[TopologyChange::RemoveNode(NodeId::new(9))])
+            .is_err());
+        assert!(plb
+            .what_if(&[TopologyChange::AddNode(create_node_desc_with_capacity(
+                0, "CPU", 100
+            ))])
+            .is_err());
+        assert!(plb
+            .what_if(&[TopologyChange::SetReplicaCount {
+                service_name: String::from("Unknown"),
+                replica_count: 3,
+            }])
+            .is_err());
+
+        // the live state is untouched
+        assert_eq!(node_loads, plb.node_loads());
+        assert!(plb.last_movements().is_empty());
+        assert!(plb.pending_movements().is_empty());
+    }
+
+    #[test]
+    fn test_capacity_report() {
+        let fu_descs = vec![
+            create_fu_desc(
+                Uuid::from_u128(1),
+                "LogicalServer",
+                create_replicas(Uuid::from_u128(1), &[(ReplicaRole::Primary, 0)]),
+                0,
+            ),
+            create_fu_desc(
+                Uuid::from_u128(2),
+                "LogicalServer",
+                create_replicas(Uuid::from_u128(2), &[(ReplicaRole::Primary, 1)]),
+                0,
+            ),
+            // 2 replicas of 70 do not fit anywhere
+            create_fu_desc(Uuid::from_u128(3), "Big", HashMap::new(), 2),
+        ];
+        let mut plb = PlacementAndLoadBalancing::new(
+            vec![
+                create_node_desc_with_capacity(0, "CPU", 100)
+                    .with_property("NodeType", "FrontEnd")
+                    .with_fault_domain("fd0"),
+                create_node_desc_with_capacity(1, "CPU", 100)
+                    .with_property("NodeType", "FrontEnd")
+                    .with_fault_domain("fd1"),
+                create_node_desc_with_capacity(2, "CPU", 50)
+                    .with_property("NodeType", "BackEnd")
+                    .with_fault_domain("fd1"),
+                NodeDescription::new(
+                    NodeInstance::new(NodeId::new(3), 0),
+                    false,
+                    HashMap::new(),
+                    HashMap::from([(String::from("CPU"), 100)]),
+                ),
+            ],
+            vec![ApplicationDescription::new("App")
+                .with_capacity(ApplicationCapacitiesDescription::new("CPU", 0, 0, 50))
+                .with_minimum_nodes(2)],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![
+                create_service_desc("Worker.ISO", "LogicalServer")
+                    .with_application_name("App")
+                    .with_metric(ServiceMetric::new("CPU", 1.0, 40, 40)),
+                create_service_desc("Worker.ISO", "Big")
+                    .with_metric(ServiceMetric::new("CPU", 1.0, 70, 70)),
+            ],
+            fu_descs,
+            vec![],
+        )
+        .unwrap();
+        plb.set_config(PLBConfig {
+            placement_strategy: SearchStrategyKind::Greedy,
+            balancing_strategy: SearchStrategyKind::Greedy,
+            constraint_check_strategy: SearchStrategyKind::Greedy,
+            ..Default::default()
+        });
+        let capacity = |capacity, load, free| {
+            BTreeMap::from([(
+                String::from("CPU"),
+                MetricCapacity {
+                    capacity,
+                    load,
+                    free,
+                },
+            )])
+        };
+
+        let report = plb
+            .capacity_report(&create_node_desc_with_capacity(0, "CPU", 100))
+            .unwrap();
+        // the down node is left out
+        assert_eq!(
+            BTreeMap::from([
+                (NodeId::new(0), capacity(100, 40, 60)),
+                (NodeId::new(1), capacity(100, 40, 60)),
+                (NodeId::new(2), capacity(50, 0, 50)),
+            ]),
+            report.nodes
+        );
+        assert_eq!(
+            BTreeMap::from([
+                (String::from("fd0"), capacity(100, 40, 60)),
+                (String::from("fd1"), capacity(150, 40, 110)),
+            ]),
+            report.fault_domains
+        );
+        assert_eq!(
+            BTreeMap::from([
+                (String::from("BackEnd"), capacity(50, 0, 50)),
+                (String::from("FrontEnd"), capacity(200, 80, 120)),
+            ]),
+            report.node_types
+        );
+        assert_eq!(capacity(250, 80, 170), report.cluster);
+        // App reserves 50 on 2 nodes and uses 80 of them
+        assert_eq!(
+            BTreeMap::from([(String::from("CPU"), 20)]),
+            report.unused_reservations
+        );
+        assert_eq!(
+            BTreeMap::from([(String::from("CPU"), 150)]),
+            report.headroom
+        );
+        // LogicalServer has 1 replica per partition and Big 2
+        assert_eq!(
+            BTreeMap::from([(String::from("CPU"), BTreeMap::from([(1, 60), (2, 60)]))]),
+            report.largest_placeable_load
+        );
+        assert_eq!(60, report.largest_placeable_partition("CPU", 2));
+        assert_eq!(50, report.largest_placeable_partition("CPU", 3));
+        assert_eq!(0, report.largest_placeable_partition("CPU", 4));
+        assert_eq!(0, report.largest_placeable_partition("Memory", 1));
+        // one new node per replica of Big
+        assert_eq!(2, report.unplaceable_replicas);
+        assert_eq!(Some(2), report.additional_nodes);
+
+        // nodes too small never help
+        let report = plb
+            .capacity_report(&create_node_desc_with_capacity(0, "CPU", 50))
+            .unwrap();
+        assert_eq!(2, report.unplaceable_replicas);
+        assert_eq!(None, report.additional_nodes);
+    }
+
+    #[test]
+    fn test_predicted_load() {
+        let fu_descs = (0..2)
+            .map(|node_id| {
+                let fu_id = Uuid::from_u128(node_id + 1);
+                create_fu_desc(
+                    fu_id,
+                    "LogicalServer",
+                    create_replicas(fu_id, &[(ReplicaRole::Primary, node_id)]),
+                    0,
+                )
+            })
+            .collect();
+        let mut plb = PlacementAndLoadBalancing::new(
+            vec![create_node_desc(0), create_node_desc(1)],
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![create_service_desc("Worker.ISO", "LogicalServer")
+                .with_metric(
+                    ServiceMetric::new("CPU", 1.0, 10, 10)
+                        .with_built_in_type(BuiltInType::Predicted),
+                )
+                .with_metric(ServiceMetric::new("Memory", 1.0, 10, 10))],
+            fu_descs,
+            vec![],
+        )
+        .unwrap();
+        plb.set_config(PLBConfig {
+            load_forecast: LoadForecastConfig {
+                method: ForecastMethod::LinearTrend {
+                    horizon: time::Duration::minutes(2),
+                },
+                history_size: 10,
+            },
+            ..Default::default()
+        });
+
+        // the load of partition 1 grows by 10 every minute, partition 2 stays flat
+        let initial_time = OffsetDateTime::now_utc();
+        for (minutes, load) in [(0, 10), (1, 20), (2, 30)] {
+            plb.update_load_or_move_cost(
+                LoadOrMoveCostDescription::new(Uuid::from_u128(1))
+                    .with_primary_load("CPU", load)
+                    .with_primary_load("Memory", load),
+            );
+            plb.update_load_or_move_cost(
+                LoadOrMoveCostDescription::new(Uuid::from_u128(2))
+                    .with_primary_load("CPU", 30)
+                    .with_primary_load("Memory", 30),
+            );
+            plb.refresh(initial_time + time::Duration::minutes(minutes))
+                .unwrap();
+        }
+
+        assert_eq!(
+            vec![
+                (initial_time, 10),
+                (initial_time + time::Duration::minutes(1), 20),
+                (initial_time + time::Duration::minutes(2), 30),
+            ],
+            plb.load_history(Uuid::from_u128(1), "CPU", ReplicaRole::Primary)
+        );
+        assert_eq!(
+            Some(50),
+            plb.forecast_load(Uuid::from_u128(1), "CPU", ReplicaRole::Primary)
+        );
+        // the predicted metric weighs its forecast load, the other metric its last reported load
+        let node_loads = plb.node_loads();
+        assert_eq!(50, node_loads[&NodeId::new(0)]["CPU"]);
+        assert_eq!(30, node_loads[&NodeId::new(0)]["Memory"]);
+        assert_eq!(30, node_loads[&NodeId::new(1)]["CPU"]);
+        assert_eq!(30, node_loads[&NodeId::new(1)]["Memory"]);
+
+        // the forecasts follow the settings
+        plb.set_config(PLBConfig {
+            load_forecast: LoadForecastConfig {
+                method: ForecastMethod::ExponentialSmoothing { alpha: 1.0 },
+                history_size: 10,
+            },
+            ..Default::default()
+        });
+        assert_eq!(30, plb.node_loads()[&NodeId::new(0)]["CPU"]);
+
+        plb.delete_failover_unit(Uuid::from_u128(1)).unwrap();
+        plb.refresh(initial_time + time::Duration::minutes(3))
+            .unwrap();
+        assert!(plb
+            .load_history(Uuid::from_u128(1), "CPU", ReplicaRole::Primary)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_built_in_metrics() {
+        // 4 singleton partitions without any metric are all placed on node 0
+        let fu_descs = (1..=4)
+            .map(|fu_index| {
+                let fu_id = Uuid::from_u128(fu_index);
+                create_fu_desc(
+                    fu_id,
+                    "LogicalServer",
+                    create_replicas(fu_id, &[(ReplicaRole::Primary, 0)]),
+                    0,
+                )
+            })
+            .chain([create_fu_desc(
+                Uuid::from_u128(5),
+                "Stateless",
+                create_replicas(Uuid::from_u128(5), &[(ReplicaRole::Auxiliary, 1)]),
+                0,
+            )])
+            .collect();
+        let mut plb = PlacementAndLoadBalancing::new(
+            vec![create_node_desc(0), create_node_desc(1)],
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![
+                create_service_desc("Worker.ISO", "LogicalServer"),
+                // a declared metric replaces the default metric with the same name
+                create_service_desc("Worker.ISO", "Stateless")
+                    .with_metric(ServiceMetric::new("Count", 1.0, 0, 0)),
+            ],
+            fu_descs,
+            vec![],
+        )
+        .unwrap();
+        plb.set_config(PLBConfig {
+            balancing_strategy: SearchStrategyKind::Greedy,
+            ..Default::default()
+        });
+
+        let node_loads = plb.node_loads();
+        assert_eq!(
+            BTreeMap::from([
+                (String::from("Count"), 4),
+                (String::from("PrimaryCount"), 4),
+                (String::from("ReplicaCount"), 4),
+            ]),
+            node_loads[&NodeId::new(0)]
+        );
+        assert_eq!(
+            BTreeMap::from([
+                (String::from("Count"), 0),
+                (String::from("PrimaryCount"), 0),
+                (String::from("ReplicaCount"), 0),
+            ]),
+            node_loads[&NodeId::new(1)]
+        );
+
+        // the replica counts are balanced without any reported load
+        let initial_time = OffsetDateTime::now_utc();
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::LoadBalancing);
+        plb.refresh(initial_time + MIN_BALANCING_INTERVAL).unwrap();
+        assert_eq!(
+            2,
+            plb.last_movements()
+                .iter()
+                .filter(|movement| matches!(movement, Movement::AddReplica { node, .. } if *node == NodeId::new(1)))
+                .count()
+        );
+
+        // without default metrics there is nothing to balance
+        plb.set_config(PLBConfig {
+            default_metrics: vec![],
+            ..Default::default()
+        });
+        assert_eq!(
+            BTreeMap::from([
+                (NodeId::new(0), BTreeMap::from([(String::from("Count"), 0)])),
+                (NodeId::new(1), BTreeMap::from([(String::from("Count"), 0)])),
+            ]),
+            plb.node_loads()
+        );
+    }
+
+    #[test]
+    fn test_resource_governance() {
+        // the front end and back end services share a package, the other service runs in a package of its own
+        let package = ServicePackageDescription::new("Package", 1.5, 600);
+        let other_package = ServicePackageDescription::new("OtherPackage", 0.5, 600);
+        let nodes = (0..2)
+            .map(|node_id| {
+                NodeDescription::new(
+                    NodeInstance::new(NodeId::new(node_id), 0),
+                    true,
+                    HashMap::new(),
+                    HashMap::from([
+                        (String::from(CPU_CORES_METRIC), 4000),
+                        (String::from(MEMORY_IN_MB_METRIC), 1000),
+                    ]),
+                )
+            })
+            .collect();
+        let fu_descs = [(1, "FrontEnd"), (2, "BackEnd")]
+            .into_iter()
+            .map(|(fu_index, service_name)| {
+                let fu_id = Uuid::from_u128(fu_index);
+                create_fu_desc(
+                    fu_id,
+                    service_name,
+                    create_replicas(fu_id, &[(ReplicaRole::Primary, 0)]),
+                    0,
+                )
+            })
+            .chain([3, 4].map(|fu_index| {
+                let service_name = if fu_index == 3 { "Other" } else { "BackEnd" };
+                create_fu_desc(Uuid::from_u128(fu_index), service_name, HashMap::new(), 1)
+            }))
+            .collect();
+        let mut plb = PlacementAndLoadBalancing::new(
+            nodes,
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            ["FrontEnd", "BackEnd", "Other"]
+                .into_iter()
+                .map(|service_name| {
+                    let service_package = match service_name {
+                        "Other" => other_package.clone(),
+                        _ => package.clone(),
+                    };
+                    create_service_desc("Worker.ISO", service_name)
+                        .with_application_name("App")
+                        .with_service_package(service_package)
+                })
+                .collect(),
+            fu_descs,
+            vec![],
+        )
+        .unwrap();
+
+        // the two replicas of the package on node 0 reserve its limits once
+        let node_loads = plb.node_loads();
+        assert_eq!(1500, node_loads[&NodeId::new(0)][CPU_CORES_METRIC]);
+        assert_eq!(600, node_loads[&NodeId::new(0)][MEMORY_IN_MB_METRIC]);
+        assert_eq!(0, node_loads[&NodeId::new(1)][MEMORY_IN_MB_METRIC]);
+
+        // another package does not fit in the memory left on node 0, while the package already there does
+        assert_eq!(
+            vec![NodeRejectReason::CapacityExceeded(String::from(
+                MEMORY_IN_MB_METRIC
+            ))],
+            plb.explain_placement(Uuid::from_u128(3)).unwrap().nodes[&NodeId::new(0)]
+        );
+        assert_eq!(
+            vec![NodeId::new(0), NodeId::new(1)],
+            plb.explain_placement(Uuid::from_u128(4))
+                .unwrap()
+                .eligible_nodes()
+        );
+
+        let initial_time = OffsetDateTime::now_utc();
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::Placement);
+        plb.refresh(initial_time + MIN_PLACEMENT_INTERVAL).unwrap();
+        assert!(plb.last_movements().contains(&Movement::AddReplica {
+            fu_id: Uuid::from_u128(3),
+            node: NodeId::new(1),
+        }));
+    }
+
+    #[test]
+    fn test_annealing_balancing_is_reproducible() {
+        let initial_time = OffsetDateTime::now_utc();
+        let config = AnnealingConfig {
+            max_iterations: 2_000,
+            time_budget: time::Duration::new(60, 0),
+            seed: 42,
+            ..Default::default()
+        };
+
+        let mut runs = vec![];
+        for _ in 0..2 {
+            let mut plb = create_unbalanced_plb();
+            plb.set_config(PLBConfig {
+                balancing_strategy: SearchStrategyKind::Annealing,
+                annealing: config.clone(),
+                ..Default::default()
+            });
+            plb.scheduler
+                .set_last_phase_time(initial_time, Phase::LoadBalancing);
+            let solutions = plb
+                .refresh(initial_time + MIN_BALANCING_INTERVAL)
+                .unwrap()
+                .solutions();
+
+            let statistics = plb.last_search_statistics();
+            assert_eq!(1, statistics.len());
+            assert_eq!(2_000, statistics[0].iterations);
+            assert!(!statistics[0].aborted_on_time_budget);
+            assert_eq!(
+                statistics[0].iterations,
+                statistics[0].accepted_moves + statistics[0].rejected_moves
+            );
+            // 2 partitions end up on each node, each of the 4 singletons moving make-before-break, starting with
+            // the new replica
+            assert!(statistics[0].final_score < 1e-6);
+            assert_eq!(4, solutions.len());
+
+            runs.push(solutions);
+        }
+
+        assert_eq!(runs[0], runs[1]);
+    }
+
+    #[test]
+    fn test_greedy_balancing_strategy() {
+        let initial_time = OffsetDateTime::now_utc();
+        let mut plb = create_unbalanced_plb();
+        plb.set_config(PLBConfig {
+            balancing_strategy: SearchStrategyKind::Greedy,
+            ..Default::default()
+        });
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::LoadBalancing);
+
+        let solutions = plb
+            .refresh(initial_time + MIN_BALANCING_INTERVAL)
+            .unwrap()
+            .solutions();
+
+        // the greedy strategy does not run a search, but still ends up with 2 partitions on each node, each of the 4
+        // singletons moving make-before-break, starting with the new replica
+        assert!(plb.last_search_statistics().is_empty());
+        assert_eq!(4, solutions.len());
+        assert!(solutions
+            .iter()
+            .all(|solution| matches!(solution, Solution::AddReplica(_))));
+    }
+
+    #[test]
+    fn test_custom_search_strategy() {
+        struct DropEverything;
+
+        impl SearchStrategy for DropEverything {
+            fn name(&self) -> &str {
+                "DropEverything"
+            }
+
+            fn search(&mut self, view: SnapshotView<'_>, _phase: Phase) -> strategy::SearchOutcome {
+                strategy::SearchOutcome {
+                    movements: view
+                        .failover_units()
+                        .flat_map(|fu| {
+                            fu.active_replicas().map(|replica| Movement::DropReplica {
+                                fu_id: fu.id(),
+                                node: replica.location(),
+                            })
+                        })
+                        .collect(),
+                    statistics: None,
+                }
+            }
+        }
+
+        let initial_time = OffsetDateTime::now_utc();
+        let mut plb = create_unbalanced_plb();
+        plb.set_search_strategy(Phase::Placement, Box::new(DropEverything));
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::Placement);
+
+        let solutions = plb
+            .refresh(initial_time + MIN_PLACEMENT_INTERVAL)
+            .unwrap()
+            .solutions();
+
+        assert_eq!(6, solutions.len());
+        assert!(solutions
+            .iter()
+            .all(|solution| matches!(solution, Solution::DeleteReplica(_))));
+    }
+
+    #[test]
+    fn test_exact_solver_optimality_gap() {
+        // partitions with a CPU load of 5, 4, 3, 3, 3 and 2 are all placed on node 0 out of 2 nodes, the best
+        // placement puts a load of 10 on each node
+        let loads = [5, 4, 3, 3, 3, 2];
+        let fu_descs = (1..=loads.len() as u128)
+            .map(|fu_index| {
+                let fu_id = Uuid::from_u128(fu_index);
+                create_fu_desc(
+                    fu_id,
+                    "LogicalServer",
+                    create_replicas(fu_id, &[(ReplicaRole::Primary, 0)]),
+                    0,
+                )
+            })
+            .collect();
+        let mut plb = PlacementAndLoadBalancing::new(
+            vec![
+                create_node_desc_with_capacity(0, "CPU", 100),
+                create_node_desc_with_capacity(1, "CPU", 100),
+            ],
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![create_service_desc("Worker.ISO", "LogicalServer")
+                .with_metric(ServiceMetric::new("CPU", 1.0, 0, 0))],
+            fu_descs,
+            vec![],
+        )
+        .unwrap();
+        for (fu_index, load) in loads.iter().enumerate() {
+            plb.update_load_or_move_cost(
+                LoadOrMoveCostDescription::new(Uuid::from_u128(fu_index as u128 + 1))
+                    .with_primary_load("CPU", *load),
+            );
+        }
+        plb.set_config(PLBConfig {
+            default_metrics: vec![],
+            ..Default::default()
+        });
+        plb.refresh(OffsetDateTime::now_utc()).unwrap();
+
+        let limits = ExactSolverLimits::default();
+        let optimum = plb.solve_exact(&limits).unwrap();
+        assert!(optimum.proven_optimal);
+        assert!(optimum.score < 1e-9);
+
+        // doing nothing leaves the whole load on node 0
+        let gap = plb.optimality_gap(&[], &limits).unwrap();
+        assert!(gap.proven_optimal);
+        assert!((gap.heuristic_score - 1.0).abs() < 1e-9);
+        assert!((gap.gap - 1.0).abs() < 1e-9);
+
+        // the exact solution has no gap with itself, while the greedy strategy stops at a load of 11 and 9
+        let gap = plb.optimality_gap(&optimum.movements, &limits).unwrap();
+        assert!(gap.gap.abs() < 1e-9);
+        let gap = plb
+            .evaluate_strategy(&mut GreedyStrategy::new(100), Phase::LoadBalancing, &limits)
+            .unwrap();
+        assert!((gap.gap - 0.1).abs() < 1e-9);
+
+        // the solver refuses clusters that are too large
+        let too_small = ExactSolverLimits {
+            max_replicas: 5,
+            ..Default::default()
+        };
+        assert!(plb.solve_exact(&too_small).is_err());
+    }
+
+    #[test]
+    fn test_exact_solver_constraints() {
+        // the 3 replicas spread over the 3 nodes would be balanced, but the child must stay with a parent replica
+        let fu_ids = [1, 2, 3].map(Uuid::from_u128);
+        let mut plb = PlacementAndLoadBalancing::new(
+            (0..3)
+                .map(|node_id| create_node_desc_with_capacity(node_id, "CPU", 100))
+                .collect(),
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![
+                create_service_desc("Worker.ISO", "Parent")
+                    .with_metric(ServiceMetric::new("CPU", 1.0, 10, 10)),
+                create_service_desc("Worker.ISO", "Child")
+                    .with_affinitized_service("Parent")
+                    .with_metric(ServiceMetric::new("CPU", 1.0, 10, 10)),
+            ],
+            fu_ids
+                .iter()
+                .zip(["Parent", "Parent", "Child"])
+                .map(|(fu_id, service_name)| {
+                    create_fu_desc(
+                        *fu_id,
+                        service_name,
+                        create_replicas(*fu_id, &[(ReplicaRole::Primary, 0)]),
+                        0,
+                    )
+                })
+                .collect(),
+            vec![],
+        )
+        .unwrap();
+        plb.set_config(PLBConfig {
+            default_metrics: vec![],
+            ..Default::default()
+        });
+        plb.refresh(OffsetDateTime::now_utc()).unwrap();
+
+        let optimum = plb.solve_exact(&ExactSolverLimits::default()).unwrap();
+        assert!(optimum.proven_optimal);
+        assert!(optimum.score > 1e-9);
+        let location = |fu_id: Uuid| {
+            optimum
+                .movements
+                .iter()
+                .find_map(|movement| match movement {
+                    Movement::MoveReplica { to, .. } if movement.fu_id() == fu_id => Some(*to),
+                    _ => None,
+                })
+                .unwrap_or(NodeId::new(0))
+        };
+        assert!([location(fu_ids[0]), location(fu_ids[1])].contains(&location(fu_ids[2])));
+
+        // the secondary on the down node 2 uses fd1, so the primary on node 0 cannot move to node 1, the other failover
+        // unit has to balance the nodes
+        let (fu_id, other_fu_id) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let mut down_node = create_node_desc(2).with_fault_domain("fd1");
+        down_node.is_up = false;
+        let mut plb = PlacementAndLoadBalancing::new(
+            vec![
+                create_node_desc_with_capacity(0, "CPU", 100).with_fault_domain("fd0"),
+                create_node_desc_with_capacity(1, "CPU", 100).with_fault_domain("fd1"),
+                down_node,
+            ],
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![create_service_desc("Worker.ISO", "LogicalServer")
+                .with_metric(ServiceMetric::new("CPU", 1.0, 10, 0))],
+            vec![
+                create_fu_desc(
+                    fu_id,
+                    "LogicalServer",
+                    create_replicas(
+                        fu_id,
+                        &[(ReplicaRole::Primary, 0), (ReplicaRole::Secondary, 2)],
+                    ),
+                    0,
+                ),
+                create_fu_desc(
+                    other_fu_id,
+                    "LogicalServer",
+                    create_replicas(
+                        other_fu_id,
+                        &[(ReplicaRole::Primary, 0), (ReplicaRole::Secondary, 1)],
+                    ),
+                    0,
+                ),
+            ],
+            vec![],
+        )
+        .unwrap();
+        plb.set_config(PLBConfig {
+            default_metrics: vec![],
+            ..Default::default()
+        });
+        plb.refresh(OffsetDateTime::now_utc()).unwrap();
+
+        let optimum = plb.solve_exact(&ExactSolverLimits::default()).unwrap();
+        assert!(optimum.proven_optimal);
+        assert!(optimum.score < 1e-9);
+        assert!(optimum
+            .movements
+            .iter()
+            .all(|movement| movement.fu_id() == other_fu_id));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut plb = create_unbalanced_plb();
+        plb.update_load_or_move_cost(
+            LoadOrMoveCostDescription::new(Uuid::from_u128(1)).with_primary_load("CPU", 30),
+        );
+        plb.start_upgrade_domain(UpgradeScope::Application(String::from("App")), "UD1");
+        plb.refresh(OffsetDateTime::now_utc()).unwrap();
+
+        let path = std::env::temp_dir().join(format!("plb-snapshot-{}.json", Uuid::new_v4()));
+        plb.dump_snapshot(&path).unwrap();
+        let restored = PlacementAndLoadBalancing::from_snapshot_file(&path).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let json = serde_json::from_str::<serde_json::Value>(&content).unwrap();
+        assert_eq!(1, json["version"]);
+        assert_eq!(3, json["nodes"].as_array().unwrap().len());
+        assert_eq!(6, json["failover_units"].as_array().unwrap().len());
+        assert_eq!(
+            json,
+            serde_json::to_value(&*restored.cluster_snapshot.borrow()).unwrap()
+        );
+        // the reported load of the first partition replaces its default load
+        assert_eq!(
+            80,
+            restored
+                .cluster_snapshot
+                .borrow()
+                .node_load(NodeId::new(0), "CPU")
+        );
+
+        // snapshots written by a newer schema are rejected
+        let mut newer = json.clone();
+        newer["version"] = serde_json::json!(snapshot::SNAPSHOT_SCHEMA_VERSION + 1);
+        assert!(serde_json::from_value::<ClusterSnapshot>(newer).is_err());
+    }
+
+    #[test]
+    fn test_apply_movements_back() {
+        let initial_time = OffsetDateTime::now_utc();
+        let mut plb = create_unbalanced_plb();
+        plb.set_config(PLBConfig {
+            balancing_strategy: SearchStrategyKind::Greedy,
+            ..Default::default()
+        });
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::LoadBalancing);
+
+        // FM carries out the solutions and reports the failover units back, the make-before-break sequences taking 3
+        // rounds of acknowledgments
+        let mut report = plb.refresh(initial_time + MIN_BALANCING_INTERVAL).unwrap();
+        let mut rounds = 0;
+        while !report.solutions().is_empty() {
+            let movements = plb.last_movements().to_vec();
+            assert_eq!(
+                report.solutions(),
+                movements
+                    .iter()
+                    .map(Movement::to_solution)
+                    .collect::<Vec<Solution>>()
+            );
+            let mut fu_descs = BTreeMap::<Uuid, FailoverUnitDescription>::new();
+            for (id, movement) in report.solution_ids().into_iter().zip(&movements) {
+                let fu_desc = fu_descs
+                    .entry(movement.fu_id())
+                    .or_insert_with(|| plb.failover_unit_description(movement.fu_id()).unwrap());
+                fu_desc.apply_movement(movement).unwrap();
+                // the same movement cannot be applied twice
+                assert!(fu_desc.clone().apply_movement(movement).is_err());
+                plb.acknowledge_solution(id, MovementOutcome::Succeeded)
+                    .unwrap();
+            }
+            for fu_desc in fu_descs.into_values() {
+                plb.update_failover_unit(fu_desc);
+            }
+            report = plb.refresh(initial_time + MIN_BALANCING_INTERVAL).unwrap();
+            rounds += 1;
+        }
+
+        assert_eq!(3, rounds);
+        assert!(plb.node_loads().values().all(|loads| loads["CPU"] == 20));
+    }
+
+    #[test]
+    fn test_unreported_make_before_break_step() {
+        let initial_time = OffsetDateTime::now_utc();
+        let mut plb = create_unbalanced_plb();
+        plb.set_config(PLBConfig {
+            balancing_strategy: SearchStrategyKind::Greedy,
+            ..Default::default()
+        });
+        let event_sink = Rc::new(InMemoryEventSink::new());
+        plb.set_event_sink(event_sink.clone());
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::LoadBalancing);
+        let now = initial_time + MIN_BALANCING_INTERVAL;
+
+        // FM acknowledges the adds of the make-before-break moves without reporting the new replicas
+        let report = plb.refresh(now).unwrap();
+        let adds = plb.last_movements().len();
+        assert!(adds > 0);
+        assert!(plb
+            .last_movements()
+            .iter()
+            .all(|movement| matches!(movement, Movement::AddReplica { .. })));
+        for id in report.solution_ids() {
+            plb.acknowledge_solution(id, MovementOutcome::Succeeded)
+                .unwrap();
+        }
+        event_sink.take();
+
+        // the swaps do not apply to the partitions FM reports, they are dropped and the drops after them never issued
+        for _ in 0..3 {
+            let report = plb.refresh(now).unwrap();
+            assert!(!report
+                .solutions()
+                .iter()
+                .any(|solution| matches!(solution, Solution::DeleteReplica(_))));
+        }
+        let dropped_swaps = event_sink
+            .take()
+            .into_iter()
+            .filter(|event| {
+                matches!(
+                    event,
+                    PlbEvent::SolutionDropped {
+                        rejected: RejectedMovement {
+                            movement: Movement::SwapPrimary { .. },
+                            violation: PlanViolation::InvalidMovement(_),
+                            ..
+                        },
+                        ..
+                    }
+                )
+            })
+            .count();
+        assert_eq!(adds, dropped_swaps);
+        assert!(plb.pending_movements().is_empty());
+    }
+
+    #[test]
+    fn test_inapplicable_follow_ups() {
+        let initial_time = OffsetDateTime::now_utc();
+        let mut plb = create_unbalanced_plb();
+        plb.set_config(PLBConfig {
+            balancing_strategy: SearchStrategyKind::Greedy,
+            ..Default::default()
+        });
+        let event_sink = Rc::new(InMemoryEventSink::new());
+        plb.set_event_sink(event_sink.clone());
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::LoadBalancing);
+
+        let report = plb.refresh(initial_time + MIN_BALANCING_INTERVAL).unwrap();
+        let id = report.solution_ids()[0];
+        let Movement::AddReplica { fu_id, node } = plb.last_movements()[0] else {
+            panic!("expected the add of a make-before-break move");
+        };
+        let follow_ups = report
+            .phases
+            .iter()
+            .flat_map(|phase_report| &phase_report.deferred)
+            .filter(|movement| movement.fu_id() == fu_id)
+            .cloned()
+            .collect::<Vec<Movement>>();
+        assert_eq!(2, follow_ups.len());
+
+        // FM reports the primary on the third node before the add is done: the swap from node 0 no longer applies
+        let mut fu_desc = plb.failover_unit_description(fu_id).unwrap();
+        for replica in fu_desc.replicas.values_mut() {
+            replica.location = NodeId::new(3 - node.id_value);
+        }
+        plb.update_failover_unit(fu_desc);
+        event_sink.take();
+        plb.refresh(initial_time + MIN_BALANCING_INTERVAL * 2)
+            .unwrap();
+        assert!(event_sink.take().contains(&PlbEvent::FollowUpsDropped {
+            id,
+            movements: follow_ups,
+        }));
+
+        // the add succeeding does not release them
+        plb.acknowledge_solution(id, MovementOutcome::Succeeded)
+            .unwrap();
+        plb.refresh(initial_time + MIN_BALANCING_INTERVAL * 2)
+            .unwrap();
+        assert!(!plb
+            .last_movements()
+            .iter()
+            .any(|movement| movement.fu_id() == fu_id));
+    }
+
+    #[test]
+    fn test_delete_entities() {
+        let mut plb = create_unbalanced_plb();
+        plb.delete_failover_unit(Uuid::from_u128(1)).unwrap();
+        plb.delete_node(NodeId::new(2)).unwrap();
+        assert!(plb.delete_node(NodeId::new(3)).is_err());
+        assert!(plb.delete_service("Unknown").is_err());
+        plb.refresh(OffsetDateTime::now_utc()).unwrap();
+
+        let node_loads = plb.node_loads();
+        assert_eq!(2, node_loads.len());
+        assert_eq!(50, node_loads[&NodeId::new(0)]["CPU"]);
+        assert!(plb.failover_unit_description(Uuid::from_u128(1)).is_none());
+    }
+
+    #[test]
+    fn test_explain_placement() {
+        let parent_fu_id = Uuid::from_u128(100);
+        let fu_id = Uuid::from_u128(1);
+        let nodes = (0..8)
+            .map(|node_id| {
+                let node_desc = match node_id {
+                    0 => NodeDescription::new(
+                        NodeInstance::new(NodeId::new(0), 0),
+                        false,
+                        HashMap::new(),
+                        HashMap::new(),
+                    ),
+                    3 | 4 => create_node_desc(node_id).with_fault_domain("fd:/0"),
+                    5 => create_node_desc_with_capacity(5, "CPU", 5),
+                    _ => create_node_desc(node_id),
+                };
+                let node_type = if node_id == 1 { "BackEnd" } else { "FrontEnd" };
+                node_desc.with_property("NodeType", node_type)
+            })
+            .collect();
+        let plb = PlacementAndLoadBalancing::new(
+            nodes,
+            vec![],
+            vec![ServiceTypeDescription::new(
+                "Worker.ISO",
+                HashSet::from([NodeId::new(2)]),
+            )],
+            vec![
+                create_service_desc("Worker.ISO", "Parent"),
+                create_service_desc("Worker.ISO", "Child")
+                    .with_placement_constraints("NodeType == FrontEnd")
+                    .with_affinitized_service("Parent")
+                    .with_metric(ServiceMetric::new("CPU", 1.0, 10, 10)),
+            ],
+            vec![
+                create_fu_desc(
+                    parent_fu_id,
+                    "Parent",
+                    create_replicas(
+                        parent_fu_id,
+                        &[
+                            (ReplicaRole::Primary, 0),
+                            (ReplicaRole::Secondary, 1),
+                            (ReplicaRole::Secondary, 2),
+                            (ReplicaRole::Secondary, 3),
+                            (ReplicaRole::Secondary, 4),
+                            (ReplicaRole::Secondary, 5),
+                            (ReplicaRole::Secondary, 7),
+                        ],
+                    ),
+                    0,
+                ),
+                create_fu_desc(
+                    fu_id,
+                    "Child",
+                    create_replicas(fu_id, &[(ReplicaRole::Primary, 3)]),
+                    1,
+                ),
+            ],
+            vec![],
+        )
+        .unwrap();
+
+        let explanation = plb.explain_placement(fu_id).unwrap();
+        let expected = [
+            NodeRejectReason::NodeDown,
+            NodeRejectReason::PlacementConstraintNotSatisfied,
+            NodeRejectReason::BlockListed,
+            NodeRejectReason::ReplicaAlreadyOnNode,
+            NodeRejectReason::FaultDomainAlreadyUsed,
+            NodeRejectReason::CapacityExceeded(String::from("CPU")),
+            NodeRejectReason::AffinityParentAbsent,
+        ];
+        for (node_id, reason) in expected.iter().enumerate() {
+            assert_eq!(
+                vec![reason.clone()],
+                explanation.nodes[&NodeId::new(node_id as u128)]
+            );
+            assert_eq!(1, explanation.rejection_counts[reason]);
+        }
+        assert_eq!(vec![NodeId::new(7)], explanation.eligible_nodes());
+        assert!(plb.explain_placement(Uuid::from_u128(2)).is_err());
+
+        // placement agrees with the explanation
+        let mut plb = plb;
+        let initial_time = OffsetDateTime::now_utc();
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::Placement);
+        let solutions = plb
+            .refresh(initial_time + MIN_PLACEMENT_INTERVAL)
+            .unwrap()
+            .solutions();
+        assert_eq!(
+            vec![Movement::AddReplica {
+                fu_id,
+                node: NodeId::new(7)
+            }
+            .to_solution()],
+            solutions
+        );
+
+        // a service update with constraints that do not parse is rejected with the parsing error
+        let event_sink = Rc::new(InMemoryEventSink::new());
+        plb.set_event_sink(event_sink.clone());
+        assert!(plb
+            .update_service(
+                create_service_desc("Worker.ISO", "Child")
+                    .with_placement_constraints("NodeType == "),
+            )
+            .is_err());
+        plb.refresh(initial_time + MIN_PLACEMENT_INTERVAL).unwrap();
+        assert!(event_sink.take().contains(&PlbEvent::UpdateRejected {
+            kind: EntityKind::Service,
+            key: String::from("Child"),
+            reason: String::from(
+                "Invalid placement constraint NodeType == : expected a value to compare NodeType with"
+            ),
+        }));
+        assert_eq!(
+            "NodeType == FrontEnd",
+            plb.cluster_snapshot.borrow().services["Child"]
+                .service_description
+                .placement_constraints
+        );
+    }
+
+    #[test]
+    fn test_misplaced_replicas() {
+        // the secondary of the partition is on a BackEnd node while the service only runs on FrontEnd nodes
+        let now = OffsetDateTime::now_utc();
+        let fu_id = Uuid::from_u128(1);
+        let mut plb = PlacementAndLoadBalancing::new(
+            vec![
+                create_node_desc(0).with_property("NodeType", "FrontEnd"),
+                create_node_desc(1).with_property("NodeType", "BackEnd"),
+                create_node_desc(2).with_property("NodeType", "FrontEnd"),
+            ],
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![create_service_desc("Worker.ISO", "LogicalServer")
+                .with_placement_constraints("NodeType == FrontEnd")],
+            vec![create_fu_desc(
+                fu_id,
+                "LogicalServer",
+                create_replicas(
+                    fu_id,
+                    &[(ReplicaRole::Primary, 0), (ReplicaRole::Secondary, 1)],
+                ),
+                0,
+            )],
+            vec![],
+        )
+        .unwrap();
+        plb.scheduler.reset(now - MIN_BALANCING_INTERVAL);
+
+        let report = plb.refresh(now).unwrap();
+        assert_eq!(
+            vec![ConstraintViolation::ReplicaMisplaced {
+                fu_id,
+                node_id: NodeId::new(1),
+                reason: NodeRejectReason::PlacementConstraintNotSatisfied,
+            }],
+            report.violations
+        );
+        assert_eq!(
+            [Movement::MoveReplica {
+                fu_id,
+                role: ReplicaRole::Secondary,
+                from: NodeId::new(1),
+                to: NodeId::new(2),
+            }
+            .to_solution()],
+            report.solutions_of(Phase::ConstraintCheck)
+        );
+    }
+
+    #[test]
+    fn test_refresh_report() {
+        let now = OffsetDateTime::now_utc();
+        let mut plb = create_unbalanced_plb();
+        plb.scheduler.reset(now - MIN_BALANCING_INTERVAL);
+        plb.scheduler.set_last_phase_time(now, Phase::LoadBalancing);
+        plb.update_service(
+            create_service_desc("Worker.ISO", "LogicalServer")
+                .with_metric(ServiceMetric::new("CPU", 1.0, 20, 20)),
+        )
+        .unwrap();
+        plb.update_load_or_move_cost(
+            LoadOrMoveCostDescription::new(Uuid::from_u128(1)).with_primary_load("CPU", 30),
+        );
+
+        let report = plb.refresh(now).unwrap();
+        assert_eq!(
+            UpdateCounts {
+                services: 1,
+                loads: 1,
+                ..Default::default()
+            },
+            report.updates
+        );
+        assert_eq!(
+            vec![ConstraintViolation::CapacityExceeded {
+                node_id: NodeId::new(0),
+                metric_name: String::from("CPU"),
+                load: 130,
+                capacity: 100,
+            }],
+            report.violations
+        );
+        assert_eq!(
+            vec![SkippedPhase {
+                phase: Phase::LoadBalancing,
+                reason: PhaseSkipReason::IntervalNotElapsed {
+                    next_run_time: now + MIN_BALANCING_INTERVAL
+                },
+            }],
+            report.skipped_phases
+        );
+        assert_eq!(
+            vec![Phase::Placement, Phase::ConstraintCheck],
+            report
+                .phases
+                .iter()
+                .map(|phase| phase.phase)
+                .collect::<Vec<Phase>>()
+        );
+        // the overloaded node is relieved by the constraint check phase
+        assert!(report.solutions_of(Phase::Placement).is_empty());
+        assert!(!report.solutions_of(Phase::ConstraintCheck).is_empty());
+        assert_eq!(
+            report.solutions(),
+            report.solutions_of(Phase::ConstraintCheck)
+        );
+        assert_eq!(plb.last_movements(), report.phases[1].movements.as_slice());
+    }
+
+    #[test]
+    fn test_event_sink() {
+        let now = OffsetDateTime::now_utc();
+        let mut plb = create_unbalanced_plb();
+        let event_sink = Rc::new(InMemoryEventSink::new());
+        plb.set_event_sink(event_sink.clone());
+        plb.scheduler.reset(now - MIN_BALANCING_INTERVAL);
+        plb.scheduler.set_last_phase_time(now, Phase::LoadBalancing);
+        plb.update_service(
+            create_service_desc("Worker.ISO", "LogicalServer")
+                .with_metric(ServiceMetric::new("CPU", 1.0, 20, 20)),
+        )
+        .unwrap();
+        plb.update_load_or_move_cost(
+            LoadOrMoveCostDescription::new(Uuid::from_u128(1)).with_primary_load("CPU", 30),
+        );
+        plb.complete_upgrade(UpgradeScope::Cluster);
+        // the second deletion finds the failover unit already removed
+        plb.delete_failover_unit(Uuid::from_u128(6)).unwrap();
+        plb.delete_failover_unit(Uuid::from_u128(6)).unwrap();
+
+        let report = plb.refresh(now).unwrap();
+        let constraint_check = report.phase(Phase::ConstraintCheck).unwrap();
+        let mut expected = vec![
+            PlbEvent::UpdateApplied {
+                kind: EntityKind::Service,
+                key: String::from("LogicalServer"),
+                deleted: false,
+            },
+            PlbEvent::UpdateApplied {
+                kind: EntityKind::Load,
+                key: Uuid::from_u128(1).to_string(),
+                deleted: false,
+            },
+            PlbEvent::UpdateRejected {
+                kind: EntityKind::Upgrade,
+                key: String::from("Cluster"),
+                reason: String::from("No upgrade in progress for the scope"),
+            },
+            PlbEvent::UpdateApplied {
+                kind: EntityKind::FailoverUnit,
+                key: Uuid::from_u128(6).to_string(),
+                deleted: true,
+            },
+            PlbEvent::UpdateRejected {
+                kind: EntityKind::FailoverUnit,
+                key: Uuid::from_u128(6).to_string(),
+                reason: String::from("Not in the cluster snapshot"),
+            },
+            PlbEvent::PhaseSkipped {
+                phase: Phase::LoadBalancing,
+                next_run_time: now + MIN_BALANCING_INTERVAL,
+            },
+            PlbEvent::PhaseStarted {
+                phase: Phase::Placement,
+                strategy: report.phases[0].strategy.clone(),
+            },
+            PlbEvent::PhaseFinished {
+                phase: Phase::Placement,
+                strategy: report.phases[0].strategy.clone(),
+                duration: report.phases[0].duration,
+                movements: 0,
+            },
+            PlbEvent::PhaseStarted {
+                phase: Phase::ConstraintCheck,
+                strategy: constraint_check.strategy.clone(),
+            },
+            // the searcher reports the violation before the overloaded node is relieved
+            PlbEvent::ViolationDetected(ConstraintViolation::CapacityExceeded {
+                node_id: NodeId::new(0),
+                metric_name: String::from("CPU"),
+                load: 110,
+                capacity: 100,
+            }),
+            PlbEvent::PhaseFinished {
+                phase: Phase::ConstraintCheck,
+                strategy: constraint_check.strategy.clone(),
+                duration: constraint_check.duration,
+                movements: constraint_check.movements.len(),
+            },
+        ];
+        expected.extend(
+            constraint_check
+                .solution_ids
+                .iter()
+                .zip(&constraint_check.solutions)
+                .map(|(id, solution)| PlbEvent::SolutionEmitted {
+                    phase: Phase::ConstraintCheck,
+                    id: *id,
+                    solution: solution.clone(),
+                }),
+        );
+        assert!(!constraint_check.solutions.is_empty());
+        assert_eq!(expected, event_sink.take());
+
+        // once the movements relieving node 0 failed, the new replica can only go to the overloaded node 0, so the
+        // placement reports it as unplaceable
+        for id in report.solution_ids() {
+            plb.acknowledge_solution(id, MovementOutcome::Failed)
+                .unwrap();
+        }
+        assert!(event_sink
+            .events()
+            .iter()
+            .all(|event| matches!(event, PlbEvent::SolutionAcknowledged { .. })));
+        let fu_id = Uuid::from_u128(7);
+        plb.update_failover_unit(create_fu_desc(
+            fu_id,
+            "LogicalServer",
+            create_replicas(
+                fu_id,
+                &[(ReplicaRole::Primary, 1), (ReplicaRole::Secondary, 2)],
+            ),
+            1,
+        ));
+        plb.scheduler.reset(now);
+        let report = plb.refresh(now + MIN_PLACEMENT_INTERVAL).unwrap();
+        assert!(report.solutions_of(Phase::Placement).is_empty());
+        assert!(event_sink
+            .events()
+            .contains(&PlbEvent::ReplicaUnplaceable { fu_id }));
+    }
+
+    #[test]
+    fn test_load_report_checks() {
+        let fu_id = Uuid::from_u128(1);
+        let cpu = ServiceMetric::new("CPU", 1.0, 10, 10).with_maximum_load(50);
+        let mut plb = PlacementAndLoadBalancing::new(
+            vec![create_node_desc(0), create_node_desc(1)],
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![create_service_desc("Worker.ISO", "LogicalServer").with_metric(cpu.clone())],
+            vec![create_fu_desc(
+                fu_id,
+                "LogicalServer",
+                create_replicas(
+                    fu_id,
+                    &[(ReplicaRole::Primary, 0), (ReplicaRole::Secondary, 1)],
+                ),
+                0,
+            )],
+            vec![LoadOrMoveCostDescription::new(fu_id).with_primary_load("CPU", 70)],
+        )
+        .unwrap();
+        // the initial loads are clamped as well
+        assert_eq!(50, plb.node_loads()[&NodeId::new(0)]["CPU"]);
+        let event_sink = Rc::new(InMemoryEventSink::new());
+        plb.set_event_sink(event_sink.clone());
+        let load_events = |events: Vec<PlbEvent>| {
+            events
+                .into_iter()
+                .filter(|event| {
+                    matches!(
+                        event,
+                        PlbEvent::LoadClamped { .. }
+                            | PlbEvent::LoadSpike { .. }
+                            | PlbEvent::UpdateRejected { .. }
+                    )
+                })
+                .collect::<Vec<PlbEvent>>()
+        };
+        let now = OffsetDateTime::now_utc();
+
+        // the load above the maximum is clamped
+        plb.update_load_or_move_cost(
+            LoadOrMoveCostDescription::new(fu_id)
+                .with_primary_load("CPU", 80)
+                .with_secondary_load("CPU", 4),
+        );
+        plb.refresh(now).unwrap();
+        assert_eq!(
+            vec![PlbEvent::LoadClamped {
+                fu_id,
+                metric_name: String::from("CPU"),
+                role: ReplicaRole::Primary,
+                reported: 80,
+                maximum: 50,
+            }],
+            load_events(event_sink.take())
+        );
+        assert_eq!(50, plb.node_loads()[&NodeId::new(0)]["CPU"]);
+
+        // the secondary load grows more than tenfold
+        plb.update_load_or_move_cost(
+            LoadOrMoveCostDescription::new(fu_id)
+                .with_primary_load("CPU", 40)
+                .with_secondary_load("CPU", 45),
+        );
+        plb.refresh(now).unwrap();
+        assert_eq!(
+            vec![PlbEvent::LoadSpike {
+                fu_id,
+                metric_name: String::from("CPU"),
+                role: ReplicaRole::Secondary,
+                previous: 4,
+                load: 45,
+            }],
+            load_events(event_sink.take())
+        );
+
+        // a default load above the maximum rejects the service update
+        let invalid = create_service_desc("Worker.ISO", "LogicalServer")
+            .with_metric(ServiceMetric::new("CPU", 1.0, 60, 10).with_maximum_load(50));
+        assert_eq!(
+            "Invalid service LogicalServer: Default load 60 of metric CPU is above its maximum load 50",
+            plb.update_service(invalid.clone()).unwrap_err().to_string()
+        );
+        plb.refresh(now).unwrap();
+        assert_eq!(
+            vec![PlbEvent::UpdateRejected {
+                kind: EntityKind::Service,
+                key: String::from("LogicalServer"),
+                reason: String::from("Default load 60 of metric CPU is above its maximum load 50"),
+            }],
+            load_events(event_sink.take())
+        );
+        assert_eq!(
+            &[cpu],
+            plb.cluster_snapshot.borrow().services["LogicalServer"].metrics()
+        );
+
+        // so does it at construction
+        assert!(PlacementAndLoadBalancing::new(
+            vec![create_node_desc(0)],
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![invalid],
+            vec![],
+            vec![],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_metrics() {
+        let now = OffsetDateTime::now_utc();
+        let mut plb = create_unbalanced_plb();
+        plb.scheduler.reset(now - MIN_BALANCING_INTERVAL);
+        // a new partition missing 2 replicas, only one of which is placed per refresh
+        plb.update_failover_unit(create_fu_desc(
+            Uuid::from_u128(7),
+            "LogicalServer",
+            HashMap::new(),
+            2,
+        ));
+
+        let report = plb.refresh(now).unwrap();
+        plb.update_node(create_node_desc_with_capacity(3, "CPU", 100));
+        let metrics = plb.metrics();
+        assert_eq!(1, metrics.refreshes);
+        assert_eq!(
+            (0, 60),
+            (metrics.node_loads["CPU"].min, metrics.node_loads["CPU"].max)
+        );
+        assert_eq!(1, metrics.unplaced_replicas);
+        assert_eq!(1, metrics.update_queue_depth);
+        assert_eq!(1, metrics.refresh_latency.count());
+
+        let text = plb.render_metrics();
+        let balancing_moves = report.phase(Phase::LoadBalancing).unwrap().movements.len();
+        assert!(balancing_moves > 0);
+        for line in [
+            String::from("# TYPE plb_refreshes_total counter"),
+            String::from("plb_refreshes_total 1"),
+            String::from("plb_node_load_min{metric=\"CPU\"} 0"),
+            String::from("plb_node_load_max{metric=\"CPU\"} 60"),
+            String::from("plb_unplaced_replicas 1"),
+            String::from("plb_constraint_violations{kind=\"CapacityExceeded\"} 0"),
+            String::from("plb_movements_total{phase=\"Placement\"} 1"),
+            format!(
+                "plb_movements_total{{phase=\"LoadBalancing\"}} {}",
+                balancing_moves
+            ),
+            String::from("plb_refresh_duration_seconds_bucket{le=\"+Inf\"} 1"),
+            String::from("plb_refresh_duration_seconds_count 1"),
+            String::from("# TYPE plb_update_queue_depth gauge"),
+            String::from("plb_update_queue_depth 1"),
+        ] {
+            assert!(text.lines().any(|rendered| rendered == line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_pending_movements() {
+        let mut plb = create_empty_plb();
+        plb.update_node(create_node_desc(0));
+        plb.update_node(create_node_desc(1));
+        plb.update_node(create_node_desc(2));
+        plb.update_service_type(create_service_type_desc("Worker.ISO"));
+        plb.update_service(create_service_desc("Worker.ISO", "LogicalServer"))
+            .unwrap();
+        let fu_id = Uuid::from_u128(1);
+        plb.update_failover_unit(create_fu_desc(
+            fu_id,
+            "LogicalServer",
+            create_replicas(fu_id, &[(ReplicaRole::Primary, 0)]),
+            1,
+        ));
+        let timeout = plb.config().pending_movement_timeout;
+        let initial_time = OffsetDateTime::now_utc();
+        plb.scheduler.reset(initial_time);
+        let add_replica = Movement::AddReplica {
+            fu_id,
+            node: NodeId::new(2),
+        };
+
+        let time1 = initial_time + MIN_PLACEMENT_INTERVAL;
+        let report = plb.refresh(time1).unwrap();
+        assert_eq!(vec![add_replica.clone()], plb.last_movements());
+        let first_id = report.solution_ids()[0];
+        assert_eq!(
+            vec![PendingMovement {
+                id: first_id,
+                phase: Phase::Placement,
+                movement: add_replica.clone(),
+                issued_at: time1,
+            }],
+            plb.pending_movements()
+        );
+
+        // the replica being built counts in the replica difference, so it is not proposed again
+        let time2 = time1 + MIN_PLACEMENT_INTERVAL;
+        assert!(plb.refresh(time2).unwrap().solutions().is_empty());
+        assert_eq!(1, plb.pending_movements().len());
+
+        // a failed movement is proposed again with a new id
+        plb.acknowledge_solution(first_id, MovementOutcome::Failed)
+            .unwrap();
+        assert!(plb
+            .acknowledge_solution(first_id, MovementOutcome::Failed)
+            .is_err());
+        let time3 = time2 + MIN_PLACEMENT_INTERVAL;
+        let report = plb.refresh(time3).unwrap();
+        assert_eq!(vec![add_replica.clone()], plb.last_movements());
+        let second_id = report.solution_ids()[0];
+        assert_ne!(first_id, second_id);
+
+        // an unacknowledged movement expires and is proposed again
+        let time4 = time3 + timeout;
+        let report = plb.refresh(time4).unwrap();
+        assert_eq!(
+            vec![second_id],
+            report
+                .expired_movements
+                .iter()
+                .map(|expired| expired.id)
+                .collect::<Vec<SolutionId>>()
+        );
+        assert_eq!(vec![add_replica.clone()], plb.last_movements());
+
+        // the movement is done once FM reports the failover unit with the new replica, even if it is not
+        // acknowledged
+        let mut fu_desc = plb.failover_unit_description(fu_id).unwrap();
+        fu_desc.apply_movement(&add_replica).unwrap();
+        plb.update_failover_unit(fu_desc);
+        let time5 = time4 + MIN_PLACEMENT_INTERVAL;
+        assert!(plb.refresh(time5).unwrap().solutions().is_empty());
+        assert!(plb.pending_movements().is_empty());
+
+        let metrics = plb.metrics();
+        assert_eq!(1, metrics.acknowledged_movements[&MovementOutcome::Failed]);
+        assert_eq!(1, metrics.expired_movements);
+        assert_eq!(0, metrics.pending_movements);
+    }
+
+    #[test]
+    fn test_validate_plan() {
+        let now = OffsetDateTime::now_utc();
+        let mut plb = create_unbalanced_plb();
+        plb.update_load_or_move_cost(
+            LoadOrMoveCostDescription::new(Uuid::from_u128(3)).with_primary_load("CPU", 95),
+        );
+        plb.scheduler.reset(now);
+        plb.refresh(now).unwrap();
+
+        let move_to = |fu_index: u128, from: u128, to: u128| Movement::MoveReplica {
+            fu_id: Uuid::from_u128(fu_index),
+            role: ReplicaRole::Primary,
+            from: NodeId::new(from),
+            to: NodeId::new(to),
+        };
+        let plan = vec![
+            move_to(1, 0, 1),
+            // the replica of partition 1 is already in motion
+            move_to(1, 0, 2),
+            // node 1 holds the load of partition 1 as well
+            move_to(3, 0, 1),
+            Movement::AddReplica {
+                fu_id: Uuid::from_u128(2),
+                node: NodeId::new(0),
+            },
+            Movement::SwapPrimary {
+                fu_id: Uuid::from_u128(4),
+                from: NodeId::new(0),
+                to: NodeId::new(1),
+            },
+            Movement::DropReplica {
+                fu_id: Uuid::from_u128(99),
+                node: NodeId::new(0),
+            },
+            move_to(3, 0, 2),
+        ];
+        let validation = plb.validate_plan(&plan);
+        // the singletons move make-before-break
+        assert_eq!(
+            [
+                make_before_break(Uuid::from_u128(1), 0, 1),
+                make_before_break(Uuid::from_u128(3), 0, 2),
+            ]
+            .concat(),
+            validation.accepted
+        );
+        assert_eq!(
+            vec![1, 2, 3, 4, 5],
+            validation
+                .rejected
+                .iter()
+                .map(|rejected| rejected.index)
+                .collect::<Vec<usize>>()
+        );
+        assert_eq!(
+            PlanViolation::ReplicaInMotion,
+            validation.rejected[0].violation
+        );
+        assert_eq!(
+            PlanViolation::ConstraintViolated {
+                node_id: NodeId::new(1),
+                reason: NodeRejectReason::CapacityExceeded(String::from("CPU")),
+            },
+            validation.rejected[1].violation
+        );
+        assert_eq!(
+            PlanViolation::ConstraintViolated {
+                node_id: NodeId::new(0),
+                reason: NodeRejectReason::ReplicaAlreadyOnNode,
+            },
+            validation.rejected[2].violation
+        );
+        assert!(matches!(
+            validation.rejected[3].violation,
+            PlanViolation::InvalidMovement(_)
+        ));
+        assert_eq!(
+            PlanViolation::FailoverUnitNotFound,
+            validation.rejected[4].violation
+        );
+        let err = validation.into_result().unwrap_err().to_string();
+        assert!(err.starts_with("Invalid plan, 5 movements rejected"));
+        assert_eq!(
+            make_before_break(Uuid::from_u128(1), 0, 1),
+            plb.validate_plan(&[move_to(1, 0, 1)])
+                .into_result()
+                .unwrap()
+        );
+
+        // the refresh drops the movements of a strategy that fail the validation
+        struct AddReplicaTwice;
+
+        impl SearchStrategy for AddReplicaTwice {
+            fn name(&self) -> &str {
+                "AddReplicaTwice"
+            }
+
+            fn search(
+                &mut self,
+                _view: SnapshotView<'_>,
+                _phase: Phase,
+            ) -> strategy::SearchOutcome {
+                strategy::SearchOutcome {
+                    movements: vec![
+                        Movement::AddReplica {
+                            fu_id: Uuid::from_u128(1),
+                            node: NodeId::new(1),
+                        },
+                        Movement::AddReplica {
+                            fu_id: Uuid::from_u128(1),
+                            node: NodeId::new(1),
+                        },
+                    ],
+                    statistics: None,
+                }
+            }
+        }
+        plb.set_search_strategy(Phase::Placement, Box::new(AddReplicaTwice));
+        let report = plb.refresh(now + MIN_PLACEMENT_INTERVAL).unwrap();
+        let placement = report.phase(Phase::Placement).unwrap();
+        assert_eq!(1, placement.solutions.len());
+        assert_eq!(1, placement.dropped.len());
+        assert_eq!(
+            PlanViolation::ConstraintViolated {
+                node_id: NodeId::new(1),
+                reason: NodeRejectReason::ReplicaAlreadyOnNode,
+            },
+            placement.dropped[0].violation
+        );
+    }
+
+    #[test]
+    fn test_quorum_safe_movements() {
+        let (fu1, fu2, fu3) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        let plb = PlacementAndLoadBalancing::new(
+            (0..5).map(create_node_desc).collect(),
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![create_service_desc("Worker.ISO", "LogicalServer").with_replica_set_size(3, 2)],
+            vec![
+                create_fu_desc(
+                    fu1,
+                    "LogicalServer",
+                    create_replicas(
+                        fu1,
+                        &[
+                            (ReplicaRole::Primary, 0),
+                            (ReplicaRole::Secondary, 1),
+                            (ReplicaRole::Secondary, 2),
+                        ],
+                    ),
+                    0,
+                ),
+                // a replica short of its minimum replica set size
+                create_fu_desc(
+                    fu2,
+                    "LogicalServer",
+                    create_replicas(fu2, &[(ReplicaRole::Primary, 0)]),
+                    2,
+                ),
+                create_fu_desc(
+                    fu3,
+                    "LogicalServer",
+                    create_replicas(
+                        fu3,
+                        &[(ReplicaRole::Primary, 0), (ReplicaRole::Secondary, 1)],
+                    ),
+                    1,
+                ),
+            ],
+            vec![],
+        )
+        .unwrap();
+        let move_secondary = |fu_id: Uuid, from: u128, to: u128| Movement::MoveReplica {
+            fu_id,
+            role: ReplicaRole::Secondary,
+            from: NodeId::new(from),
+            to: NodeId::new(to),
+        };
+        let drop_replica = |fu_id: Uuid, node: u128| Movement::DropReplica {
+            fu_id,
+            node: NodeId::new(node),
+        };
+
+        let validation = plb.validate_plan(&[
+            move_secondary(fu1, 1, 3),
+            // only one replica of a failover unit is in motion at a time
+            move_secondary(fu1, 2, 4),
+            drop_replica(fu1, 2),
+            // the singleton below its minimum replica set size does not move, make-before-break or not
+            Movement::MoveReplica {
+                fu_id: fu2,
+                role: ReplicaRole::Primary,
+                from: NodeId::new(0),
+                to: NodeId::new(1),
+            },
+        ]);
+        assert_eq!(vec![move_secondary(fu1, 1, 3)], validation.accepted);
+        assert_eq!(
+            vec![
+                PlanViolation::ReplicaInMotion,
+                PlanViolation::ReplicaInMotion,
+                PlanViolation::BelowMinReplicaSetSize {
+                    replicas: 1,
+                    min_replica_set_size: 2,
+                },
+            ],
+            validation
+                .rejected
+                .into_iter()
+                .map(|rejected| rejected.violation)
+                .collect::<Vec<PlanViolation>>()
+        );
+
+        // dropping a replica of partition 3 would leave fewer replicas than the minimum replica set size
+        assert!(plb.validate_plan(&[drop_replica(fu1, 2)]).is_valid());
+        assert_eq!(
+            PlanViolation::BelowMinReplicaSetSize {
+                replicas: 2,
+                min_replica_set_size: 2,
+            },
+            plb.validate_plan(&[drop_replica(fu3, 1)]).rejected[0].violation
+        );
+
+        // the replicas moved by the movements in flight stay in motion until FM acknowledges them
+        let now = OffsetDateTime::now_utc();
+        let mut plb = create_unbalanced_plb();
+        plb.set_config(PLBConfig {
+            balancing_strategy: SearchStrategyKind::Greedy,
+            ..Default::default()
+        });
+        plb.scheduler.set_last_phase_time(now, Phase::LoadBalancing);
+        plb.refresh(now + MIN_BALANCING_INTERVAL).unwrap();
+        let moved = plb.last_movements()[0].fu_id();
+        let validation = plb.validate_plan(&[Movement::MoveReplica {
+            fu_id: moved,
+            role: ReplicaRole::Primary,
+            from: NodeId::new(0),
+            to: NodeId::new(2),
+        }]);
+        assert_eq!(
+            PlanViolation::ReplicaInMotion,
+            validation.rejected[0].violation
+        );
+
+        // the replica being added failed, the rest of its make-before-break sequence is discarded
+        let id = plb.pending_movements()[0].id;
+        plb.acknowledge_solution(id, MovementOutcome::Failed)
+            .unwrap();
+        assert!(plb
+            .refresh(now + MIN_BALANCING_INTERVAL)
+            .unwrap()
+            .solutions()
+            .is_empty());
+        assert!(plb
+            .validate_plan(&[Movement::MoveReplica {
+                fu_id: moved,
+                role: ReplicaRole::Primary,
+                from: NodeId::new(0),
+                to: NodeId::new(2),
+            }])
+            .is_valid());
+    }
+
+    /// Partitions with their primary on node 0 and a secondary on each other node, the primary load being 5 times the
+    /// secondary load
+    fn create_primary_heavy_plb(node_count: u128, fu_count: u128) -> PlacementAndLoadBalancing {
+        let fu_descs = (1..=fu_count)
+            .map(|fu_index| {
+                let fu_id = Uuid::from_u128(fu_index);
+                let replicas = (0..node_count)
+                    .map(|node| match node {
+                        0 => (ReplicaRole::Primary, 0),
+                        node => (ReplicaRole::Secondary, node),
+                    })
+                    .collect::<Vec<(ReplicaRole, u128)>>();
+                create_fu_desc(fu_id, "LogicalServer", create_replicas(fu_id, &replicas), 0)
+            })
+            .collect();
+        PlacementAndLoadBalancing::new(
+            (0..node_count)
+                .map(|node| create_node_desc_with_capacity(node, "CPU", 100))
+                .collect(),
+            vec![],
+            vec![create_service_type_desc("Worker.ISO")],
+            vec![create_service_desc("Worker.ISO", "LogicalServer")
+                .with_metric(ServiceMetric::new("CPU", 1.0, 10, 2))],
+            fu_descs,
+            vec![],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_primary_swap_balancing() {
+        let now = OffsetDateTime::now_utc();
+        let balance = |plb: &mut PlacementAndLoadBalancing, config: PLBConfig| {
+            plb.set_config(config);
+            plb.scheduler.set_last_phase_time(now, Phase::LoadBalancing);
+            let solutions = plb
+                .refresh(now + MIN_BALANCING_INTERVAL)
+                .unwrap()
+                .solutions();
+            assert!(solutions
+                .iter()
+                .all(|solution| matches!(solution, Solution::SwapReplica(_))));
+            plb.last_movements().to_vec()
+        };
+        let swapped_to = |movements: &[Movement]| {
+            movements
+                .iter()
+                .map(|movement| match movement {
+                    Movement::SwapPrimary { from, to, .. } => {
+                        assert_eq!(NodeId::new(0), *from);
+                        to.id_value
+                    }
+                    movement => panic!("Unexpected movement {:?}", movement),
+                })
+                .collect::<Vec<u128>>()
+        };
+        let greedy = PLBConfig {
+            balancing_strategy: SearchStrategyKind::Greedy,
+            ..Default::default()
+        };
+        let swapping = |config: PLBConfig| PLBConfig {
+            primary_swap_balancing: true,
+            ..config
+        };
+
+        // every node already hosts a replica of every partition, no replica can move
+        let mut plb = create_primary_heavy_plb(2, 4);
+        assert!(balance(&mut plb, greedy.clone()).is_empty());
+
+        // swapping 2 of the 4 primaries balances the load without copying any data
+        let mut plb = create_primary_heavy_plb(2, 4);
+        let movements = balance(&mut plb, swapping(greedy.clone()));
+        assert_eq!(vec![1, 1], swapped_to(&movements));
+
+        // the swaps count against the moves of the greedy strategy
+        let mut plb = create_primary_heavy_plb(2, 4);
+        let movements = balance(
+            &mut plb,
+            swapping(PLBConfig {
+                greedy_max_moves: 1,
+                ..greedy.clone()
+            }),
+        );
+        assert_eq!(vec![1], swapped_to(&movements));
+
+        // the annealing search runs on the cluster with the swaps done, which is balanced already
+        let mut plb = create_primary_heavy_plb(2, 4);
+        let movements = balance(
+            &mut plb,
+            swapping(PLBConfig {
+                balancing_strategy: SearchStrategyKind::Annealing,
+                annealing: AnnealingConfig {
+                    max_iterations: 500,
+                    time_budget: time::Duration::new(60, 0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+        );
+        assert_eq!(vec![1, 1], swapped_to(&movements));
+        assert_eq!(1, plb.last_search_statistics().len());
+
+        // the secondaries on nodes 1 and 2 improve the balance as much, the promotion comparator picks node 1
+        let mut plb = create_primary_heavy_plb(3, 2);
+        let movements = balance(&mut plb, swapping(greedy));
+        assert_eq!(vec![1], swapped_to(&movements));
+    }
+
+    #[test]
+    fn test_snapshot_indexes() {
+        let mut plb = create_unbalanced_plb();
+        assert_eq!(6, plb.replicas_on_node(NodeId::new(0)).len());
+        assert_eq!(60, plb.node_loads()[&NodeId::new(0)]["CPU"]);
+
+        // a new default load applies to every failover unit of the service
+        plb.update_service(
+            create_service_desc("Worker.ISO", "LogicalServer")
+                .with_application_name("fabric:/Worker")
+                .with_metric(ServiceMetric::new("CPU", 1.0, 20, 20)),
+        )
+        .unwrap();
+        // a reported load overrides the default load
+        plb.update_load_or_move_cost(
+            LoadOrMoveCostDescription::new(Uuid::from_u128(2)).with_primary_load("CPU", 5),
+        );
+        // a moved replica is indexed on its new node
+        let fu_id = Uuid::from_u128(1);
+        plb.update_failover_unit(create_fu_desc(
+            fu_id,
+            "LogicalServer",
+            create_replicas(fu_id, &[(ReplicaRole::Primary, 1)]),
+            0,
+        ));
+        plb.refresh(OffsetDateTime::now_utc()).unwrap();
+
+        assert_eq!(
+            vec![(fu_id, ReplicaRole::Primary)],
+            plb.replicas_on_node(NodeId::new(1))
+        );
+        assert_eq!(85, plb.node_loads()[&NodeId::new(0)]["CPU"]);
+        assert_eq!(20, plb.node_loads()[&NodeId::new(1)]["CPU"]);
+        assert_eq!(
+            vec![String::from("LogicalServer")],
+            plb.services_of_application("fabric:/Worker")
+        );
+        assert_eq!(
+            vec![String::from("LogicalServer")],
+            plb.services_of_service_type("Worker.ISO")
+        );
+
+        plb.delete_failover_unit(Uuid::from_u128(2)).unwrap();
+        plb.refresh(OffsetDateTime::now_utc()).unwrap();
+        assert_eq!(80, plb.node_loads()[&NodeId::new(0)]["CPU"]);
+        assert_eq!(5, plb.failover_units_of_service("LogicalServer").len());
+
+        // without the service, its failover units no longer have any load
+        plb.delete_service("LogicalServer").unwrap();
+        plb.refresh(OffsetDateTime::now_utc()).unwrap();
+        assert!(plb.services_of_service_type("Worker.ISO").is_empty());
+        assert_eq!(
+            0,
+            plb.cluster_snapshot
+                .borrow()
+                .node_load(NodeId::new(0), "CPU")
+        );
+        assert_eq!(5, plb.failover_units_of_service("LogicalServer").len());
+        plb.cluster_snapshot.borrow().verify_indexes().unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_indexes_at_scale() {
+        let cluster = ClusterSpec {
+            node_count: 100,
+            partitions_per_service: 100,
+            seed: 7,
+            ..Default::default()
+        }
+        .generate();
+        let replica_count = cluster.replica_count();
+        let mut plb = cluster.into_plb().unwrap();
+        let total_load = |plb: &PlacementAndLoadBalancing| -> u32 {
+            plb.node_loads().values().map(|loads| loads["CPU"]).sum()
+        };
+        let initial_load = total_load(&plb);
+        assert_eq!(
+            replica_count,
+            (0..100)
+                .map(|node_id| plb.replicas_on_node(NodeId::new(node_id)).len())
+                .sum::<usize>()
+        );
+
+        // shift every replica of a service to the next node, the total load does not change
+        let service_name = plb.services_of_application("fabric:/App0")[0].clone();
+        for fu_id in plb.failover_units_of_service(&service_name) {
+            let mut fu_desc = plb.failover_unit_description(fu_id).unwrap();
+            for replica in fu_desc.replicas.values_mut() {
+                replica.location = NodeId::new((replica.location.id_value + 1) % 100);
+            }
+            plb.update_failover_unit(fu_desc);
+        }
+        plb.refresh(OffsetDateTime::now_utc()).unwrap();
+        assert_eq!(initial_load, total_load(&plb));
+
+        plb.delete_service(&service_name).unwrap();
+        plb.refresh(OffsetDateTime::now_utc()).unwrap();
+        assert!(total_load(&plb) < initial_load);
+        plb.cluster_snapshot.borrow().verify_indexes().unwrap();
+    }
+
+    /// Run with `cargo test --release -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_refresh_at_scale() {
+        let cluster = ClusterSpec {
+            node_count: 1_000,
+            partitions_per_service: 1_000,
+            unplaced_fraction: 0.01,
+            seed: 11,
+            ..Default::default()
+        }
+        .generate();
+        let start = std::time::Instant::now();
+        let mut plb = cluster.into_plb().unwrap();
+        let now = OffsetDateTime::now_utc();
+        plb.scheduler.reset(now - MIN_BALANCING_INTERVAL);
+        let report = plb.refresh(now).unwrap();
+        let elapsed = start.elapsed();
+
+        let phases: Vec<Phase> = report.phases.iter().map(|phase| phase.phase).collect();
+        assert!(phases.contains(&Phase::Placement));
+        assert!(phases.contains(&Phase::LoadBalancing));
+        plb.cluster_snapshot.borrow().verify_indexes().unwrap();
+        assert!(
+            elapsed < std::time::Duration::from_secs(30),
+            "refresh took {:?}",
+            elapsed
+        );
+    }
+
+    #[cfg(any(debug_assertions, feature = "verify-indexes"))]
+    #[test]
+    fn test_indexes_out_of_sync() {
+        let mut plb = create_unbalanced_plb();
+        let event_sink = Rc::new(InMemoryEventSink::new());
+        plb.set_event_sink(event_sink.clone());
+        plb.refresh(OffsetDateTime::now_utc()).unwrap();
+        assert!(!event_sink
+            .take()
+            .iter()
+            .any(|event| matches!(event, PlbEvent::IndexesOutOfSync { .. })));
+
+        plb.cluster_snapshot.borrow_mut().indexes = index::SnapshotIndexes::default();
+        plb.refresh(OffsetDateTime::now_utc()).unwrap();
+        assert!(event_sink.take().contains(&PlbEvent::IndexesOutOfSync {
+            reason: String::from("The node replicas index of the cluster snapshot is out of sync"),
+        }));
+        plb.cluster_snapshot.borrow().verify_indexes().unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_record_and_replay() {
+        let initial_time = OffsetDateTime::now_utc();
+        let path = std::env::temp_dir().join(format!("plb-trace-{}.jsonl", Uuid::new_v4()));
+        let mut plb = create_unbalanced_plb();
+        plb.set_config(PLBConfig {
+            balancing_strategy: SearchStrategyKind::Annealing,
+            annealing: AnnealingConfig {
+                max_iterations: 500,
+                time_budget: time::Duration::new(60, 0),
+                seed: 7,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        plb.scheduler
+            .set_last_phase_time(initial_time, Phase::LoadBalancing);
+        plb.update_node(create_node_desc_with_capacity(3, "CPU", 100));
+        plb.start_recording(&path).unwrap();
+
+        let fu_id = Uuid::from_u128(7);
+        plb.update_failover_unit(create_fu_desc(
+            fu_id,
+            "LogicalServer",
+            create_replicas(fu_id, &[(ReplicaRole::Primary, 1)]),
+            1,
+        ));
+        plb.update_load_or_move_cost(
+            LoadOrMoveCostDescription::new(fu_id).with_primary_load("CPU", 25),
+        );
+        plb.delete_failover_unit(Uuid::from_u128(6)).unwrap();
+        let mut solution_count = 0;
+        for second in [3, 6, 10] {
+            solution_count += plb
+                .refresh(initial_time + time::Duration::new(second, 0))
+                .unwrap()
+                .solutions()
+                .len();
+        }
+        assert!(solution_count > 0);
+
+        // an acknowledgment racing with the expiry of its solution is rejected and left out of the trace
+        let pending = plb.pending_movements();
+        plb.acknowledge_solution(pending[0].id, MovementOutcome::Failed)
+            .unwrap();
+        let expired = pending.last().unwrap().id;
+        let timeout = plb.config().pending_movement_timeout;
+        plb.refresh(initial_time + time::Duration::new(10, 0) + timeout)
+            .unwrap();
+        assert!(plb
+            .acknowledge_solution(expired, MovementOutcome::Succeeded)
+            .is_err());
+        plb.stop_recording().unwrap();
+
+        let report = PlacementAndLoadBalancing::replay_trace(&path).unwrap();
+        assert_eq!(4, report.refreshes);
+        assert!(report.is_identical());
+
+        // a trace whose solutions were not produced by the engine does not replay identically
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(
+            &path,
+            content.replace("\"solutions\":[{", "\"solutions\":[{\"AddReplica\":\"\"},{"),
+        )
+        .unwrap();
+        let report = PlacementAndLoadBalancing::replay_trace(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!report.is_identical());
+    }
 }
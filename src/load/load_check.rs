@@ -0,0 +1,76 @@
+//! This module checks the loads reported for the failover units before they are applied to the cluster snapshot.
+//!
+//! A reported load above the maximum load of its metric is clamped to the maximum, and a load more than the spike
+//! factor of the configuration times the previous load reported for the same replica role is flagged as a spike. Both
+//! are surfaced as events, the spike being applied as reported.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    events::PlbEvent, failoverunit::failover_unit::ReplicaRole,
+    load::load_or_move_cost::LoadOrMoveCost, ClusterSnapshot,
+};
+
+impl ClusterSnapshot {
+    /// Clamps the loads of the report to the maximum load of their metric, and returns the events flagging the clamped
+    /// loads and the spikes. A `spike_factor` of 0 disables the spike detection.
+    pub(crate) fn check_load_report(
+        &self,
+        load: &mut LoadOrMoveCost,
+        spike_factor: f64,
+    ) -> Vec<PlbEvent> {
+        let fu_id = load.id();
+        let maximum_loads = self
+            .failover_units
+            .get(&fu_id)
+            .and_then(|fu| self.service_of(fu))
+            .map(|service| {
+                self.service_metrics(service)
+                    .map(|metric| (String::from(metric.name()), metric.maximum_load()))
+                    .collect::<BTreeMap<String, u32>>()
+            })
+            .unwrap_or_default();
+        let previous = self.loads.get(&fu_id);
+        let load_desc = &mut load.load_description;
+        let mut events = vec![];
+        for (role, loads) in [
+            (ReplicaRole::Primary, &mut load_desc.primary_loads),
+            (ReplicaRole::Secondary, &mut load_desc.secondary_loads),
+        ] {
+            let mut metric_names = loads.keys().cloned().collect::<Vec<String>>();
+            metric_names.sort();
+            for metric_name in metric_names {
+                let reported = loads[&metric_name];
+                let maximum = maximum_loads.get(&metric_name).copied().unwrap_or(u32::MAX);
+                let load = reported.min(maximum);
+                if reported > maximum {
+                    loads.insert(metric_name.clone(), load);
+                    events.push(PlbEvent::LoadClamped {
+                        fu_id,
+                        metric_name: metric_name.clone(),
+                        role,
+                        reported,
+                        maximum,
+                    });
+                }
+                let previous = previous
+                    .and_then(|previous| previous.load(&metric_name, role))
+                    .unwrap_or_default();
+                if spike_factor > 0.0
+                    && previous > 0
+                    && load as f64 > previous as f64 * spike_factor
+                {
+                    events.push(PlbEvent::LoadSpike {
+                        fu_id,
+                        metric_name,
+                        role,
+                        previous,
+                        load,
+                    });
+                }
+            }
+        }
+
+        events
+    }
+}
@@ -1,5 +1,10 @@
+use std::collections::HashMap;
+
 use uuid::Uuid;
 
+use crate::failoverunit::failover_unit::ReplicaRole;
+
+#[derive(Clone)]
 pub struct LoadOrMoveCost {
     pub(crate) load_description: LoadOrMoveCostDescription,
 }
@@ -8,8 +13,43 @@ impl LoadOrMoveCost {
     pub fn id(&self) -> Uuid {
         self.load_description.fu_id
     }
+
+    /// Returns the reported load of a replica with the given role for the metric, if any was reported
+    pub fn load(&self, metric_name: &str, role: ReplicaRole) -> Option<u32> {
+        match role {
+            ReplicaRole::Primary => self.load_description.primary_loads.get(metric_name),
+            _ => self.load_description.secondary_loads.get(metric_name),
+        }
+        .copied()
+    }
 }
 
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct LoadOrMoveCostDescription {
     pub(crate) fu_id: Uuid,
+    /// Load reported by the primary replica, keyed by metric name
+    pub(crate) primary_loads: HashMap<String, u32>,
+    /// Load reported by the secondary replicas, keyed by metric name
+    pub(crate) secondary_loads: HashMap<String, u32>,
+}
+
+impl LoadOrMoveCostDescription {
+    pub fn new(fu_id: Uuid) -> Self {
+        LoadOrMoveCostDescription {
+            fu_id,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_primary_load(mut self, metric_name: &str, load: u32) -> Self {
+        self.primary_loads.insert(String::from(metric_name), load);
+        self
+    }
+
+    pub fn with_secondary_load(mut self, metric_name: &str, load: u32) -> Self {
+        self.secondary_loads.insert(String::from(metric_name), load);
+        self
+    }
 }
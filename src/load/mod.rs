@@ -1 +1,2 @@
+pub(crate) mod load_check;
 pub mod load_or_move_cost;
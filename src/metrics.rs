@@ -0,0 +1,427 @@
+//! This module contains the counters and gauges maintained by the PLB engine for monitoring, and their rendering in
+//! the Prometheus text exposition format.
+//!
+//! The metrics are updated at the end of every refresh:
+//!     - gauges describe the cluster snapshot: the spread of the node loads per metric, the replicas left unplaced,
+//!       the constraint violations per kind and the movements in flight
+//!     - counters and the latency histogram accumulate over the refreshes
+//!
+//! Serving the rendered text over HTTP is left to the caller.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{
+    constraint::ConstraintViolation,
+    node::node_id::NodeId,
+    pending::{MovementOutcome, PendingMovements},
+    report::RefreshReport,
+    scheduler::Phase,
+    solver::Movement,
+    ClusterSnapshot,
+};
+
+/// Upper bounds of the refresh latency histogram buckets, in seconds
+const REFRESH_LATENCY_BUCKETS: [f64; 10] =
+    [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// Spread of the loads of the nodes that are up, for a metric
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoadSpread {
+    pub min: u32,
+    pub max: u32,
+    /// Population standard deviation
+    pub std_dev: f64,
+}
+
+impl LoadSpread {
+    fn new(loads: &[u32]) -> Self {
+        if loads.is_empty() {
+            return LoadSpread::default();
+        }
+        let count = loads.len() as f64;
+        let mean = loads.iter().map(|load| f64::from(*load)).sum::<f64>() / count;
+        let variance = loads
+            .iter()
+            .map(|load| (f64::from(*load) - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        LoadSpread {
+            min: loads.iter().copied().min().unwrap_or_default(),
+            max: loads.iter().copied().max().unwrap_or_default(),
+            std_dev: variance.sqrt(),
+        }
+    }
+}
+
+/// A histogram with fixed buckets, similar to a Prometheus histogram
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    /// Number of observations per bucket, not cumulative. The last bucket holds the observations above every bound.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &[f64]) -> Self {
+        Histogram {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// The number of observations less than or equal to each bound, followed by the total number of observations
+    pub fn cumulative_counts(&self) -> Vec<(f64, u64)> {
+        self.bounds
+            .iter()
+            .copied()
+            .chain([f64::INFINITY])
+            .zip(self.counts.iter().scan(0, |total, count| {
+                *total += count;
+                Some(*total)
+            }))
+            .collect()
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+/// The metrics of the PLB engine
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlbMetrics {
+    /// Number of refreshes run
+    pub refreshes: u64,
+    /// Spread of the node loads per metric, as of the last refresh
+    pub node_loads: BTreeMap<String, LoadSpread>,
+    /// Replicas missing from their failover unit that are neither placed by the last refresh nor in flight
+    pub unplaced_replicas: u64,
+    /// Constraint violations per kind, as of the last refresh
+    pub violations: BTreeMap<&'static str, u64>,
+    /// Movements generated per phase, over all the refreshes
+    pub movements: BTreeMap<Phase, u64>,
+    /// Movements handed to FM and not acknowledged yet
+    pub pending_movements: u64,
+    /// Pending movements acknowledged by FM, per outcome
+    pub acknowledged_movements: BTreeMap<MovementOutcome, u64>,
+    /// Pending movements FM did not acknowledge in time
+    pub expired_movements: u64,
+    /// Wall-clock duration of the refreshes
+    pub refresh_latency: Histogram,
+    /// Updates waiting in the update queue for the next refresh
+    pub update_queue_depth: u64,
+}
+
+impl Default for PlbMetrics {
+    fn default() -> Self {
+        PlbMetrics {
+            refreshes: 0,
+            node_loads: BTreeMap::new(),
+            unplaced_replicas: 0,
+            violations: ConstraintViolation::KINDS
+                .into_iter()
+                .map(|kind| (kind, 0))
+                .collect(),
+            movements: Phase::ALL.into_iter().map(|phase| (phase, 0)).collect(),
+            pending_movements: 0,
+            acknowledged_movements: [MovementOutcome::Succeeded, MovementOutcome::Failed]
+                .into_iter()
+                .map(|outcome| (outcome, 0))
+                .collect(),
+            expired_movements: 0,
+            refresh_latency: Histogram::new(&REFRESH_LATENCY_BUCKETS),
+            update_queue_depth: 0,
+        }
+    }
+}
+
+impl PlbMetrics {
+    /// Updates the metrics with the outcome of a refresh, the cluster snapshot it ran on and the movements in flight
+    /// once it is done
+    pub(crate) fn observe_refresh(
+        &mut self,
+        snapshot: &ClusterSnapshot,
+        report: &RefreshReport,
+        pending: &PendingMovements,
+    ) {
+        self.refreshes += 1;
+
+        let up_nodes = snapshot
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.is_up())
+            .map(|(node_id, _)| *node_id)
+            .collect::<Vec<NodeId>>();
+        self.node_loads = snapshot
+            .metric_names()
+            .into_iter()
+            .map(|metric_name| {
+                let loads = up_nodes
+                    .iter()
+                    .map(|node_id| snapshot.node_load(*node_id, &metric_name))
+                    .collect::<Vec<u32>>();
+                (metric_name, LoadSpread::new(&loads))
+            })
+            .collect();
+
+        let mut missing_replicas = snapshot
+            .failover_units
+            .iter()
+            .filter(|(_, fu)| fu.replia_diff() > 0)
+            .map(|(fu_id, fu)| (*fu_id, fu.replia_diff().unsigned_abs() as u64))
+            .collect::<BTreeMap<_, _>>();
+        // the replicas placed by the refresh are pending as well
+        for pending_movement in pending.iter() {
+            if let Movement::AddReplica { fu_id, .. } = pending_movement.movement {
+                if let Some(missing) = missing_replicas.get_mut(&fu_id) {
+                    *missing = missing.saturating_sub(1);
+                }
+            }
+        }
+        self.unplaced_replicas = missing_replicas.values().sum();
+        self.pending_movements = pending.len() as u64;
+        self.expired_movements += report.expired_movements.len() as u64;
+
+        for count in self.violations.values_mut() {
+            *count = 0;
+        }
+        for violation in &report.violations {
+            *self.violations.entry(violation.kind()).or_default() += 1;
+        }
+
+        for phase in &report.phases {
+            *self.movements.entry(phase.phase).or_default() += phase.movements.len() as u64;
+        }
+        for follow_up in &report.follow_ups {
+            *self.movements.entry(follow_up.phase).or_default() += 1;
+        }
+        self.refresh_latency.observe(report.duration.as_secs_f64());
+    }
+
+    pub(crate) fn observe_acknowledgment(
+        &mut self,
+        outcome: MovementOutcome,
+        pending: &PendingMovements,
+    ) {
+        *self.acknowledged_movements.entry(outcome).or_default() += 1;
+        self.pending_movements = pending.len() as u64;
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        write_header(
+            &mut out,
+            "plb_refreshes_total",
+            "counter",
+            "Number of PLB refreshes run",
+        );
+        writeln!(out, "plb_refreshes_total {}", self.refreshes).unwrap();
+
+        for (name, help, value) in [
+            (
+                "plb_node_load_min",
+                "Minimum load of the nodes that are up, per metric",
+                (|spread: &LoadSpread| f64::from(spread.min)) as fn(&LoadSpread) -> f64,
+            ),
+            (
+                "plb_node_load_max",
+                "Maximum load of the nodes that are up, per metric",
+                |spread| f64::from(spread.max),
+            ),
+            (
+                "plb_node_load_std_dev",
+                "Standard deviation of the loads of the nodes that are up, per metric",
+                |spread| spread.std_dev,
+            ),
+        ] {
+            write_header(&mut out, name, "gauge", help);
+            for (metric_name, spread) in &self.node_loads {
+                writeln!(
+                    out,
+                    "{}{{metric=\"{}\"}} {}",
+                    name,
+                    escape_label_value(metric_name),
+                    value(spread)
+                )
+                .unwrap();
+            }
+        }
+
+        write_header(
+            &mut out,
+            "plb_unplaced_replicas",
+            "gauge",
+            "Replicas missing from their failover unit that the last refresh did not place",
+        );
+        writeln!(out, "plb_unplaced_replicas {}", self.unplaced_replicas).unwrap();
+
+        write_header(
+            &mut out,
+            "plb_constraint_violations",
+            "gauge",
+            "Hard constraints broken by the cluster snapshot, per kind",
+        );
+        for (kind, count) in &self.violations {
+            writeln!(
+                out,
+                "plb_constraint_violations{{kind=\"{}\"}} {}",
+                kind, count
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut out,
+            "plb_movements_total",
+            "counter",
+            "Movements generated, per phase",
+        );
+        for (phase, count) in &self.movements {
+            writeln!(
+                out,
+                "plb_movements_total{{phase=\"{:?}\"}} {}",
+                phase, count
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut out,
+            "plb_pending_movements",
+            "gauge",
+            "Movements handed to FM and not acknowledged yet",
+        );
+        writeln!(out, "plb_pending_movements {}", self.pending_movements).unwrap();
+
+        write_header(
+            &mut out,
+            "plb_acknowledged_movements_total",
+            "counter",
+            "Pending movements acknowledged by FM, per outcome",
+        );
+        for (outcome, count) in &self.acknowledged_movements {
+            writeln!(
+                out,
+                "plb_acknowledged_movements_total{{outcome=\"{:?}\"}} {}",
+                outcome, count
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut out,
+            "plb_expired_movements_total",
+            "counter",
+            "Pending movements FM did not acknowledge in time",
+        );
+        writeln!(
+            out,
+            "plb_expired_movements_total {}",
+            self.expired_movements
+        )
+        .unwrap();
+
+        write_header(
+            &mut out,
+            "plb_refresh_duration_seconds",
+            "histogram",
+            "Wall-clock duration of the PLB refreshes",
+        );
+        for (bound, count) in self.refresh_latency.cumulative_counts() {
+            let bound = if bound.is_infinite() {
+                String::from("+Inf")
+            } else {
+                bound.to_string()
+            };
+            writeln!(
+                out,
+                "plb_refresh_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, count
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "plb_refresh_duration_seconds_sum {}",
+            self.refresh_latency.sum()
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "plb_refresh_duration_seconds_count {}",
+            self.refresh_latency.count()
+        )
+        .unwrap();
+
+        write_header(
+            &mut out,
+            "plb_update_queue_depth",
+            "gauge",
+            "Updates waiting in the update queue for the next refresh",
+        );
+        writeln!(out, "plb_update_queue_depth {}", self.update_queue_depth).unwrap();
+
+        out
+    }
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+/// Escapes the backslashes, double quotes and line feeds of a label value
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::new(&[0.1, 1.0]);
+        for value in [0.05, 0.1, 0.5, 2.0] {
+            histogram.observe(value);
+        }
+        assert_eq!(
+            vec![(0.1, 2), (1.0, 3), (f64::INFINITY, 4)],
+            histogram.cumulative_counts()
+        );
+        assert_eq!(4, histogram.count());
+        assert!((histogram.sum() - 2.65).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_load_spread() {
+        assert_eq!(LoadSpread::default(), LoadSpread::new(&[]));
+        let spread = LoadSpread::new(&[0, 10, 20]);
+        assert_eq!((0, 20), (spread.min, spread.max));
+        assert!((spread.std_dev - (200.0f64 / 3.0).sqrt()).abs() < 1e-9);
+        assert_eq!("a\\\"b\\\\c\\n", escape_label_value("a\"b\\c\n"));
+    }
+}
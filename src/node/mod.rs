@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod node;
 pub mod node_description;
 pub mod node_id;
@@ -1,7 +1,11 @@
 //! Represents a node in a Service Fabric cluster.
 
-use super::{node_description::NodeDescription, node_id::NodeId};
+use super::{
+    node_description::{NodeDeactivationIntent, NodeDescription},
+    node_id::NodeId,
+};
 
+#[derive(Clone)]
 pub struct Node {
     pub(crate) node_description: NodeDescription,
 }
@@ -10,4 +14,37 @@ impl Node {
     pub fn node_id(&self) -> NodeId {
         self.node_description.node_instance.id
     }
+
+    pub fn is_up(&self) -> bool {
+        self.node_description.is_up
+    }
+
+    pub fn deactivation_intent(&self) -> NodeDeactivationIntent {
+        self.node_description.deactivation_intent
+    }
+
+    pub fn upgrade_domain(&self) -> &str {
+        &self.node_description.upgrade_domain
+    }
+
+    pub fn fault_domain(&self) -> &str {
+        &self.node_description.fault_domain
+    }
+
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.node_description
+            .properties
+            .get(name)
+            .map(|value| value.as_str())
+    }
+
+    /// Returns the capacity of the node for the given metric, or None if the node is not capacity-bound on it
+    pub fn capacity(&self, metric_name: &str) -> Option<u32> {
+        self.node_description.capacities.get(metric_name).copied()
+    }
+
+    /// New replicas can only be placed on nodes that are up and not being deactivated
+    pub fn accepts_new_replicas(&self) -> bool {
+        self.is_up() && self.deactivation_intent() == NodeDeactivationIntent::None
+    }
 }
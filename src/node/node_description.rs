@@ -1,29 +1,68 @@
 //! The internal state of a [Node]
 
-
-
 use super::node_instance::NodeInstance;
 use std::collections::HashMap;
 
-type DomainId = String;
+pub type DomainId = String;
 
+/// The reason a node is being deactivated by the cluster manager. Similar to the C++ implementation, the intent
+/// decides how aggressively PLB has to move replicas away from the node:
+///     - Pause: no new replicas are placed on the node, existing replicas stay
+///     - Restart: no new replicas are placed on the node and primaries are swapped out
+///     - RemoveData / RemoveNode: the node is drained completely
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NodeDeactivationIntent {
+    #[default]
+    None,
+    Pause,
+    Restart,
+    RemoveData,
+    RemoveNode,
+}
+
+impl NodeDeactivationIntent {
+    /// Whether the replicas hosted on the node have to be moved away from it
+    pub fn requires_drain(&self) -> bool {
+        matches!(
+            self,
+            NodeDeactivationIntent::RemoveData | NodeDeactivationIntent::RemoveNode
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct NodeDescription {
     pub(crate) node_instance: NodeInstance,
     pub(crate) is_up: bool,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     pub(crate) capacity_ratios: HashMap<String, u32>,
     pub(crate) capacities: HashMap<String, u32>,
+    pub(crate) deactivation_intent: NodeDeactivationIntent,
+    pub(crate) upgrade_domain: DomainId,
+    pub(crate) fault_domain: DomainId,
+    /// Node properties referenced by the placement constraints of the services
+    pub(crate) properties: HashMap<String, String>,
 }
 
-impl NodeDescription {
-    pub fn default() -> NodeDescription {
+impl Default for NodeDescription {
+    fn default() -> NodeDescription {
         NodeDescription {
             node_instance: NodeInstance::default(),
             is_up: true,
             capacity_ratios: HashMap::new(),
             capacities: HashMap::new(),
+            deactivation_intent: NodeDeactivationIntent::None,
+            upgrade_domain: DomainId::new(),
+            fault_domain: DomainId::new(),
+            properties: HashMap::new(),
         }
     }
+}
 
+impl NodeDescription {
     pub fn new(
         node_instance: NodeInstance,
         is_up: bool,
@@ -35,6 +74,32 @@ impl NodeDescription {
             is_up,
             capacity_ratios,
             capacities,
+            deactivation_intent: NodeDeactivationIntent::None,
+            upgrade_domain: DomainId::new(),
+            fault_domain: DomainId::new(),
+            properties: HashMap::new(),
         }
     }
+
+    /// Marks the node as being deactivated with the given intent
+    pub fn with_deactivation_intent(mut self, intent: NodeDeactivationIntent) -> NodeDescription {
+        self.deactivation_intent = intent;
+        self
+    }
+
+    pub fn with_upgrade_domain(mut self, upgrade_domain: &str) -> NodeDescription {
+        self.upgrade_domain = DomainId::from(upgrade_domain);
+        self
+    }
+
+    pub fn with_fault_domain(mut self, fault_domain: &str) -> NodeDescription {
+        self.fault_domain = DomainId::from(fault_domain);
+        self
+    }
+
+    pub fn with_property(mut self, name: &str, value: &str) -> NodeDescription {
+        self.properties
+            .insert(String::from(name), String::from(value));
+        self
+    }
 }
@@ -2,18 +2,14 @@
 
 use std::fmt;
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct NodeId {
     pub(crate) id_value: u128,
 }
 
 impl NodeId {
-    pub fn default() -> NodeId {
-        NodeId {
-            id_value: u128::default(),
-        }
-    }
-
     pub fn new(id_value: u128) -> NodeId {
         NodeId { id_value }
     }
@@ -27,6 +23,12 @@ impl fmt::Debug for NodeId {
     }
 }
 
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id_value)
+    }
+}
+
 impl Iterator for NodeId {
     type Item = NodeId;
 
@@ -1,19 +1,12 @@
 use super::node_id::NodeId;
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NodeInstance {
     pub(crate) id: NodeId,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     pub(crate) instance_id: u64,
 }
 
-impl Default for NodeInstance {
-    fn default() -> NodeInstance {
-        NodeInstance {
-            id: NodeId::default(),
-            instance_id: 0,
-        }
-    }
-}
-
 impl NodeInstance {
     pub fn new(id: NodeId, instance_id: u64) -> NodeInstance {
         NodeInstance { id, instance_id }
@@ -0,0 +1,259 @@
+//! This module tracks the movements handed to FM that are still in flight.
+//!
+//! Every solution generated by a refresh is given an id and kept as a pending movement until FM acknowledges it,
+//! the failover unit reported by FM already reflects it, or it expires. While a movement is pending, the searches
+//! run on the cluster snapshot as if it was done: its replicas count in the node loads and in the replica difference
+//! of its failover unit, so the next phases do not propose it again.
+//!
+//! The movements of a sequence that must not start before the previous one is done, like the drop of a make-before-break
+//! move, are follow-ups of the pending movement before them: they are only handed to FM by the first refresh after FM
+//! acknowledged that movement as succeeded, or reported its failover unit with the movement done. A follow-up of a
+//! movement that failed, expired or no longer applies to its failover unit is discarded, and a released follow-up is
+//! validated again against the failover units reported by FM before it is handed out.
+
+use std::collections::BTreeMap;
+
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::{
+    failoverunit::failover_unit::FailoverUnit, scheduler::Phase, solver::Movement, ClusterSnapshot,
+};
+
+/// Identifies a solution generated by PLB, for FM to acknowledge it
+pub type SolutionId = u64;
+
+/// How FM carried out a solution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MovementOutcome {
+    /// The movement is done. FM reports the new state of the failover unit with an update.
+    Succeeded,
+    /// The movement was abandoned, PLB may propose it again
+    Failed,
+}
+
+/// A movement handed to FM and not acknowledged yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PendingMovement {
+    pub id: SolutionId,
+    pub phase: Phase,
+    pub movement: Movement,
+    /// The time of the refresh that generated the movement
+    pub issued_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct PendingMovements {
+    next_id: SolutionId,
+    movements: BTreeMap<SolutionId, PendingMovement>,
+    /// The movements to hand to FM once the pending movement succeeded, in order
+    #[cfg_attr(feature = "serde", serde(default))]
+    follow_ups: BTreeMap<SolutionId, Vec<Movement>>,
+    /// The follow-ups of the movements that succeeded, to issue at the next refresh
+    #[cfg_attr(feature = "serde", serde(default))]
+    released: Vec<(Phase, Vec<Movement>)>,
+}
+
+impl PendingMovements {
+    /// Starts tracking the movement, returns the id of its solution
+    pub(crate) fn issue(
+        &mut self,
+        phase: Phase,
+        movement: Movement,
+        now: OffsetDateTime,
+    ) -> SolutionId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.movements.insert(
+            id,
+            PendingMovement {
+                id,
+                phase,
+                movement,
+                issued_at: now,
+            },
+        );
+        id
+    }
+
+    /// Makes the movements follow-ups of the pending movement
+    pub(crate) fn defer(&mut self, id: SolutionId, movements: Vec<Movement>) {
+        if !movements.is_empty() {
+            self.follow_ups.entry(id).or_default().extend(movements);
+        }
+    }
+
+    /// Discards the follow-ups of the pending movement, returns them
+    pub(crate) fn drop_follow_ups(&mut self, id: SolutionId) -> Vec<Movement> {
+        self.follow_ups.remove(&id).unwrap_or_default()
+    }
+
+    /// Stops tracking the movement, discarding its follow-ups
+    pub(crate) fn remove(&mut self, id: SolutionId) -> Option<PendingMovement> {
+        self.follow_ups.remove(&id);
+        self.movements.remove(&id)
+    }
+
+    /// Stops tracking the movement once it succeeded, its follow-ups are issued by the next refresh
+    pub(crate) fn succeed(&mut self, id: SolutionId) -> Option<PendingMovement> {
+        let pending_movement = self.movements.remove(&id)?;
+        if let Some(follow_ups) = self.follow_ups.remove(&id) {
+            self.released.push((pending_movement.phase, follow_ups));
+        }
+        Some(pending_movement)
+    }
+
+    /// Takes the follow-ups of the movements that succeeded, for the refresh to validate and issue them
+    pub(crate) fn take_released(&mut self) -> Vec<(Phase, Vec<Movement>)> {
+        std::mem::take(&mut self.released)
+    }
+
+    /// Issues the first movement of the sequence, the next ones becoming its follow-ups. Returns the issued movement.
+    pub(crate) fn issue_sequence(
+        &mut self,
+        phase: Phase,
+        mut movements: Vec<Movement>,
+        now: OffsetDateTime,
+    ) -> Option<PendingMovement> {
+        if movements.is_empty() {
+            return None;
+        }
+        let id = self.issue(phase, movements.remove(0), now);
+        self.defer(id, movements);
+        self.movements.get(&id).cloned()
+    }
+
+    /// Stops tracking the movements issued more than `timeout` before now, returns them
+    pub(crate) fn expire(
+        &mut self,
+        now: OffsetDateTime,
+        timeout: Duration,
+    ) -> Vec<PendingMovement> {
+        let expired = self
+            .movements
+            .values()
+            .filter(|pending| now - pending.issued_at >= timeout)
+            .map(|pending| pending.id)
+            .collect::<Vec<SolutionId>>();
+        expired
+            .into_iter()
+            .filter_map(|id| self.remove(id))
+            .collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.movements.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &PendingMovement> {
+        self.movements.values()
+    }
+
+    /// The follow-ups not issued yet, per pending movement or, once released, without one
+    fn sequences(&self) -> impl Iterator<Item = (Option<SolutionId>, &Vec<Movement>)> {
+        self.follow_ups
+            .iter()
+            .map(|(id, movements)| (Some(*id), movements))
+            .chain(self.released.iter().map(|(_, movements)| (None, movements)))
+    }
+
+    /// The follow-ups not issued yet, in order for each failover unit
+    pub(crate) fn follow_ups(&self) -> impl Iterator<Item = &Movement> {
+        self.follow_ups
+            .values()
+            .chain(self.released.iter().map(|(_, movements)| movements))
+            .flatten()
+    }
+}
+
+/// The outcome of [ClusterSnapshot::apply_pending_movements]
+#[derive(Debug, Default)]
+pub(crate) struct PendingProjection {
+    /// The failover units as they were, to restore them once the search is done
+    pub(crate) originals: Vec<FailoverUnit>,
+    /// The movements that no longer apply because the failover unit reported by FM already reflects them
+    pub(crate) reflected: Vec<SolutionId>,
+    /// The movements that neither apply to their failover unit nor are reflected by it, or whose failover unit is gone
+    pub(crate) inapplicable: Vec<SolutionId>,
+    /// The movements whose follow-ups do not apply to the failover unit once the movement is done
+    pub(crate) inapplicable_follow_ups: Vec<SolutionId>,
+}
+
+impl ClusterSnapshot {
+    /// Applies the pending movements to their failover units, in the order they were issued, then their follow-ups.
+    /// The movements that do not apply are sorted out between the ones the failover unit already reflects and the
+    /// ones that are no longer applicable.
+    pub(crate) fn apply_pending_movements(
+        &mut self,
+        pending: &PendingMovements,
+    ) -> PendingProjection {
+        let mut projection = PendingProjection::default();
+        let mut projected = BTreeMap::<Uuid, FailoverUnit>::new();
+        for pending_movement in pending.iter() {
+            let fu_id = pending_movement.movement.fu_id();
+            let fu = match projected.get_mut(&fu_id) {
+                Some(fu) => fu,
+                None => match self.failover_units.get(&fu_id) {
+                    Some(fu) => projected.entry(fu_id).or_insert_with(|| fu.clone()),
+                    None => {
+                        projection.inapplicable.push(pending_movement.id);
+                        continue;
+                    }
+                },
+            };
+            let fu_desc = &mut fu.failover_unit_description;
+            if fu_desc.apply_movement(&pending_movement.movement).is_ok() {
+                continue;
+            }
+            if fu_desc.reflects_movement(&pending_movement.movement) {
+                projection.reflected.push(pending_movement.id);
+            } else {
+                projection.inapplicable.push(pending_movement.id);
+            }
+        }
+        // the follow-ups of a movement are projected together, or not at all if one of them does not apply. The ones
+        // of a movement that did not apply are discarded with it, and the released ones are validated again before
+        // they are issued.
+        for (id, movements) in pending.sequences() {
+            if id.is_some_and(|id| projection.inapplicable.contains(&id)) {
+                continue;
+            }
+            let Some(fu_id) = movements.first().map(Movement::fu_id) else {
+                continue;
+            };
+            let Some(fu) = projected
+                .get(&fu_id)
+                .or_else(|| self.failover_units.get(&fu_id))
+            else {
+                continue;
+            };
+            let mut fu = fu.clone();
+            let applied = movements.iter().all(|movement| {
+                fu.failover_unit_description
+                    .apply_movement(movement)
+                    .is_ok()
+            });
+            if applied {
+                projected.insert(fu_id, fu);
+            } else if let Some(id) = id {
+                projection.inapplicable_follow_ups.push(id);
+            }
+        }
+
+        projection.originals = projected
+            .into_values()
+            .filter_map(|fu| self.insert_failover_unit(fu))
+            .collect();
+        projection
+    }
+
+    /// Puts back the failover units replaced by [ClusterSnapshot::apply_pending_movements]
+    pub(crate) fn restore_failover_units(&mut self, originals: Vec<FailoverUnit>) {
+        for fu in originals {
+            self.insert_failover_unit(fu);
+        }
+    }
+}
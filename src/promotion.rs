@@ -0,0 +1,38 @@
+//! Compare-node-for-promotion (CNFP) logic used to pick the secondary replica that becomes the new primary
+
+use std::cmp::Ordering;
+
+use crate::{
+    failoverunit::failover_unit::{FailoverUnit, ReplicaRole},
+    node::node_id::NodeId,
+    ClusterSnapshot,
+};
+
+/// Given 2 candidate nodes hosting secondary replicas, returns which one is preferred for promoting to primary.
+/// `Ordering::Less` means Node 1 is preferred.
+///
+/// The default CNFP algorithm is Dummy PLB, which prefers the node with the smaller node id
+pub(crate) fn compare_node_for_promotion(node1: NodeId, node2: NodeId) -> Ordering {
+    // NodeId also implements Iterator, so the Ord implementation has to be called explicitly
+    Ord::cmp(&node1, &node2)
+}
+
+/// Selects the node of the secondary replica that should become the new primary of the failover unit.
+/// Only secondaries on nodes that are up, not being deactivated and not excluded by the caller are considered.
+pub(crate) fn select_new_primary(
+    snapshot: &ClusterSnapshot,
+    fu: &FailoverUnit,
+    excluded: impl Fn(NodeId) -> bool,
+) -> Option<NodeId> {
+    fu.active_replicas()
+        .filter(|replica| replica.role() == ReplicaRole::Secondary)
+        .map(|replica| replica.location())
+        .filter(|node_id| !excluded(*node_id))
+        .filter(|node_id| {
+            snapshot
+                .nodes
+                .get(node_id)
+                .is_some_and(|node| node.accepts_new_replicas())
+        })
+        .min_by(|node1, node2| compare_node_for_promotion(*node1, *node2))
+}
@@ -0,0 +1,152 @@
+//! This module contains the report of a PLB refresh: what was applied to the cluster snapshot, which phases ran or
+//! were skipped, how long they took and what they generated.
+
+use std::time::Duration;
+
+use time::OffsetDateTime;
+
+use crate::{
+    annealing::SearchStatistics,
+    constraint::ConstraintViolation,
+    pending::{PendingMovement, SolutionId},
+    scheduler::Phase,
+    score::ClusterScore,
+    solver::{Movement, Solution},
+    validation::RejectedMovement,
+};
+
+/// Number of updates applied to the cluster snapshot at the start of a refresh, per entity kind
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateCounts {
+    pub nodes: usize,
+    pub applications: usize,
+    pub service_types: usize,
+    pub services: usize,
+    pub failover_units: usize,
+    pub loads: usize,
+    /// Upgrade domains started and upgrades completed
+    pub upgrades: usize,
+    /// Entities removed, of any kind
+    pub deletions: usize,
+}
+
+impl UpdateCounts {
+    pub fn total(&self) -> usize {
+        self.nodes
+            + self.applications
+            + self.service_types
+            + self.services
+            + self.failover_units
+            + self.loads
+            + self.upgrades
+            + self.deletions
+    }
+}
+
+/// Why a phase did not run during a refresh
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PhaseSkipReason {
+    /// The minimum interval since the last run of the phase has not elapsed yet
+    IntervalNotElapsed { next_run_time: OffsetDateTime },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedPhase {
+    pub phase: Phase,
+    pub reason: PhaseSkipReason,
+}
+
+/// A phase run during a refresh
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseReport {
+    pub phase: Phase,
+    /// Name of the search strategy that ran the phase
+    pub strategy: String,
+    /// Wall-clock duration of the search
+    pub duration: Duration,
+    /// Statistics of the search, for the strategies that run one
+    pub statistics: Option<SearchStatistics>,
+    pub movements: Vec<Movement>,
+    /// The solutions generated by the phase, in the order of the movements
+    pub solutions: Vec<Solution>,
+    /// The id of each solution, for FM to acknowledge it
+    pub solution_ids: Vec<SolutionId>,
+    /// Follow-ups of the movements, handed to FM by a later refresh once the movement before them succeeded
+    pub deferred: Vec<Movement>,
+    /// Movements generated by the search strategy that failed the validation of the plan, left out of the solutions
+    pub dropped: Vec<RejectedMovement>,
+    /// Score of the cluster before the phase, with the movements in flight and those of the previous phases done
+    pub score_before: ClusterScore,
+    /// Score of the cluster once the movements of the phase are done
+    pub score_after: ClusterScore,
+}
+
+/// The outcome of a PLB refresh
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefreshReport {
+    pub now: OffsetDateTime,
+    pub updates: UpdateCounts,
+    /// Hard constraints broken by the cluster snapshot once the updates are applied, before the phases run
+    pub violations: Vec<ConstraintViolation>,
+    /// The phases run, in order
+    pub phases: Vec<PhaseReport>,
+    pub skipped_phases: Vec<SkippedPhase>,
+    /// Pending movements FM did not acknowledge in time, no longer considered in flight
+    pub expired_movements: Vec<PendingMovement>,
+    /// Follow-ups of the movements that succeeded since the previous refresh, handed to FM before the solutions of the
+    /// phases
+    pub follow_ups: Vec<PendingMovement>,
+    /// Wall-clock duration of the whole refresh
+    pub duration: Duration,
+}
+
+impl RefreshReport {
+    /// All the solutions generated by the refresh: the follow-ups, then the solutions in the order of the phases
+    pub fn solutions(&self) -> Vec<Solution> {
+        self.follow_ups
+            .iter()
+            .map(|follow_up| follow_up.movement.to_solution())
+            .chain(
+                self.phases
+                    .iter()
+                    .flat_map(|phase| phase.solutions.iter().cloned()),
+            )
+            .collect()
+    }
+
+    /// The ids of all the solutions generated by the refresh, in the order of [RefreshReport::solutions]
+    pub fn solution_ids(&self) -> Vec<SolutionId> {
+        self.follow_ups
+            .iter()
+            .map(|follow_up| follow_up.id)
+            .chain(
+                self.phases
+                    .iter()
+                    .flat_map(|phase| phase.solution_ids.iter().copied()),
+            )
+            .collect()
+    }
+
+    /// The solutions generated by the phase, empty if it did not run
+    pub fn solutions_of(&self, phase: Phase) -> &[Solution] {
+        self.phases
+            .iter()
+            .find(|phase_report| phase_report.phase == phase)
+            .map(|phase_report| phase_report.solutions.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn phase(&self, phase: Phase) -> Option<&PhaseReport> {
+        self.phases
+            .iter()
+            .find(|phase_report| phase_report.phase == phase)
+    }
+
+    /// The statistics of the searches run by the refresh
+    pub fn search_statistics(&self) -> Vec<&SearchStatistics> {
+        self.phases
+            .iter()
+            .filter_map(|phase| phase.statistics.as_ref())
+            .collect()
+    }
+}
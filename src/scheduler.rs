@@ -2,10 +2,12 @@
 
 use time::{Duration, OffsetDateTime};
 
+use crate::events::{PlbEvent, PlbEventSink};
+
 /// Minimum duration between 2 placement phases
 pub const MIN_PLACEMENT_INTERVAL: Duration = Duration::new(3, 0);
 /// Minimum duration between 2 load balancing phases
-const MIN_BALANCING_INTERVAL: Duration = Duration::new(10, 0);
+pub const MIN_BALANCING_INTERVAL: Duration = Duration::new(10, 0);
 /// Minimu duration between 2 constraint check phases
 const MIN_CONSTRAINT_CHECK_INTERVAL: Duration = Duration::new(5, 0);
 
@@ -13,15 +15,26 @@ const MIN_CONSTRAINT_CHECK_INTERVAL: Duration = Duration::new(5, 0);
 ///     1. Placement
 ///     2. LoadBalancing
 ///     3. ConstraintCheck
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Phase {
     Placement,
     LoadBalancing,
     ConstraintCheck,
 }
 
+impl Phase {
+    /// All the phases, in the order they run within a refresh
+    pub const ALL: [Phase; 3] = [
+        Phase::Placement,
+        Phase::LoadBalancing,
+        Phase::ConstraintCheck,
+    ];
+}
+
 /// PLBScheduler initiates the scheduling phase and action for the PLB
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PLBScheduler {
     current_phase: Option<Phase>,
     last_placement_time: OffsetDateTime,
@@ -62,8 +75,12 @@ impl PLBScheduler {
     }
 
     /// Get a list of current phases that is due for the PLB by comparing the current timestamp with the last timestamps for
-    /// all 3 PLB phases
-    pub fn get_current_phases(&mut self, now: OffsetDateTime) -> Vec<Phase> {
+    /// all 3 PLB phases. The phases that are not due are reported to the event sink as skipped.
+    pub fn get_current_phases(
+        &mut self,
+        now: OffsetDateTime,
+        events: &dyn PlbEventSink,
+    ) -> Vec<Phase> {
         let mut phases = vec![];
         if now - self.last_placement_time >= MIN_PLACEMENT_INTERVAL {
             phases.push(Phase::Placement);
@@ -77,10 +94,28 @@ impl PLBScheduler {
             phases.push(Phase::ConstraintCheck);
             self.last_constraint_time = now;
         }
+        for phase in Phase::ALL {
+            if !phases.contains(&phase) {
+                events.on_event(&PlbEvent::PhaseSkipped {
+                    phase,
+                    next_run_time: self.next_run_time(phase),
+                });
+            }
+        }
 
         phases
     }
 
+    /// The earliest time the phase is due again
+    pub fn next_run_time(&self, phase: Phase) -> OffsetDateTime {
+        match phase {
+            Phase::Placement => self.last_placement_time + MIN_PLACEMENT_INTERVAL,
+            Phase::LoadBalancing => self.last_balancing_time + MIN_BALANCING_INTERVAL,
+            Phase::ConstraintCheck => self.last_constraint_time + MIN_CONSTRAINT_CHECK_INTERVAL,
+        }
+    }
+
+    #[cfg(test)]
     pub(super) fn set_last_phase_time(&mut self, now: OffsetDateTime, phase: Phase) {
         match phase {
             Phase::Placement => self.last_placement_time = now,
@@ -92,22 +127,28 @@ impl PLBScheduler {
 
 #[cfg(test)]
 mod tests {
-    use crate::scheduler;
-
     use super::*;
+    use crate::events::NoopEventSink;
 
     #[test]
     fn test_scheduling_phase() {
         let initial_timestamp = OffsetDateTime::now_utc();
         let mut scheduler = PLBScheduler::new(initial_timestamp);
         // Initially there should be no phase scheduled
-        assert!(scheduler.get_current_phases(initial_timestamp).is_empty());
+        assert!(scheduler
+            .get_current_phases(initial_timestamp, &NoopEventSink)
+            .is_empty());
 
         let time1 = initial_timestamp + Duration::new(1, 0);
         // No phase due
-        assert!(scheduler.get_current_phases(time1).is_empty());
+        assert!(scheduler
+            .get_current_phases(time1, &NoopEventSink)
+            .is_empty());
 
         let time2 = initial_timestamp + MIN_PLACEMENT_INTERVAL;
-        assert_eq!(vec![Phase::Placement], scheduler.get_current_phases(time2));
+        assert_eq!(
+            vec![Phase::Placement],
+            scheduler.get_current_phases(time2, &NoopEventSink)
+        );
     }
 }
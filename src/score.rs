@@ -0,0 +1,100 @@
+//! This module describes how balanced the cluster is, with numbers meant for dashboards and regression alerts.
+//!
+//! Every number is computed over the nodes that are up, from the node loads normalized by the node capacities (the raw
+//! load for the nodes without a capacity for the metric):
+//!     - the standard deviation of the normalized loads, 0 when perfectly balanced
+//!     - the ratio of the maximum to the average normalized load, 1 when perfectly balanced
+//!     - the fraction of nodes whose load is above the activity threshold of the metric
+//!
+//! The aggregate of each number is the average over the metrics weighted by the [ServiceMetric] weights, the greatest
+//! weight of the services reporting a metric being its weight.
+//!
+//! [ServiceMetric]: crate::service::service_metric::ServiceMetric
+
+use std::collections::BTreeMap;
+
+use crate::balance::LoadTable;
+
+/// How balanced the load of a metric is
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricScore {
+    pub weight: f64,
+    /// Standard deviation of the normalized node loads
+    pub std_dev: f64,
+    /// Maximum normalized node load over the average one, 0 when the metric has no load
+    pub max_avg_ratio: f64,
+    /// Fraction of the nodes whose load is above the activity threshold of the metric
+    pub active_node_fraction: f64,
+}
+
+/// How balanced the load of the cluster is, see [crate::score]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClusterScore {
+    pub metrics: BTreeMap<String, MetricScore>,
+    /// Weighted average of the standard deviations of the metrics
+    pub std_dev: f64,
+    /// Weighted average of the max/avg ratios of the metrics
+    pub max_avg_ratio: f64,
+    /// Weighted average of the fractions of active nodes of the metrics
+    pub active_node_fraction: f64,
+    /// The objective minimized by the search strategies: the weighted coefficient of variation of the metrics plus a
+    /// large penalty for every capacity overflow. The lower the better.
+    pub search_score: f64,
+}
+
+impl ClusterScore {
+    /// Scores the loads of the table. A metric without an activity threshold counts the nodes with any load as active.
+    pub(crate) fn new(loads: &LoadTable, activity_thresholds: &BTreeMap<String, u32>) -> Self {
+        let metrics = loads
+            .metrics
+            .iter()
+            .map(|(metric_name, metric)| {
+                let threshold = activity_thresholds
+                    .get(metric_name)
+                    .copied()
+                    .unwrap_or_default() as i64;
+                let mean = metric.mean();
+                let max = metric
+                    .loads
+                    .iter()
+                    .map(|(node_id, load)| metric.normalized(*node_id, *load))
+                    .fold(0.0, f64::max);
+                let active_nodes = metric
+                    .loads
+                    .values()
+                    .filter(|load| **load > threshold)
+                    .count();
+                let score = MetricScore {
+                    weight: metric.weight,
+                    std_dev: metric.std_dev(),
+                    max_avg_ratio: if mean > 0.0 { max / mean } else { 0.0 },
+                    active_node_fraction: if metric.loads.is_empty() {
+                        0.0
+                    } else {
+                        active_nodes as f64 / metric.loads.len() as f64
+                    },
+                };
+                (metric_name.clone(), score)
+            })
+            .collect::<BTreeMap<String, MetricScore>>();
+
+        let total_weight = metrics.values().map(|metric| metric.weight).sum::<f64>();
+        let weighted = |value: fn(&MetricScore) -> f64| {
+            if total_weight <= 0.0 {
+                return 0.0;
+            }
+            metrics
+                .values()
+                .map(|metric| metric.weight * value(metric))
+                .sum::<f64>()
+                / total_weight
+        };
+        ClusterScore {
+            std_dev: weighted(|metric| metric.std_dev),
+            max_avg_ratio: weighted(|metric| metric.max_avg_ratio),
+            active_node_fraction: weighted(|metric| metric.active_node_fraction),
+            search_score: loads.score(),
+            metrics,
+        }
+    }
+}
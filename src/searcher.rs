@@ -1,14 +1,20 @@
-use std::{cell::RefCell, rc::Rc};
-
 use uuid::Uuid;
 
-use crate::{scheduler::Phase, ClusterSnapshot};
+use crate::{
+    constraint::ConstraintViolation,
+    events::{NoopEventSink, PlbEvent, PlbEventSink},
+    node::{node_description::NodeDeactivationIntent, node_id::NodeId},
+    scheduler::Phase,
+    ClusterSnapshot,
+};
 
 #[derive(Debug, Clone)]
 pub enum Action {
     /// Placement action. Placing a new replica on a failover unit
     NewReplicaPlacement(Vec<Uuid>),
     /// Placement action. Delete an existing replia on a failover unit
+    ExtraReplicaRemoval(Vec<Uuid>),
+    /// Placement action. Prepare failover units with replicas in an upgrade domain being upgraded
     Upgrade(Vec<Uuid>),
     // Balancing action
     LoadBalancing,
@@ -16,27 +22,39 @@ pub enum Action {
     Defragmentation,
     // ConstraintCheck action
     FixConstraintViolation,
+    /// ConstraintCheck action. Move replicas away from nodes being deactivated
+    NodeDeactivation(Vec<NodeId>),
+    /// ConstraintCheck action. Move the (failover unit, node) replicas hosted on nodes failing the placement
+    /// constraints, block list, fault domain or affinity of their service
+    MisplacedReplicaRelocation(Vec<(Uuid, NodeId)>),
 }
 
-#[derive(Default)]
-pub struct Searcher {
-    snapshot: Rc<RefCell<ClusterSnapshot>>,
+/// Searches the snapshot for the actions required in a phase
+pub struct Searcher<'a> {
+    snapshot: &'a ClusterSnapshot,
+    events: &'a dyn PlbEventSink,
 }
 
-impl Searcher {
-    pub fn new(snapshot: &Rc<RefCell<ClusterSnapshot>>) -> Self {
+impl<'a> Searcher<'a> {
+    pub fn new(snapshot: &'a ClusterSnapshot) -> Self {
         Searcher {
-            snapshot: Rc::clone(snapshot),
+            snapshot,
+            events: &NoopEventSink,
         }
     }
 
+    /// Reports the violations found by the constraint check phase to the event sink
+    pub fn with_event_sink(mut self, events: &'a dyn PlbEventSink) -> Self {
+        self.events = events;
+        self
+    }
+
     pub fn generate_actions(&self, phase: Phase) -> Vec<Action> {
         match phase {
             Phase::Placement => {
                 // search for placement
                 let fus_for_placement = self
                     .snapshot
-                    .borrow()
                     .failover_units
                     .iter()
                     .filter_map(|(fu_id, fu)| {
@@ -48,9 +66,8 @@ impl Searcher {
                     })
                     .collect::<Vec<Uuid>>();
 
-                let fus_for_upgrade = self
+                let fus_for_removal = self
                     .snapshot
-                    .borrow()
                     .failover_units
                     .iter()
                     .filter_map(|(fu_id, fu)| {
@@ -66,14 +83,68 @@ impl Searcher {
                 if !fus_for_placement.is_empty() {
                     actions.push(Action::NewReplicaPlacement(fus_for_placement));
                 }
+                if !fus_for_removal.is_empty() {
+                    actions.push(Action::ExtraReplicaRemoval(fus_for_removal));
+                }
+
+                // search for failover units blocking an upgrade domain walk
+                let fus_for_upgrade = self.snapshot.fus_in_upgrading_domains();
                 if !fus_for_upgrade.is_empty() {
                     actions.push(Action::Upgrade(fus_for_upgrade));
                 }
 
                 actions
             }
-            Phase::LoadBalancing => vec![],
-            Phase::ConstraintCheck => vec![],
+            Phase::LoadBalancing => {
+                // balancing is only meaningful when there are loads to spread over more than one node
+                let snapshot = self.snapshot;
+                if snapshot.nodes.len() > 1 && !snapshot.metric_names().is_empty() {
+                    vec![Action::LoadBalancing]
+                } else {
+                    vec![]
+                }
+            }
+            Phase::ConstraintCheck => {
+                // search for nodes whose deactivation requires replicas to be moved
+                let deactivating_nodes = self
+                    .snapshot
+                    .nodes
+                    .iter()
+                    .filter_map(|(node_id, node)| match node.deactivation_intent() {
+                        NodeDeactivationIntent::None | NodeDeactivationIntent::Pause => None,
+                        _ => Some(*node_id),
+                    })
+                    .collect::<Vec<NodeId>>();
+
+                let mut actions = vec![];
+                if !deactivating_nodes.is_empty() {
+                    actions.push(Action::NodeDeactivation(deactivating_nodes));
+                }
+                let violations = self.snapshot.constraint_violations();
+                for violation in &violations {
+                    self.events
+                        .on_event(&PlbEvent::ViolationDetected(violation.clone()));
+                }
+                if violations.iter().any(|violation| {
+                    matches!(violation, ConstraintViolation::CapacityExceeded { .. })
+                }) {
+                    actions.push(Action::FixConstraintViolation);
+                }
+                let misplaced_replicas = violations
+                    .iter()
+                    .filter_map(|violation| match violation {
+                        ConstraintViolation::ReplicaMisplaced { fu_id, node_id, .. } => {
+                            Some((*fu_id, *node_id))
+                        }
+                        ConstraintViolation::CapacityExceeded { .. } => None,
+                    })
+                    .collect::<Vec<(Uuid, NodeId)>>();
+                if !misplaced_replicas.is_empty() {
+                    actions.push(Action::MisplacedReplicaRelocation(misplaced_replicas));
+                }
+
+                actions
+            }
         }
     }
 }
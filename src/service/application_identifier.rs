@@ -1,5 +1,7 @@
 pub struct ApplicationIdentifier {
+    #[allow(dead_code)]
     application_name: String,
+    #[allow(dead_code)]
     application_number: u64,
 }
 
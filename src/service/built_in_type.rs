@@ -1,3 +1,7 @@
+/// The names of the system metrics mirror the C++ implementation
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BuiltInType {
     None,
     PrimaryCount,
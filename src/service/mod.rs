@@ -1,5 +1,8 @@
 pub mod application_identifier;
 pub mod built_in_type;
+pub mod placement_constraint;
+#[allow(clippy::module_inception)]
 pub mod service;
 pub mod service_description;
 pub mod service_metric;
+pub mod service_package;
@@ -0,0 +1,279 @@
+//! The placement constraint expression of a service, evaluated against the properties of the nodes.
+//!
+//! Similar to the C++ implementation, an expression compares node properties with values and combines the
+//! comparisons with boolean operators, e.g. `NodeType == FrontEnd && (Zone != "zone 1" || Memory >= 64)`:
+//!     - comparisons: `==`, `!=`, `<`, `<=`, `>`, `>=`. Values that both parse as numbers are compared as numbers,
+//!       otherwise as strings
+//!     - boolean operators: `!`, `&&` and `||`, by decreasing precedence, and parentheses
+//!
+//! A comparison involving a property the node does not define is false.
+
+use anyhow::{anyhow, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComparisonOperator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlacementConstraint {
+    Comparison {
+        property: String,
+        operator: ComparisonOperator,
+        value: String,
+    },
+    Not(Box<PlacementConstraint>),
+    And(Box<PlacementConstraint>, Box<PlacementConstraint>),
+    Or(Box<PlacementConstraint>, Box<PlacementConstraint>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Operator(ComparisonOperator),
+    Not,
+    And,
+    Or,
+    OpenParenthesis,
+    CloseParenthesis,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next_is = |chars: &mut std::iter::Peekable<std::str::Chars>, expected: char| {
+            chars.next_if_eq(&expected).is_some()
+        };
+        let token = match c {
+            _ if c.is_whitespace() => continue,
+            '(' => Token::OpenParenthesis,
+            ')' => Token::CloseParenthesis,
+            '&' if next_is(&mut chars, '&') => Token::And,
+            '|' if next_is(&mut chars, '|') => Token::Or,
+            '=' if next_is(&mut chars, '=') => Token::Operator(ComparisonOperator::Equal),
+            '!' if next_is(&mut chars, '=') => Token::Operator(ComparisonOperator::NotEqual),
+            '!' => Token::Not,
+            '<' if next_is(&mut chars, '=') => Token::Operator(ComparisonOperator::LessOrEqual),
+            '<' => Token::Operator(ComparisonOperator::Less),
+            '>' if next_is(&mut chars, '=') => Token::Operator(ComparisonOperator::GreaterOrEqual),
+            '>' => Token::Operator(ComparisonOperator::Greater),
+            '"' | '\'' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some(other) => word.push(other),
+                        None => return Err(anyhow!("Unterminated string in {}", expression)),
+                    }
+                }
+                Token::Word(word)
+            }
+            _ if c.is_alphanumeric() || matches!(c, '_' | '.' | '-' | ':' | '/') => {
+                let mut word = String::from(c);
+                while let Some(next) = chars.next_if(|next| {
+                    next.is_alphanumeric() || matches!(next, '_' | '.' | '-' | ':' | '/')
+                }) {
+                    word.push(next);
+                }
+                Token::Word(word)
+            }
+            _ => return Err(anyhow!("Unexpected character {} in {}", c, expression)),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    expression: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow!(
+            "Invalid placement constraint {}: {}",
+            self.expression,
+            message
+        )
+    }
+
+    fn parse_or(&mut self) -> Result<PlacementConstraint> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            left = PlacementConstraint::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<PlacementConstraint> {
+        let mut left = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            left = PlacementConstraint::And(Box::new(left), Box::new(self.parse_unary()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<PlacementConstraint> {
+        match self.next() {
+            Some(Token::Not) => Ok(PlacementConstraint::Not(Box::new(self.parse_unary()?))),
+            Some(Token::OpenParenthesis) => {
+                let inner = self.parse_or()?;
+                match self.next() {
+                    Some(Token::CloseParenthesis) => Ok(inner),
+                    _ => Err(self.error("missing closing parenthesis")),
+                }
+            }
+            Some(Token::Word(property)) => {
+                let Some(Token::Operator(operator)) = self.next() else {
+                    return Err(self.error(&format!("expected a comparison after {}", property)));
+                };
+                let Some(Token::Word(value)) = self.next() else {
+                    return Err(
+                        self.error(&format!("expected a value to compare {} with", property))
+                    );
+                };
+                Ok(PlacementConstraint::Comparison {
+                    property,
+                    operator,
+                    value,
+                })
+            }
+            _ => Err(self.error("expected a comparison")),
+        }
+    }
+}
+
+impl PlacementConstraint {
+    /// Parses a placement constraint expression. An empty expression has no constraint.
+    pub fn parse(expression: &str) -> Result<Option<PlacementConstraint>> {
+        let tokens = tokenize(expression)?;
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+        let mut parser = Parser {
+            expression,
+            tokens,
+            position: 0,
+        };
+        let constraint = parser.parse_or()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unexpected trailing tokens"));
+        }
+
+        Ok(Some(constraint))
+    }
+
+    /// Evaluates the constraint with the given lookup of node properties
+    pub fn is_satisfied<'a>(&self, property_of: &impl Fn(&str) -> Option<&'a str>) -> bool {
+        match self {
+            PlacementConstraint::Comparison {
+                property,
+                operator,
+                value,
+            } => {
+                let Some(actual) = property_of(property) else {
+                    return false;
+                };
+                let ordering = match (actual.parse::<f64>(), value.parse::<f64>()) {
+                    (Ok(actual), Ok(value)) => actual.partial_cmp(&value),
+                    _ => Some(actual.cmp(value.as_str())),
+                };
+                let Some(ordering) = ordering else {
+                    return false;
+                };
+                match operator {
+                    ComparisonOperator::Equal => ordering.is_eq(),
+                    ComparisonOperator::NotEqual => ordering.is_ne(),
+                    ComparisonOperator::Less => ordering.is_lt(),
+                    ComparisonOperator::LessOrEqual => ordering.is_le(),
+                    ComparisonOperator::Greater => ordering.is_gt(),
+                    ComparisonOperator::GreaterOrEqual => ordering.is_ge(),
+                }
+            }
+            PlacementConstraint::Not(inner) => !inner.is_satisfied(property_of),
+            PlacementConstraint::And(left, right) => {
+                left.is_satisfied(property_of) && right.is_satisfied(property_of)
+            }
+            PlacementConstraint::Or(left, right) => {
+                left.is_satisfied(property_of) || right.is_satisfied(property_of)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn evaluate(expression: &str, properties: &[(&str, &str)]) -> bool {
+        let properties = properties
+            .iter()
+            .map(|(name, value)| (*name, *value))
+            .collect::<HashMap<&str, &str>>();
+        PlacementConstraint::parse(expression)
+            .unwrap()
+            .unwrap()
+            .is_satisfied(&|name| properties.get(name).copied())
+    }
+
+    #[test]
+    fn test_evaluation() {
+        let node = [
+            ("NodeType", "FrontEnd"),
+            ("Memory", "128"),
+            ("Zone", "zone 1"),
+        ];
+        assert!(evaluate("NodeType == FrontEnd", &node));
+        assert!(!evaluate("NodeType != FrontEnd", &node));
+        // numbers are not compared as strings
+        assert!(evaluate("Memory >= 64", &node));
+        assert!(!evaluate("Memory < 64", &node));
+        assert!(evaluate("Zone == \"zone 1\"", &node));
+        // && binds tighter than ||
+        assert!(evaluate(
+            "NodeType == BackEnd && Memory > 1 || Zone == 'zone 1'",
+            &node
+        ));
+        assert!(!evaluate(
+            "NodeType == BackEnd && (Memory > 1 || Zone == 'zone 1')",
+            &node
+        ));
+        assert!(evaluate("!(NodeType == BackEnd)", &node));
+        // a missing property never satisfies a comparison
+        assert!(!evaluate("Color == Red", &node));
+        assert!(!evaluate("Color != Red", &node));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(None, PlacementConstraint::parse("  ").unwrap());
+        assert!(PlacementConstraint::parse("NodeType ==").is_err());
+        assert!(PlacementConstraint::parse("(NodeType == FrontEnd").is_err());
+        assert!(PlacementConstraint::parse("NodeType == FrontEnd)").is_err());
+        assert!(PlacementConstraint::parse("NodeType = FrontEnd").is_err());
+        assert!(PlacementConstraint::parse("Zone == \"zone 1").is_err());
+    }
+}
@@ -1,13 +1,96 @@
 //! Represents a service in a Service Fabric cluster.
 
-use super::service_description::ServiceDescription;
+use super::{
+    placement_constraint::PlacementConstraint, service_description::ServiceDescription,
+    service_metric::ServiceMetric, service_package::ServicePackageDescription,
+};
+use crate::node::node::Node;
 
+use anyhow::{anyhow, Result};
+
+#[derive(Clone)]
 pub struct Service {
     pub(crate) service_description: ServiceDescription,
+    /// The parsed placement constraints, or the parsing error of an invalid expression
+    placement_constraint: Result<Option<PlacementConstraint>, String>,
+    /// The resource governance metrics derived from the limits of the service package the service does not declare
+    rg_metrics: Vec<ServiceMetric>,
 }
 
 impl Service {
+    pub fn new(service_description: ServiceDescription) -> Self {
+        let placement_constraint =
+            PlacementConstraint::parse(&service_description.placement_constraints)
+                .map_err(|err| err.to_string());
+        let rg_metrics = service_description
+            .service_package
+            .iter()
+            .flat_map(|service_package| service_package.rg_metrics())
+            .filter(|rg_metric| {
+                service_description
+                    .metrics
+                    .iter()
+                    .all(|metric| metric.name() != rg_metric.name())
+            })
+            .collect();
+        Service {
+            service_description,
+            placement_constraint,
+            rg_metrics,
+        }
+    }
+
     pub fn servcie_name(&self) -> &str {
         &self.service_description.service_name
     }
+
+    pub fn service_type_name(&self) -> &str {
+        &self.service_description.service_type_name
+    }
+
+    pub fn application_name(&self) -> &str {
+        &self.service_description.application_name
+    }
+
+    pub fn metrics(&self) -> &[ServiceMetric] {
+        &self.service_description.metrics
+    }
+
+    /// The resource governance metrics derived from the service package, see [ServicePackageDescription::rg_loads]
+    pub fn rg_metrics(&self) -> &[ServiceMetric] {
+        &self.rg_metrics
+    }
+
+    /// The number of replicas a failover unit of the service needs for a write quorum, 0 if not set
+    pub fn min_replica_set_size(&self) -> usize {
+        self.service_description.min_replica_set_size.max(0) as usize
+    }
+
+    pub fn service_package(&self) -> Option<&ServicePackageDescription> {
+        self.service_description.service_package.as_ref()
+    }
+
+    /// Checks that the placement constraints parse and that the default loads of the metrics do not exceed their
+    /// maximum load
+    pub fn validate(&self) -> Result<()> {
+        if let Err(err) = &self.placement_constraint {
+            return Err(anyhow!("{}", err));
+        }
+        self.metrics().iter().try_for_each(ServiceMetric::validate)
+    }
+
+    /// The parent service the replicas of the service are affinitized with, if any
+    pub fn affinitized_service(&self) -> Option<&str> {
+        Some(self.service_description.affinitized_service.as_str()).filter(|name| !name.is_empty())
+    }
+
+    /// Whether the properties of the node satisfy the placement constraints of the service. A service whose
+    /// expression cannot be parsed cannot be placed on any node.
+    pub fn allows_node(&self, node: &Node) -> bool {
+        match &self.placement_constraint {
+            Ok(Some(constraint)) => constraint.is_satisfied(&|name| node.property(name)),
+            Ok(None) => true,
+            Err(_) => false,
+        }
+    }
 }
@@ -1,27 +1,92 @@
 //! The internal state of a [Service]
 
+use super::{service_metric::ServiceMetric, service_package::ServicePackageDescription};
 
-use super::service_metric::ServiceMetric;
-
-#[derive(Default)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ServiceDescription {
     pub(crate) service_name: String,
     pub(crate) service_type_name: String,
-    application_name: String,
+    pub(crate) application_name: String,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     is_stateful: bool,
-    placement_constraints: String,
-    affinitized_service: String,
+    pub(crate) placement_constraints: String,
+    pub(crate) affinitized_service: String,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     aligned_affinity: bool,
-    metrics: Vec<ServiceMetric>,
+    pub(crate) metrics: Vec<ServiceMetric>,
+    pub(crate) service_package: Option<ServicePackageDescription>,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     default_primary_move_cost: u32,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     default_secondary_move_cost: u32,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     default_auxiliary_move_cost: u32,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     on_every_node: bool,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     allow_multiple_instances_on_node: bool,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     partition_count: i32,
     target_replica_set_size: i32,
+    pub(crate) min_replica_set_size: i32,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     has_persisted_state: bool,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     service_id: u64,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     application_id: u64,
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
     service_instance: u64,
 }
+
+impl ServiceDescription {
+    pub fn new(service_type_name: &str, service_name: &str) -> Self {
+        ServiceDescription {
+            service_type_name: String::from(service_type_name),
+            service_name: String::from(service_name),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_application_name(mut self, application_name: &str) -> Self {
+        self.application_name = String::from(application_name);
+        self
+    }
+
+    pub fn with_metric(mut self, metric: ServiceMetric) -> Self {
+        self.metrics.push(metric);
+        self
+    }
+
+    /// Restricts the nodes of the service to the ones whose properties satisfy the placement constraint expression
+    pub fn with_placement_constraints(mut self, placement_constraints: &str) -> Self {
+        self.placement_constraints = String::from(placement_constraints);
+        self
+    }
+
+    /// Places the replicas of the service only on the nodes hosting a replica of the parent service
+    pub fn with_affinitized_service(mut self, parent_service_name: &str) -> Self {
+        self.affinitized_service = String::from(parent_service_name);
+        self
+    }
+
+    /// Runs the service in the resource governed service package, see [ServicePackageDescription]
+    pub fn with_service_package(mut self, service_package: ServicePackageDescription) -> Self {
+        self.service_package = Some(service_package);
+        self
+    }
+
+    /// Sets the number of replicas the failover units of the service target, and the number of replicas they need for
+    /// a write quorum. The replicas of a failover unit below its minimum replica set size are never moved or dropped.
+    pub fn with_replica_set_size(
+        mut self,
+        target_replica_set_size: i32,
+        min_replica_set_size: i32,
+    ) -> Self {
+        self.target_replica_set_size = target_replica_set_size;
+        self.min_replica_set_size = min_replica_set_size;
+        self
+    }
+}
@@ -1,12 +1,120 @@
-use super::built_in_type::BuiltInType;
+use anyhow::{anyhow, Result};
 
+use crate::failoverunit::failover_unit::ReplicaRole;
+
+use super::{built_in_type::BuiltInType, service_package::is_rg_metric_name};
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ServiceMetric {
-    name: String,
-    built_in_type: BuiltInType,
-    weight: f64,
-    primary_default_load: u32,
-    secondary_default_load: u32,
-    auxilliary_default_load: u32,
-    maxium_load: u32,
-    is_rg_metric: bool,
+    pub(crate) name: String,
+    pub(crate) built_in_type: BuiltInType,
+    pub(crate) weight: f64,
+    pub(crate) primary_default_load: u32,
+    pub(crate) secondary_default_load: u32,
+    pub(crate) auxilliary_default_load: u32,
+    pub(crate) maxium_load: u32,
+    pub(crate) is_rg_metric: bool,
+}
+
+impl ServiceMetric {
+    /// Creates a custom (non built-in) metric with the given weight and default loads. The metrics named after a
+    /// resource governance metric, see [crate::service::service_package], are resource governance metrics.
+    pub fn new(
+        name: &str,
+        weight: f64,
+        primary_default_load: u32,
+        secondary_default_load: u32,
+    ) -> Self {
+        ServiceMetric {
+            name: String::from(name),
+            built_in_type: BuiltInType::None,
+            weight,
+            primary_default_load,
+            secondary_default_load,
+            auxilliary_default_load: secondary_default_load,
+            maxium_load: u32::MAX,
+            is_rg_metric: is_rg_metric_name(name),
+        }
+    }
+
+    /// Creates a built-in metric named after its type, e.g. `PrimaryCount`. The load of the metrics counting replicas is
+    /// computed from the replica roles, see [ServiceMetric::count_load].
+    pub fn built_in(built_in_type: BuiltInType, weight: f64) -> Self {
+        ServiceMetric {
+            built_in_type,
+            ..ServiceMetric::new(&format!("{:?}", built_in_type), weight, 0, 0)
+        }
+    }
+
+    /// Marks the metric as a built-in one, e.g. [BuiltInType::Predicted] for its load to be forecast
+    pub fn with_built_in_type(mut self, built_in_type: BuiltInType) -> Self {
+        self.built_in_type = built_in_type;
+        self
+    }
+
+    /// Caps the load a replica can report for the metric, the loads reported above it are clamped
+    pub fn with_maximum_load(mut self, maximum_load: u32) -> Self {
+        self.maxium_load = maximum_load;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn maximum_load(&self) -> u32 {
+        self.maxium_load
+    }
+
+    /// Checks that none of the default loads exceeds the maximum load
+    pub fn validate(&self) -> Result<()> {
+        let default_load = self
+            .primary_default_load
+            .max(self.secondary_default_load)
+            .max(self.auxilliary_default_load);
+        if default_load > self.maxium_load {
+            return Err(anyhow!(
+                "Default load {} of metric {} is above its maximum load {}",
+                default_load,
+                self.name,
+                self.maxium_load
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the metric is a resource governance metric, whose load is reserved once per service package on a node
+    /// rather than added up per replica
+    pub fn is_rg_metric(&self) -> bool {
+        self.is_rg_metric
+    }
+
+    /// The load of a replica of the given role for the built-in metrics counting replicas, None for the other metrics:
+    ///     - PrimaryCount and Sys_PrimaryLoad count the primaries
+    ///     - ReplicaCount and Sys_ReplicaCount count the primaries and secondaries
+    ///     - Count and Sys_LogicalLoad count every replica
+    pub fn count_load(&self, role: ReplicaRole) -> Option<u32> {
+        let counted = match self.built_in_type {
+            BuiltInType::PrimaryCount | BuiltInType::Sys_PrimaryLoad => {
+                role == ReplicaRole::Primary
+            }
+            BuiltInType::ReplicaCount | BuiltInType::Sys_ReplicaCount => {
+                matches!(role, ReplicaRole::Primary | ReplicaRole::Secondary)
+            }
+            BuiltInType::Count | BuiltInType::Sys_LogicalLoad => true,
+            BuiltInType::None | BuiltInType::Predicted => return None,
+        };
+        Some(counted as u32)
+    }
+
+    /// The load assumed for a replica of the given role when the partition has not reported any load
+    pub fn default_load(&self, role: ReplicaRole) -> u32 {
+        match role {
+            ReplicaRole::Primary => self.primary_default_load,
+            ReplicaRole::Auxiliary | ReplicaRole::StandByAuxiliary => self.auxilliary_default_load,
+            _ => self.secondary_default_load,
+        }
+    }
 }
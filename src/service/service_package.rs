@@ -0,0 +1,58 @@
+//! The service package a service runs in, and the resource governance limits of the package
+
+use super::service_metric::ServiceMetric;
+
+/// The resource governance metric of the CPU cores of a service package. Its loads and the node capacities for it are
+/// in thousandths of a core.
+pub const CPU_CORES_METRIC: &str = "servicefabric:/_CpuCores";
+/// The resource governance metric of the memory of a service package, in MB
+pub const MEMORY_IN_MB_METRIC: &str = "servicefabric:/_MemoryInMB";
+
+/// Whether the metric is one of the resource governance metrics derived from the service package limits
+pub fn is_rg_metric_name(metric_name: &str) -> bool {
+    metric_name == CPU_CORES_METRIC || metric_name == MEMORY_IN_MB_METRIC
+}
+
+/// A service package with resource governance limits. The replicas of the services of an application running in the
+/// same package on a node share a single activation of the package, and so a single reservation of its limits.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServicePackageDescription {
+    pub(crate) name: String,
+    pub(crate) cpu_cores: f64,
+    pub(crate) memory_in_mb: u32,
+}
+
+impl ServicePackageDescription {
+    pub fn new(name: &str, cpu_cores: f64, memory_in_mb: u32) -> Self {
+        ServicePackageDescription {
+            name: String::from(name),
+            cpu_cores,
+            memory_in_mb,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The load the package adds to a node hosting any of its replicas, for every resource governance metric it limits
+    pub fn rg_loads(&self) -> Vec<(&'static str, u32)> {
+        let cpu_load = (self.cpu_cores.max(0.0) * 1000.0).round() as u32;
+        [
+            (CPU_CORES_METRIC, cpu_load),
+            (MEMORY_IN_MB_METRIC, self.memory_in_mb),
+        ]
+        .into_iter()
+        .filter(|(_, load)| *load > 0)
+        .collect()
+    }
+
+    /// The resource governance metrics of the package, whose default loads are the limits of the package
+    pub(crate) fn rg_metrics(&self) -> Vec<ServiceMetric> {
+        self.rg_loads()
+            .into_iter()
+            .map(|(metric_name, load)| ServiceMetric::new(metric_name, 0.0, load, load))
+            .collect()
+    }
+}
@@ -1,7 +1,10 @@
 //! Represents a service type in a Service Fabric cluster.
 
+use crate::node::node_id::NodeId;
+
 use super::service_type_description::ServiceTypeDescription;
 
+#[derive(Clone)]
 pub struct ServiceType {
     pub(crate) service_type_desc: ServiceTypeDescription,
 }
@@ -10,4 +13,9 @@ impl ServiceType {
     pub fn service_type_name(&self) -> &str {
         &self.service_type_desc.name
     }
+
+    /// Whether replicas of this service type are not allowed on the given node
+    pub fn is_blocked_on(&self, node_id: NodeId) -> bool {
+        self.service_type_desc.block_list.contains(&node_id)
+    }
 }
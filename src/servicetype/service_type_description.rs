@@ -1,11 +1,21 @@
 //! The internal state of a [ServiceType]
 
-
 use crate::node::node_id::NodeId;
 use std::collections::HashSet;
 
-#[derive(Default)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ServiceTypeDescription {
     pub(crate) name: String,
-    block_list: HashSet<NodeId>,
+    pub(crate) block_list: HashSet<NodeId>,
+}
+
+impl ServiceTypeDescription {
+    pub fn new(name: &str, block_list: HashSet<NodeId>) -> Self {
+        ServiceTypeDescription {
+            name: String::from(name),
+            block_list,
+        }
+    }
 }
@@ -0,0 +1,155 @@
+//! This module contains the JSON serialization of the cluster snapshot, so that the state of a cluster can be dumped
+//! and analyzed offline.
+//!
+//! The snapshot is serialized as the descriptions it was built from, together with the upgrades in progress:
+//! ```json
+//! {
+//!     "version": 1,
+//!     "nodes": [...],
+//!     "applications": [...],
+//!     "service_types": [...],
+//!     "services": [...],
+//!     "failover_units": [...],
+//!     "loads": [...],
+//!     "upgrades": [[scope, upgrade_domain], ...]
+//! }
+//! ```
+//! The fields of the descriptions are optional when reading a snapshot, except for the service metrics which have no
+//! default. A snapshot written by a newer schema version is rejected.
+
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    application::application_description::ApplicationDescription,
+    failoverunit::failover_unit::FailoverUnitDescription,
+    load::load_or_move_cost::LoadOrMoveCostDescription,
+    node::{node_description::DomainId, node_description::NodeDescription},
+    service::service_description::ServiceDescription,
+    servicetype::service_type_description::ServiceTypeDescription,
+    upgrade::UpgradeScope,
+    ClusterSnapshot, PlacementAndLoadBalancing,
+};
+
+/// Version of the snapshot JSON schema, bumped on every change that older readers cannot handle
+pub const SNAPSHOT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    nodes: Vec<&'a NodeDescription>,
+    applications: Vec<&'a ApplicationDescription>,
+    service_types: Vec<&'a ServiceTypeDescription>,
+    services: Vec<&'a ServiceDescription>,
+    failover_units: Vec<&'a FailoverUnitDescription>,
+    loads: Vec<&'a LoadOrMoveCostDescription>,
+    upgrades: Vec<(&'a UpgradeScope, &'a DomainId)>,
+}
+
+#[derive(Deserialize)]
+struct SnapshotOwned {
+    version: u32,
+    #[serde(default)]
+    nodes: Vec<NodeDescription>,
+    #[serde(default)]
+    applications: Vec<ApplicationDescription>,
+    #[serde(default)]
+    service_types: Vec<ServiceTypeDescription>,
+    #[serde(default)]
+    services: Vec<ServiceDescription>,
+    #[serde(default)]
+    failover_units: Vec<FailoverUnitDescription>,
+    #[serde(default)]
+    loads: Vec<LoadOrMoveCostDescription>,
+    #[serde(default)]
+    upgrades: Vec<(UpgradeScope, DomainId)>,
+}
+
+impl Serialize for ClusterSnapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SnapshotRef {
+            version: SNAPSHOT_SCHEMA_VERSION,
+            nodes: self
+                .nodes
+                .values()
+                .map(|node| &node.node_description)
+                .collect(),
+            applications: self
+                .apps
+                .values()
+                .map(|app| &app.application_desc)
+                .collect(),
+            service_types: self
+                .service_types
+                .values()
+                .map(|service_type| &service_type.service_type_desc)
+                .collect(),
+            services: self
+                .services
+                .values()
+                .map(|service| &service.service_description)
+                .collect(),
+            failover_units: self
+                .failover_units
+                .values()
+                .map(|fu| &fu.failover_unit_description)
+                .collect(),
+            loads: self
+                .loads
+                .values()
+                .map(|load| &load.load_description)
+                .collect(),
+            upgrades: self.upgrades.iter().collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ClusterSnapshot {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = SnapshotOwned::deserialize(deserializer)?;
+        if snapshot.version > SNAPSHOT_SCHEMA_VERSION {
+            return Err(serde::de::Error::custom(format!(
+                "snapshot schema version {} is not supported, the latest supported version is {}",
+                snapshot.version, SNAPSHOT_SCHEMA_VERSION
+            )));
+        }
+
+        let mut cluster_snapshot = ClusterSnapshot::from_descriptions(
+            snapshot.nodes,
+            snapshot.applications,
+            snapshot.service_types,
+            snapshot.services,
+            snapshot.failover_units,
+            snapshot.loads,
+        )
+        .map_err(serde::de::Error::custom)?;
+        cluster_snapshot.upgrades = snapshot.upgrades.into_iter().collect();
+
+        Ok(cluster_snapshot)
+    }
+}
+
+impl PlacementAndLoadBalancing {
+    /// Creates a PLB engine from a cluster snapshot previously written by [PlacementAndLoadBalancing::dump_snapshot]
+    pub fn from_snapshot_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .map_err(|err| anyhow!("Failed to read snapshot {}: {}", path.display(), err))?;
+        let cluster_snapshot = serde_json::from_str::<ClusterSnapshot>(&content)
+            .map_err(|err| anyhow!("Failed to parse snapshot {}: {}", path.display(), err))?;
+
+        Ok(Self::with_snapshot(cluster_snapshot))
+    }
+
+    /// Writes the current cluster snapshot to a JSON file. Pending updates are not part of the snapshot until the
+    /// next refresh.
+    pub fn dump_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let content = serde_json::to_string_pretty(&*self.cluster_snapshot.borrow())?;
+        fs::write(path, content)
+            .map_err(|err| anyhow!("Failed to write snapshot {}: {}", path.display(), err))
+    }
+}
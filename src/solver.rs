@@ -1,8 +1,20 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cmp::Reverse, collections::BTreeSet};
 
-use crate::{searcher::Action, ClusterSnapshot};
+use uuid::Uuid;
+
+use crate::{
+    constraint::PlacementChecker,
+    drain::DrainPlanner,
+    events::{NoopEventSink, PlbEvent, PlbEventSink},
+    failoverunit::failover_unit::ReplicaRole,
+    node::node_id::NodeId,
+    searcher::Action,
+    upgrade::UpgradePlanner,
+    ClusterSnapshot,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Solution {
     AddReplica(String),
     DeleteReplica(String),
@@ -10,40 +22,210 @@ pub enum Solution {
     SwapReplica(String),
 }
 
-#[derive(Default)]
-pub struct Solver {
-    snapshot: Rc<RefCell<ClusterSnapshot>>,
+/// The structured form of a [Solution]. Solutions are the messages handed back to FM while movements are what
+/// PLB reasons about when planning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Movement {
+    /// Place a new replica of the failover unit on the node
+    AddReplica { fu_id: Uuid, node: NodeId },
+    /// Drop the replica of the failover unit hosted on the node
+    DropReplica { fu_id: Uuid, node: NodeId },
+    /// Move the replica with the given role from one node to another
+    MoveReplica {
+        fu_id: Uuid,
+        role: ReplicaRole,
+        from: NodeId,
+        to: NodeId,
+    },
+    /// Swap the primary role from the replica on `from` to the secondary replica on `to`
+    SwapPrimary {
+        fu_id: Uuid,
+        from: NodeId,
+        to: NodeId,
+    },
+}
+
+impl Movement {
+    pub fn fu_id(&self) -> Uuid {
+        match self {
+            Movement::AddReplica { fu_id, .. }
+            | Movement::DropReplica { fu_id, .. }
+            | Movement::MoveReplica { fu_id, .. }
+            | Movement::SwapPrimary { fu_id, .. } => *fu_id,
+        }
+    }
+
+    pub fn to_solution(&self) -> Solution {
+        match self {
+            Movement::AddReplica { fu_id, node } => Solution::AddReplica(format!(
+                "Partition {:}: AddReplica on Node {:?}",
+                fu_id, node
+            )),
+            Movement::DropReplica { fu_id, node } => Solution::DeleteReplica(format!(
+                "Partition {:}: DeleteReplica on Node {:?}",
+                fu_id, node
+            )),
+            Movement::MoveReplica {
+                fu_id,
+                role,
+                from,
+                to,
+            } => Solution::MoveReplica(format!(
+                "Partition {:}: MoveReplica {:?} from Node {:?} to Node {:?}",
+                fu_id, role, from, to
+            )),
+            Movement::SwapPrimary { fu_id, from, to } => Solution::SwapReplica(format!(
+                "Partition {:}: SwapReplica Primary from Node {:?} to Node {:?}",
+                fu_id, from, to
+            )),
+        }
+    }
+}
+
+/// Turns the actions found by the [crate::searcher::Searcher] into movements, the dummy PLB way
+pub struct Solver<'a> {
+    snapshot: &'a ClusterSnapshot,
+    events: &'a dyn PlbEventSink,
 }
 
-impl Solver {
-    pub fn new(snapshot: &Rc<RefCell<ClusterSnapshot>>) -> Self {
+impl<'a> Solver<'a> {
+    pub fn new(snapshot: &'a ClusterSnapshot) -> Self {
         Solver {
-            snapshot: Rc::clone(snapshot),
+            snapshot,
+            events: &NoopEventSink,
         }
     }
 
+    /// Reports the replicas that cannot be placed to the event sink
+    pub fn with_event_sink(mut self, events: &'a dyn PlbEventSink) -> Self {
+        self.events = events;
+        self
+    }
+
     pub fn generate_solutions(&self, actions: Vec<Action>) -> Vec<Solution> {
-        let mut solutions = vec![];
-        for action in actions {
-            match action {
-                Action::NewReplicaPlacement(fu_ids) => {
-                    for fu_id in fu_ids {
-                        // This is a dummy PLB so we select the max node id for placement
-                        if let Some((node_id, _)) = self.snapshot.borrow().nodes.last_key_value() {
-                            solutions.push(Solution::AddReplica(format!(
-                                "Partition {:}: AddReplica on Node {:?}",
-                                fu_id, node_id
-                            )));
+        self.generate_movements(actions)
+            .iter()
+            .map(Movement::to_solution)
+            .collect()
+    }
+
+    pub fn generate_movements(&self, actions: Vec<Action>) -> Vec<Movement> {
+        actions
+            .into_iter()
+            .flat_map(|action| self.solve(action))
+            .collect()
+    }
+
+    /// Generates the movements for a single action
+    pub fn solve(&self, action: Action) -> Vec<Movement> {
+        let snapshot = self.snapshot;
+        let mut movements = vec![];
+        match action {
+            Action::NewReplicaPlacement(fu_ids) => {
+                let mut checker = PlacementChecker::new(snapshot);
+                for fu_id in fu_ids {
+                    let Some(fu) = snapshot.failover_units.get(&fu_id) else {
+                        continue;
+                    };
+                    // This is a dummy PLB so we select the max node id that can take the replica for placement
+                    let target = snapshot.nodes.keys().rev().copied().find(|node_id| {
+                        checker.check(fu, ReplicaRole::Secondary, *node_id).is_ok()
+                    });
+                    match target {
+                        Some(node_id) => {
+                            checker.apply(fu, ReplicaRole::Secondary, None, Some(node_id));
+                            movements.push(Movement::AddReplica {
+                                fu_id,
+                                node: node_id,
+                            });
                         }
+                        None => self
+                            .events
+                            .on_event(&PlbEvent::ReplicaUnplaceable { fu_id }),
+                    }
+                }
+            }
+            Action::NodeDeactivation(node_ids) => {
+                let mut planner = DrainPlanner::new(snapshot);
+                for node_id in node_ids {
+                    movements.extend(planner.plan(node_id).movements);
+                }
+            }
+            Action::MisplacedReplicaRelocation(replicas) => {
+                let mut checker = PlacementChecker::new(snapshot);
+                let mut moved = BTreeSet::new();
+                for (fu_id, node_id) in replicas {
+                    // the replicas sharing a fault domain are all misplaced, moving one of them is enough
+                    if moved.contains(&fu_id) {
+                        continue;
+                    }
+                    let Some(fu) = snapshot.failover_units.get(&fu_id) else {
+                        continue;
+                    };
+                    let Some(role) = fu
+                        .active_replicas()
+                        .find(|replica| replica.location() == node_id)
+                        .map(|replica| replica.role())
+                    else {
+                        continue;
+                    };
+                    if let Some(target) = checker.select_target(fu, role, Some(node_id)) {
+                        checker.apply(fu, role, Some(node_id), Some(target));
+                        moved.insert(fu_id);
+                        movements.push(Movement::MoveReplica {
+                            fu_id,
+                            role,
+                            from: node_id,
+                            to: target,
+                        });
+                    }
+                }
+            }
+            Action::ExtraReplicaRemoval(fu_ids) => {
+                for fu_id in fu_ids {
+                    let Some(fu) = snapshot.failover_units.get(&fu_id) else {
+                        continue;
+                    };
+                    let mut candidates = fu
+                        .active_replicas()
+                        .filter(|replica| !replica.is_primary())
+                        .map(|replica| replica.location())
+                        .collect::<Vec<NodeId>>();
+                    // Drop the replicas on nodes that do not accept new replicas first, then the max node id as
+                    // this is a dummy PLB
+                    candidates.sort_by_key(|node_id| {
+                        (
+                            snapshot
+                                .nodes
+                                .get(node_id)
+                                .is_some_and(|node| node.accepts_new_replicas()),
+                            Reverse(*node_id),
+                        )
+                    });
+                    movements.extend(
+                        candidates
+                            .into_iter()
+                            .take(fu.replia_diff().unsigned_abs() as usize)
+                            .map(|node_id| Movement::DropReplica {
+                                fu_id,
+                                node: node_id,
+                            }),
+                    );
+                }
+            }
+            Action::Upgrade(fu_ids) => {
+                let mut planner = UpgradePlanner::new(snapshot);
+                for fu_id in fu_ids {
+                    if let Some(fu) = snapshot.failover_units.get(&fu_id) {
+                        movements.extend(planner.plan(fu).1);
                     }
                 }
-                Action::Upgrade(_) => todo!(),
-                Action::LoadBalancing => todo!(),
-                Action::Defragmentation => todo!(),
-                Action::FixConstraintViolation => todo!(),
             }
+            // The dummy PLB does not balance, see the other search strategies for balancing
+            Action::LoadBalancing | Action::Defragmentation | Action::FixConstraintViolation => {}
         }
 
-        solutions
+        movements
     }
 }
@@ -0,0 +1,507 @@
+//! This module contains the pluggable search strategies of the PLB engine.
+//!
+//! A [SearchStrategy] receives a read-only view of the cluster snapshot and the phase being run, and returns the
+//! movements for that phase. Every built-in strategy handles the node deactivations, upgrades and extra replicas the
+//! same way, they differ in how new replicas are placed and how the cluster is balanced:
+//!     - [DummyStrategy]: the original dummy PLB, placing on the greatest node id and never balancing
+//!     - [GreedyStrategy]: least loaded placement, balancing by repeatedly applying the best single move
+//!     - [AnnealingStrategy]: least loaded placement, balancing with a simulated annealing search
+//!
+//! With primary swap balancing, the greedy and annealing strategies first swap the primary of the failover units to
+//! one of their secondaries, picked by the promotion comparator, as long as it improves the balance and within the move
+//! budget of the phase. Primary loads being typically much higher than secondary loads, the swaps fix most of the
+//! primary load and primary count imbalance without copying any data. The replicas are then moved on the cluster with
+//! the swaps done.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::{
+    annealing::{AnnealingConfig, AnnealingSearch, SearchStatistics},
+    balance::LoadTable,
+    config::{PLBConfig, SearchStrategyKind},
+    constraint::{NodeRejectReason, PlacementChecker},
+    events::{NoopEventSink, PlbEvent, PlbEventSink},
+    failoverunit::failover_unit::{FailoverUnit, ReplicaRole},
+    node::{node::Node, node_id::NodeId},
+    promotion::select_new_primary,
+    scheduler::Phase,
+    searcher::{Action, Searcher},
+    service::service::Service,
+    solver::{Movement, Solver},
+    ClusterSnapshot,
+};
+
+/// Minimum score improvement for a greedy move to be worth it
+const MIN_SCORE_IMPROVEMENT: f64 = 1e-9;
+
+/// Read-only view of the cluster snapshot handed to the search strategies
+#[derive(Clone, Copy)]
+pub struct SnapshotView<'a> {
+    pub(crate) snapshot: &'a ClusterSnapshot,
+    events: &'a dyn PlbEventSink,
+}
+
+impl<'a> SnapshotView<'a> {
+    pub(crate) fn new(snapshot: &'a ClusterSnapshot) -> Self {
+        SnapshotView {
+            snapshot,
+            events: &NoopEventSink,
+        }
+    }
+
+    pub(crate) fn with_event_sink(mut self, events: &'a dyn PlbEventSink) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// The event sink of the engine, for the strategies to report their own events
+    pub fn events(&self) -> &'a dyn PlbEventSink {
+        self.events
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &'a Node> {
+        self.snapshot.nodes.values()
+    }
+
+    pub fn node(&self, node_id: NodeId) -> Option<&'a Node> {
+        self.snapshot.nodes.get(&node_id)
+    }
+
+    pub fn failover_units(&self) -> impl Iterator<Item = &'a FailoverUnit> {
+        self.snapshot.failover_units.values()
+    }
+
+    pub fn failover_unit(&self, fu_id: Uuid) -> Option<&'a FailoverUnit> {
+        self.snapshot.failover_units.get(&fu_id)
+    }
+
+    pub fn service(&self, service_name: &str) -> Option<&'a Service> {
+        self.snapshot.services.get(service_name)
+    }
+
+    pub fn metric_names(&self) -> BTreeSet<String> {
+        self.snapshot.metric_names()
+    }
+
+    /// Aggregated load of the replicas hosted on the node for the metric
+    pub fn node_load(&self, node_id: NodeId, metric_name: &str) -> u32 {
+        self.snapshot.node_load(node_id, metric_name)
+    }
+
+    /// Load of a replica of the failover unit with the given role, for every metric of its service
+    pub fn replica_loads(&self, fu: &FailoverUnit, role: ReplicaRole) -> Vec<(String, u32)> {
+        self.snapshot.replica_loads(fu, role)
+    }
+
+    /// Checks the hard constraints for placing a replica of the failover unit on the node
+    pub fn check_placement(
+        &self,
+        fu: &FailoverUnit,
+        role: ReplicaRole,
+        node_id: NodeId,
+    ) -> Result<(), NodeRejectReason> {
+        PlacementChecker::new(self.snapshot).check(fu, role, node_id)
+    }
+}
+
+/// The result of a search strategy for a phase
+#[derive(Debug, Clone, Default)]
+pub struct SearchOutcome {
+    pub movements: Vec<Movement>,
+    /// Statistics of the search, for the strategies that run one
+    pub statistics: Option<SearchStatistics>,
+}
+
+/// An algorithm generating the movements of a PLB phase
+pub trait SearchStrategy {
+    fn name(&self) -> &str;
+
+    fn search(&mut self, view: SnapshotView<'_>, phase: Phase) -> SearchOutcome;
+}
+
+/// Creates the built-in strategy of the given kind
+pub(crate) fn create_strategy(
+    kind: SearchStrategyKind,
+    config: &PLBConfig,
+) -> Box<dyn SearchStrategy> {
+    match kind {
+        SearchStrategyKind::Dummy => Box::new(DummyStrategy),
+        SearchStrategyKind::Greedy => Box::new(
+            GreedyStrategy::new(config.greedy_max_moves)
+                .with_primary_swaps(config.primary_swap_balancing),
+        ),
+        SearchStrategyKind::Annealing => Box::new(
+            AnnealingStrategy::new(config.annealing.clone()).with_primary_swaps(
+                if config.primary_swap_balancing {
+                    config.greedy_max_moves
+                } else {
+                    0
+                },
+            ),
+        ),
+    }
+}
+
+/// The original dummy PLB: the [Searcher] finds the actions and the [Solver] turns them into movements
+#[derive(Debug, Clone, Default)]
+pub struct DummyStrategy;
+
+impl SearchStrategy for DummyStrategy {
+    fn name(&self) -> &str {
+        "Dummy"
+    }
+
+    fn search(&mut self, view: SnapshotView<'_>, phase: Phase) -> SearchOutcome {
+        let actions = Searcher::new(view.snapshot)
+            .with_event_sink(view.events)
+            .generate_actions(phase);
+        SearchOutcome {
+            movements: Solver::new(view.snapshot)
+                .with_event_sink(view.events)
+                .generate_movements(actions),
+            statistics: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GreedyStrategy {
+    max_moves: usize,
+    primary_swaps: bool,
+}
+
+impl GreedyStrategy {
+    pub fn new(max_moves: usize) -> Self {
+        GreedyStrategy {
+            max_moves,
+            primary_swaps: false,
+        }
+    }
+
+    /// Swaps primaries before moving replicas when balancing, see [crate::strategy]. The swaps count against the
+    /// maximum number of moves.
+    pub fn with_primary_swaps(mut self, primary_swaps: bool) -> Self {
+        self.primary_swaps = primary_swaps;
+        self
+    }
+}
+
+impl SearchStrategy for GreedyStrategy {
+    fn name(&self) -> &str {
+        "Greedy"
+    }
+
+    fn search(&mut self, view: SnapshotView<'_>, phase: Phase) -> SearchOutcome {
+        let snapshot = view.snapshot;
+        let mut outcome = SearchOutcome::default();
+        for action in Searcher::new(snapshot)
+            .with_event_sink(view.events)
+            .generate_actions(phase)
+        {
+            match action {
+                Action::NewReplicaPlacement(fu_ids) => outcome
+                    .movements
+                    .extend(place_on_least_loaded(snapshot, fu_ids, view.events)),
+                Action::LoadBalancing if self.primary_swaps => {
+                    let (swaps, movements) =
+                        balance_after_swaps(snapshot, self.max_moves, |snapshot, swap_count| {
+                            greedy_balance(snapshot, self.max_moves - swap_count)
+                        });
+                    outcome.movements.extend(swaps);
+                    outcome.movements.extend(movements);
+                }
+                Action::LoadBalancing | Action::FixConstraintViolation => outcome
+                    .movements
+                    .extend(greedy_balance(snapshot, self.max_moves)),
+                action => outcome.movements.extend(
+                    Solver::new(snapshot)
+                        .with_event_sink(view.events)
+                        .solve(action),
+                ),
+            }
+        }
+
+        outcome
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AnnealingStrategy {
+    config: AnnealingConfig,
+    max_swaps: usize,
+}
+
+impl AnnealingStrategy {
+    pub fn new(config: AnnealingConfig) -> Self {
+        AnnealingStrategy {
+            config,
+            max_swaps: 0,
+        }
+    }
+
+    /// Swaps up to `max_swaps` primaries before searching when balancing, see [crate::strategy]. 0 disables the swaps.
+    pub fn with_primary_swaps(mut self, max_swaps: usize) -> Self {
+        self.max_swaps = max_swaps;
+        self
+    }
+}
+
+impl SearchStrategy for AnnealingStrategy {
+    fn name(&self) -> &str {
+        "Annealing"
+    }
+
+    fn search(&mut self, view: SnapshotView<'_>, phase: Phase) -> SearchOutcome {
+        let snapshot = view.snapshot;
+        let mut outcome = SearchOutcome::default();
+        for action in Searcher::new(snapshot)
+            .with_event_sink(view.events)
+            .generate_actions(phase)
+        {
+            match action {
+                Action::NewReplicaPlacement(fu_ids) => outcome
+                    .movements
+                    .extend(place_on_least_loaded(snapshot, fu_ids, view.events)),
+                Action::LoadBalancing if self.max_swaps > 0 => {
+                    let (swaps, (movements, statistics)) =
+                        balance_after_swaps(snapshot, self.max_swaps, |snapshot, _| {
+                            AnnealingSearch::new(snapshot, &self.config).run()
+                        });
+                    outcome.movements.extend(swaps);
+                    outcome.movements.extend(movements);
+                    outcome.statistics = Some(statistics);
+                }
+                Action::LoadBalancing | Action::FixConstraintViolation => {
+                    // the search score penalizes capacity overflows far more than imbalance, so the same search
+                    // fixes the violations first and then balances
+                    let (movements, statistics) =
+                        AnnealingSearch::new(snapshot, &self.config).run();
+                    outcome.movements.extend(movements);
+                    outcome.statistics = Some(statistics);
+                }
+                action => outcome.movements.extend(
+                    Solver::new(snapshot)
+                        .with_event_sink(view.events)
+                        .solve(action),
+                ),
+            }
+        }
+
+        outcome
+    }
+}
+
+/// Places a new replica of each failover unit on the least loaded node that can take it
+fn place_on_least_loaded(
+    snapshot: &ClusterSnapshot,
+    fu_ids: Vec<Uuid>,
+    events: &dyn PlbEventSink,
+) -> Vec<Movement> {
+    let mut checker = PlacementChecker::new(snapshot);
+    let mut movements = vec![];
+    for fu_id in fu_ids {
+        let Some(fu) = snapshot.failover_units.get(&fu_id) else {
+            continue;
+        };
+        match checker.select_target(fu, ReplicaRole::Secondary, None) {
+            Some(node_id) => {
+                checker.apply(fu, ReplicaRole::Secondary, None, Some(node_id));
+                movements.push(Movement::AddReplica {
+                    fu_id,
+                    node: node_id,
+                });
+            }
+            None => events.on_event(&PlbEvent::ReplicaUnplaceable { fu_id }),
+        }
+    }
+
+    movements
+}
+
+/// Swaps up to `max_swaps` primaries while it improves the balance, then runs the balancing on a copy of the snapshot
+/// with the swaps done, given the number of swaps. Returns the swaps and the result of the balancing.
+fn balance_after_swaps<R>(
+    snapshot: &ClusterSnapshot,
+    max_swaps: usize,
+    balance: impl FnOnce(&ClusterSnapshot, usize) -> R,
+) -> (Vec<Movement>, R) {
+    let swaps = swap_primaries(snapshot, max_swaps);
+    if swaps.is_empty() {
+        return (swaps, balance(snapshot, 0));
+    }
+
+    let mut swapped = snapshot.clone();
+    for swap in &swaps {
+        let mut fu = swapped.failover_units[&swap.fu_id()].clone();
+        // the swaps are generated from the roles of the snapshot, so they apply
+        let _ = fu.failover_unit_description.apply_movement(swap);
+        swapped.insert_failover_unit(fu);
+    }
+    let swap_count = swaps.len();
+    (swaps, balance(&swapped, swap_count))
+}
+
+/// Repeatedly swaps the primary of the failover unit that improves the cluster score the most, touching each failover
+/// unit at most once and generating at most `max_swaps` swaps. The candidates of a swap are the primaries of the most
+/// loaded node and the secondaries of the least loaded node. The new primary of a failover unit is the secondary
+/// preferred by the promotion comparator among the ones improving the score.
+fn swap_primaries(snapshot: &ClusterSnapshot, max_swaps: usize) -> Vec<Movement> {
+    let mut loads = LoadTable::new(snapshot);
+    let mut touched = HashSet::<Uuid>::new();
+    let mut swaps = vec![];
+
+    let up_nodes = snapshot
+        .nodes
+        .iter()
+        .filter(|(_, node)| node.is_up())
+        .map(|(node_id, _)| *node_id)
+        .collect::<Vec<NodeId>>();
+    while swaps.len() < max_swaps {
+        let current_score = loads.score();
+        let by_load = |node1: &NodeId, node2: &NodeId| {
+            loads
+                .weighted_node_load(*node1)
+                .total_cmp(&loads.weighted_node_load(*node2))
+        };
+        let (Some(most_loaded), Some(least_loaded)) = (
+            up_nodes.iter().copied().max_by(by_load),
+            up_nodes.iter().copied().min_by(by_load),
+        ) else {
+            break;
+        };
+        let candidates = snapshot
+            .replicas_on(most_loaded)
+            .filter(|(_, replica)| replica.role() == ReplicaRole::Primary)
+            .chain(
+                snapshot
+                    .replicas_on(least_loaded)
+                    .filter(|(_, replica)| replica.role() == ReplicaRole::Secondary),
+            )
+            .map(|(fu, _)| fu)
+            .filter(|fu| !touched.contains(&fu.id()))
+            .collect::<Vec<&FailoverUnit>>();
+
+        let mut best: Option<(f64, &FailoverUnit, NodeId, NodeId)> = None;
+        for fu in candidates {
+            let Some(from) = fu
+                .primary()
+                .map(|primary| primary.location())
+                .filter(|from| up_nodes.contains(from))
+            else {
+                continue;
+            };
+            let mut scores = HashMap::<NodeId, f64>::new();
+            for replica in fu
+                .active_replicas()
+                .filter(|replica| replica.role() == ReplicaRole::Secondary)
+            {
+                let to = replica.location();
+                if snapshot.is_upgrading(fu, to) {
+                    continue;
+                }
+                loads.swap_primary(snapshot, fu, from, to);
+                let score = loads.score();
+                loads.swap_primary(snapshot, fu, to, from);
+                if score < current_score - MIN_SCORE_IMPROVEMENT {
+                    scores.insert(to, score);
+                }
+            }
+            let Some(to) =
+                select_new_primary(snapshot, fu, |node_id| !scores.contains_key(&node_id))
+            else {
+                continue;
+            };
+            if best.is_none_or(|(best_score, ..)| scores[&to] < best_score) {
+                best = Some((scores[&to], fu, from, to));
+            }
+        }
+
+        let Some((_, fu, from, to)) = best else {
+            break;
+        };
+        loads.swap_primary(snapshot, fu, from, to);
+        touched.insert(fu.id());
+        swaps.push(Movement::SwapPrimary {
+            fu_id: fu.id(),
+            from,
+            to,
+        });
+    }
+
+    swaps
+}
+
+/// Repeatedly moves the replica of the most loaded node that improves the cluster score the most,
+/// touching each failover unit at most once
+fn greedy_balance(snapshot: &ClusterSnapshot, max_moves: usize) -> Vec<Movement> {
+    let mut loads = LoadTable::new(snapshot);
+    let mut checker = PlacementChecker::new(snapshot);
+    let mut touched = HashSet::<Uuid>::new();
+    let mut movements = vec![];
+
+    let up_nodes = snapshot
+        .nodes
+        .iter()
+        .filter(|(_, node)| node.is_up())
+        .map(|(node_id, _)| *node_id)
+        .collect::<Vec<NodeId>>();
+    let mut replicas = snapshot
+        .failover_units
+        .values()
+        .flat_map(|fu| {
+            fu.active_replicas()
+                .map(move |replica| (fu, replica.role(), replica.location()))
+        })
+        .collect::<Vec<(&FailoverUnit, ReplicaRole, NodeId)>>();
+    replicas.sort_by_key(|(fu, _, node_id)| (fu.id(), *node_id));
+
+    while movements.len() < max_moves {
+        let current_score = loads.score();
+        let Some(source) = up_nodes.iter().copied().max_by(|node1, node2| {
+            loads
+                .weighted_node_load(*node1)
+                .total_cmp(&loads.weighted_node_load(*node2))
+        }) else {
+            break;
+        };
+
+        let mut best: Option<(f64, usize, NodeId)> = None;
+        for (index, (fu, role, location)) in replicas.iter().enumerate() {
+            if *location != source || touched.contains(&fu.id()) {
+                continue;
+            }
+            for target in up_nodes.iter().copied().filter(|target| *target != source) {
+                if checker.check_move(fu, *role, Some(source), target).is_err() {
+                    continue;
+                }
+                loads.move_replica(snapshot, fu, *role, source, target);
+                let score = loads.score();
+                loads.move_replica(snapshot, fu, *role, target, source);
+                if best.is_none_or(|(best_score, _, _)| score < best_score) {
+                    best = Some((score, index, target));
+                }
+            }
+        }
+
+        match best {
+            Some((score, index, target)) if score < current_score - MIN_SCORE_IMPROVEMENT => {
+                let (fu, role, _) = replicas[index];
+                loads.move_replica(snapshot, fu, role, source, target);
+                checker.apply(fu, role, Some(source), Some(target));
+                replicas[index].2 = target;
+                touched.insert(fu.id());
+                movements.push(Movement::MoveReplica {
+                    fu_id: fu.id(),
+                    role,
+                    from: source,
+                    to: target,
+                });
+            }
+            _ => break,
+        }
+    }
+
+    movements
+}
@@ -0,0 +1,379 @@
+//! This module records the calls made to the PLB engine to a trace file and replays them, so that the solutions of a
+//! production engine can be reproduced offline.
+//!
+//! The trace is an append-only JSON Lines file. It starts with the cluster snapshot, the scheduler state and the
+//! configuration at the time the recording started, followed by one line per update, deletion, upgrade, configuration
+//! change and refresh, in the order of the calls. Each refresh line carries the solutions the engine generated.
+//!
+//! Replaying a trace rebuilds the engine from the first line, repeats every call and compares the solutions of every
+//! refresh with the recorded ones. Only the built-in search strategies can be replayed, and the searches must be
+//! bounded by their iteration count rather than by their wall-clock budget to be reproducible.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    application::application_description::ApplicationDescription,
+    config::PLBConfig,
+    failoverunit::failover_unit::FailoverUnitDescription,
+    forecast::LoadHistory,
+    load::load_or_move_cost::LoadOrMoveCostDescription,
+    node::{node_description::NodeDescription, node_id::NodeId},
+    pending::{MovementOutcome, PendingMovements, SolutionId},
+    scheduler::{PLBScheduler, Phase},
+    service::service_description::ServiceDescription,
+    servicetype::service_type_description::ServiceTypeDescription,
+    solver::Solution,
+    upgrade::UpgradeScope,
+    ClusterSnapshot, Deletion, PlacementAndLoadBalancing,
+};
+
+/// A line of the trace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum TraceRecord {
+    Start {
+        snapshot: serde_json::Value,
+        scheduler: PLBScheduler,
+        config: PLBConfig,
+        /// Solutions not acknowledged yet when the recording started
+        #[serde(default)]
+        pending: PendingMovements,
+        /// Loads reported before the recording started, for the forecasts to replay identically
+        #[serde(default)]
+        load_history: LoadHistory,
+    },
+    UpdateNode(NodeDescription),
+    UpdateApplication(ApplicationDescription),
+    UpdateServiceType(ServiceTypeDescription),
+    UpdateService(ServiceDescription),
+    UpdateFailoverUnit(FailoverUnitDescription),
+    UpdateLoad(LoadOrMoveCostDescription),
+    DeleteNode(NodeId),
+    DeleteApplication(String),
+    DeleteServiceType(String),
+    DeleteService(String),
+    DeleteFailoverUnit(Uuid),
+    StartUpgradeDomain {
+        scope: UpgradeScope,
+        upgrade_domain: String,
+    },
+    CompleteUpgrade(UpgradeScope),
+    SetConfig(PLBConfig),
+    /// A custom search strategy was set, the trace cannot be replayed from there on
+    SetSearchStrategy {
+        phase: Phase,
+        name: String,
+    },
+    Refresh {
+        now: OffsetDateTime,
+        solutions: Vec<Solution>,
+    },
+    AcknowledgeSolution {
+        id: SolutionId,
+        outcome: MovementOutcome,
+    },
+}
+
+/// Appends records to a trace file. Writing errors stop the recording, they are reported when it is stopped.
+pub(crate) struct TraceRecorder {
+    writer: BufWriter<File>,
+    error: Option<anyhow::Error>,
+}
+
+impl TraceRecorder {
+    fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| anyhow!("Failed to open trace {}: {}", path.display(), err))?;
+
+        Ok(TraceRecorder {
+            writer: BufWriter::new(file),
+            error: None,
+        })
+    }
+
+    pub(crate) fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = serde_json::to_writer(&mut self.writer, record)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(self.writer.write_all(b"\n")?));
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+
+    pub(crate) fn flush(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = self.writer.flush() {
+            self.error = Some(err.into());
+        }
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.flush();
+        match self.error {
+            Some(err) => Err(anyhow!("Failed to write trace: {}", err)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A refresh whose replayed solutions differ from the recorded ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayMismatch {
+    /// Index of the refresh in the trace, starting at 0
+    pub refresh_index: usize,
+    pub now: OffsetDateTime,
+    pub recorded: Vec<Solution>,
+    pub replayed: Vec<Solution>,
+}
+
+/// The result of replaying a trace
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Number of refreshes replayed
+    pub refreshes: usize,
+    pub mismatches: Vec<ReplayMismatch>,
+}
+
+impl ReplayReport {
+    /// Whether every replayed refresh produced the recorded solutions
+    pub fn is_identical(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl PlacementAndLoadBalancing {
+    /// Starts recording every call made to the engine to the trace file. The file is appended to if it exists.
+    /// The trace starts with the current cluster snapshot, scheduler state and configuration, followed by the updates
+    /// that are pending for the next refresh.
+    pub fn start_recording(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let mut recorder = TraceRecorder::open(path.as_ref())?;
+        recorder.record(&TraceRecord::Start {
+            snapshot: serde_json::to_value(&*self.cluster_snapshot.borrow())?,
+            scheduler: self.scheduler.clone(),
+            config: self.config.clone(),
+            pending: self.pending.clone(),
+            load_history: self.cluster_snapshot.borrow().load_history.clone(),
+        });
+        for record in self.pending_update_records() {
+            recorder.record(&record);
+        }
+        recorder.flush();
+
+        let previous = self.recorder.replace(Some(recorder));
+        match previous {
+            Some(previous) => previous.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops recording. Returns an error if any record could not be written to the trace.
+    pub fn stop_recording(&mut self) -> Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Rebuilds an engine from the trace file, repeats every recorded call and compares the solutions of every
+    /// refresh with the recorded ones. Returns an error if the trace cannot be read or replayed.
+    pub fn replay_trace(path: impl AsRef<Path>) -> Result<ReplayReport> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|err| anyhow!("Failed to open trace {}: {}", path.display(), err))?;
+
+        let mut plb: Option<PlacementAndLoadBalancing> = None;
+        let mut report = ReplayReport::default();
+        for (line_index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str::<TraceRecord>(&line).map_err(|err| {
+                anyhow!(
+                    "Invalid record on line {} of the trace: {}",
+                    line_index + 1,
+                    err
+                )
+            })?;
+
+            if let TraceRecord::Start {
+                snapshot,
+                scheduler,
+                config,
+                pending,
+                load_history,
+            } = record
+            {
+                let mut snapshot = serde_json::from_value::<ClusterSnapshot>(snapshot)?;
+                snapshot.load_history = load_history;
+                snapshot.rebuild_indexes();
+                let mut replayed = Self::with_snapshot(snapshot);
+                replayed.scheduler = scheduler;
+                replayed
+                    .cluster_snapshot
+                    .borrow_mut()
+                    .set_default_metrics(&config.default_metrics);
+                replayed.config = config;
+                replayed.pending = pending;
+                plb = Some(replayed);
+                continue;
+            }
+            let Some(plb) = plb.as_mut() else {
+                return Err(anyhow!("The trace does not start with the engine state"));
+            };
+            match record {
+                TraceRecord::Start { .. } => unreachable!(),
+                TraceRecord::UpdateNode(node_desc) => plb.update_node(node_desc),
+                TraceRecord::UpdateApplication(app_desc) => plb.update_application(app_desc),
+                TraceRecord::UpdateServiceType(service_type_desc) => {
+                    plb.update_service_type(service_type_desc)
+                }
+                TraceRecord::UpdateService(service_desc) => plb.update_service(service_desc)?,
+                TraceRecord::UpdateFailoverUnit(fu_desc) => plb.update_failover_unit(fu_desc),
+                TraceRecord::UpdateLoad(load_desc) => plb.update_load_or_move_cost(load_desc),
+                TraceRecord::DeleteNode(node_id) => plb.delete_node(node_id)?,
+                TraceRecord::DeleteApplication(app_name) => plb.delete_application(&app_name)?,
+                TraceRecord::DeleteServiceType(service_type_name) => {
+                    plb.delete_service_type(&service_type_name)?
+                }
+                TraceRecord::DeleteService(service_name) => plb.delete_service(&service_name)?,
+                TraceRecord::DeleteFailoverUnit(fu_id) => plb.delete_failover_unit(fu_id)?,
+                TraceRecord::StartUpgradeDomain {
+                    scope,
+                    upgrade_domain,
+                } => plb.start_upgrade_domain(scope, &upgrade_domain),
+                TraceRecord::CompleteUpgrade(scope) => plb.complete_upgrade(scope),
+                TraceRecord::SetConfig(config) => plb.set_config(config),
+                TraceRecord::SetSearchStrategy { phase, name } => {
+                    return Err(anyhow!(
+                        "The trace uses the custom search strategy {} for the {:?} phase, it cannot be replayed",
+                        name,
+                        phase
+                    ));
+                }
+                TraceRecord::Refresh { now, solutions } => {
+                    let replayed = plb.refresh(now)?.solutions();
+                    if replayed != solutions {
+                        report.mismatches.push(ReplayMismatch {
+                            refresh_index: report.refreshes,
+                            now,
+                            recorded: solutions,
+                            replayed,
+                        });
+                    }
+                    report.refreshes += 1;
+                }
+                TraceRecord::AcknowledgeSolution { id, outcome } => {
+                    plb.acknowledge_solution(id, outcome)?
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Appends the record to the trace, if the engine is recording
+    pub(crate) fn record(&self, record: impl FnOnce() -> TraceRecord) {
+        if let Some(recorder) = self.recorder.borrow_mut().as_mut() {
+            let record = record();
+            recorder.record(&record);
+            if matches!(record, TraceRecord::Refresh { .. }) {
+                recorder.flush();
+            }
+        }
+    }
+
+    /// The updates waiting in the update queue, as trace records
+    fn pending_update_records(&self) -> Vec<TraceRecord> {
+        let update_queue = self.plb_update_queue.lock().unwrap();
+        let mut records = vec![];
+        records.extend(
+            update_queue
+                .node_update_queue
+                .iter()
+                .map(|node| TraceRecord::UpdateNode(node.node_description.clone())),
+        );
+        records.extend(
+            update_queue
+                .app_update_queue
+                .iter()
+                .map(|app| TraceRecord::UpdateApplication(app.application_desc.clone())),
+        );
+        records.extend(
+            update_queue
+                .service_type_update_queue
+                .iter()
+                .map(|service_type| {
+                    TraceRecord::UpdateServiceType(service_type.service_type_desc.clone())
+                }),
+        );
+        records.extend(
+            update_queue
+                .service_update_queue
+                .iter()
+                .map(|service| TraceRecord::UpdateService(service.service_description.clone())),
+        );
+        records.extend(
+            update_queue
+                .failover_unit_update_queue
+                .iter()
+                .map(|fu| TraceRecord::UpdateFailoverUnit(fu.failover_unit_description.clone())),
+        );
+        records.extend(
+            update_queue
+                .load_update_queue
+                .iter()
+                .map(|load| TraceRecord::UpdateLoad(load.load_description.clone())),
+        );
+        records.extend(
+            update_queue
+                .upgrade_update_queue
+                .iter()
+                .map(|(scope, upgrade_domain)| match upgrade_domain {
+                    Some(upgrade_domain) => TraceRecord::StartUpgradeDomain {
+                        scope: scope.clone(),
+                        upgrade_domain: upgrade_domain.clone(),
+                    },
+                    None => TraceRecord::CompleteUpgrade(scope.clone()),
+                }),
+        );
+        records.extend(
+            update_queue
+                .deletion_queue
+                .iter()
+                .map(|deletion| deletion.to_record()),
+        );
+
+        records
+    }
+}
+
+impl Deletion {
+    pub(crate) fn to_record(&self) -> TraceRecord {
+        match self {
+            Deletion::Node(node_id) => TraceRecord::DeleteNode(*node_id),
+            Deletion::Application(app_name) => TraceRecord::DeleteApplication(app_name.clone()),
+            Deletion::ServiceType(service_type_name) => {
+                TraceRecord::DeleteServiceType(service_type_name.clone())
+            }
+            Deletion::Service(service_name) => TraceRecord::DeleteService(service_name.clone()),
+            Deletion::FailoverUnit(fu_id) => TraceRecord::DeleteFailoverUnit(*fu_id),
+        }
+    }
+}
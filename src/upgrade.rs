@@ -0,0 +1,216 @@
+//! This module contains the upgrade domain walk support for application and cluster upgrades.
+//!
+//! When an upgrade domain (UD) is being upgraded, PLB prepares the failover units in the scope of the upgrade:
+//!     1. primaries in the UD are swapped out to a secondary outside of the UD, chosen by the CNFP logic
+//!     2. singleton partitions in the UD are relocated to a node outside of the UD ahead of the upgrade
+//!     3. no new replica is placed in the UD until the upgrade of the UD is completed
+//!
+//! An application upgrade can run during a cluster upgrade: the services of the application are then affected by the
+//! UDs of both upgrades.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use uuid::Uuid;
+
+use crate::{
+    constraint::PlacementChecker,
+    failoverunit::failover_unit::FailoverUnit,
+    node::{node_description::DomainId, node_id::NodeId},
+    promotion::select_new_primary,
+    service::service::Service,
+    solver::Movement,
+    ClusterSnapshot,
+};
+
+/// The scope of an upgrade: either every service of the cluster or the services of a single application
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UpgradeScope {
+    Cluster,
+    Application(String),
+}
+
+impl UpgradeScope {
+    pub fn contains(&self, service: &Service) -> bool {
+        match self {
+            UpgradeScope::Cluster => true,
+            UpgradeScope::Application(app_name) => service.application_name() == app_name,
+        }
+    }
+}
+
+/// Whether a failover unit allows the upgrade of the upgrade domain to proceed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeReadiness {
+    /// No replica of the failover unit blocks the upgrade domain
+    Ready,
+    /// The primary is in the upgrade domain and is being swapped out
+    PrimarySwapPending,
+    /// The failover unit is a singleton in the upgrade domain and is being relocated
+    RelocationPending,
+    /// The failover unit blocks the upgrade domain and PLB cannot find a movement for it
+    Blocked,
+}
+
+/// The readiness of the failover units affected by the upgrade of an upgrade domain
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpgradeDomainReadiness {
+    pub upgrade_domain: DomainId,
+    pub failover_units: BTreeMap<Uuid, UpgradeReadiness>,
+}
+
+impl UpgradeDomainReadiness {
+    /// The upgrade domain can proceed once every affected failover unit is ready
+    pub fn is_ready(&self) -> bool {
+        self.failover_units
+            .values()
+            .all(|readiness| *readiness == UpgradeReadiness::Ready)
+    }
+}
+
+impl ClusterSnapshot {
+    /// Returns the upgrade domains being upgraded for the service of the failover unit, one per upgrade whose scope
+    /// contains the service
+    pub(crate) fn upgrading_domains_of(&self, fu: &FailoverUnit) -> Vec<&str> {
+        let Some(service) = self.service_of(fu) else {
+            return vec![];
+        };
+        self.upgrades
+            .iter()
+            .filter(|(scope, _)| scope.contains(service))
+            .map(|(_, upgrade_domain)| upgrade_domain.as_str())
+            .collect()
+    }
+
+    /// Whether the node belongs to an upgrade domain being upgraded for the service of the failover unit
+    pub(crate) fn is_upgrading(&self, fu: &FailoverUnit, node_id: NodeId) -> bool {
+        let Some(node) = self.nodes.get(&node_id) else {
+            return false;
+        };
+        self.upgrading_domains_of(fu)
+            .into_iter()
+            .any(|upgrade_domain| node.upgrade_domain() == upgrade_domain)
+    }
+
+    /// Returns the failover units with at least one replica in an upgrade domain being upgraded
+    pub(crate) fn fus_in_upgrading_domains(&self) -> Vec<Uuid> {
+        let upgrading_domains = self
+            .upgrades
+            .values()
+            .map(|upgrade_domain| upgrade_domain.as_str())
+            .collect::<BTreeSet<&str>>();
+        self.nodes
+            .iter()
+            .filter(|(_, node)| upgrading_domains.contains(node.upgrade_domain()))
+            .flat_map(|(node_id, _)| self.replicas_on(*node_id))
+            .filter(|(fu, replica)| self.is_upgrading(fu, replica.location()))
+            .map(|(fu, _)| fu.id())
+            .collect::<BTreeSet<Uuid>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+pub(crate) struct UpgradePlanner<'a> {
+    snapshot: &'a ClusterSnapshot,
+    checker: PlacementChecker<'a>,
+}
+
+impl<'a> UpgradePlanner<'a> {
+    pub(crate) fn new(snapshot: &'a ClusterSnapshot) -> Self {
+        UpgradePlanner {
+            snapshot,
+            checker: PlacementChecker::new(snapshot),
+        }
+    }
+
+    /// Plans the movement required for the failover unit to let its upgrade domain be upgraded.
+    /// Returns the readiness of the failover unit together with the movement, if one is required.
+    pub(crate) fn plan(&mut self, fu: &FailoverUnit) -> (UpgradeReadiness, Option<Movement>) {
+        let in_upgrading_domain = fu
+            .active_replicas()
+            .filter(|replica| self.snapshot.is_upgrading(fu, replica.location()))
+            .collect::<Vec<_>>();
+
+        // singleton partitions would be unavailable during the upgrade, so they are relocated ahead of it
+        if fu.active_replicas().count() == 1 {
+            let Some(replica) = in_upgrading_domain.first() else {
+                return (UpgradeReadiness::Ready, None);
+            };
+            return match self
+                .checker
+                .select_target(fu, replica.role(), Some(replica.location()))
+            {
+                Some(target) => {
+                    self.checker
+                        .apply(fu, replica.role(), Some(replica.location()), Some(target));
+                    (
+                        UpgradeReadiness::RelocationPending,
+                        Some(Movement::MoveReplica {
+                            fu_id: fu.id(),
+                            role: replica.role(),
+                            from: replica.location(),
+                            to: target,
+                        }),
+                    )
+                }
+                None => (UpgradeReadiness::Blocked, None),
+            };
+        }
+
+        let Some(primary) = in_upgrading_domain
+            .iter()
+            .find(|replica| replica.is_primary())
+        else {
+            return (UpgradeReadiness::Ready, None);
+        };
+        let new_primary = select_new_primary(self.snapshot, fu, |candidate| {
+            self.snapshot.is_upgrading(fu, candidate)
+        });
+        match new_primary {
+            Some(new_primary) => {
+                self.checker.apply_swap(fu, primary.location(), new_primary);
+                (
+                    UpgradeReadiness::PrimarySwapPending,
+                    Some(Movement::SwapPrimary {
+                        fu_id: fu.id(),
+                        from: primary.location(),
+                        to: new_primary,
+                    }),
+                )
+            }
+            None => (UpgradeReadiness::Blocked, None),
+        }
+    }
+
+    /// Computes the readiness of every failover unit in the scope of the upgrade with a replica in its upgrade domain.
+    /// The readiness of a failover unit accounts for the upgrade domains of every upgrade it is affected by.
+    pub(crate) fn readiness(&mut self, scope: &UpgradeScope) -> Option<UpgradeDomainReadiness> {
+        let upgrade_domain = self.snapshot.upgrades.get(scope)?;
+        let failover_units = self
+            .snapshot
+            .fus_in_upgrading_domains()
+            .into_iter()
+            .filter_map(|fu_id| self.snapshot.failover_units.get(&fu_id))
+            .filter(|fu| {
+                self.snapshot
+                    .service_of(fu)
+                    .is_some_and(|service| scope.contains(service))
+            })
+            .filter(|fu| {
+                fu.active_replicas().any(|replica| {
+                    self.snapshot
+                        .nodes
+                        .get(&replica.location())
+                        .is_some_and(|node| node.upgrade_domain() == upgrade_domain)
+                })
+            })
+            .map(|fu| (fu.id(), self.plan(fu).0))
+            .collect();
+
+        Some(UpgradeDomainReadiness {
+            upgrade_domain: upgrade_domain.clone(),
+            failover_units,
+        })
+    }
+}
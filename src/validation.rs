@@ -0,0 +1,332 @@
+//! This module validates a plan, a sequence of movements, against the cluster snapshot.
+//!
+//! The movements are applied in order to a scratch copy of the failover units they touch, on top of the loads and
+//! replica locations changed by the movements before them, and each one is checked against every hard constraint:
+//!     - the movement must apply to the failover unit: the replica it moves, drops or swaps exists with the
+//!       expected role, and no replica is added or moved to a node already hosting one
+//!     - a new or moved replica must be placeable on its target node: node up and not deactivating, placement
+//!       constraints, block list, upgrade domain being upgraded, fault domain, affinity and capacity
+//!     - a swapped primary must fit on its target node: node up, upgrade domain not being upgraded and capacity
+//!
+//! The movements must also keep the write quorum of their failover unit:
+//!     - at most one replica of a failover unit is in motion, i.e. moved or dropped, counting the movements in flight
+//!     - a failover unit below the minimum replica set size of its service, or that would fall below it, never has a
+//!       replica moved or dropped
+//!     - the replica of a singleton failover unit is moved make-before-break: the move is replaced by adding a replica
+//!       on the target node, swapping the primary to it if the moved replica is the primary, and dropping the replica
+//!       on the source node. Each step after the add is a follow-up of the step before it: FM must not carry it out
+//!       before the previous step succeeded, and the engine only hands it out once that step is acknowledged, see
+//!       [crate::pending]
+//!
+//! A movement failing a check is rejected and not applied, so the movements after it are validated without it.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{anyhow, Result};
+use uuid::Uuid;
+
+use crate::{
+    constraint::{NodeRejectReason, PlacementChecker},
+    failoverunit::failover_unit::{FailoverUnit, FailoverUnitDescription, ReplicaRole},
+    node::node_id::NodeId,
+    pending::PendingMovements,
+    solver::Movement,
+    ClusterSnapshot,
+};
+
+/// Why a movement of a plan is rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlanViolation {
+    /// The failover unit of the movement is not in the cluster snapshot
+    FailoverUnitNotFound,
+    /// The movement does not apply to the replicas of the failover unit
+    InvalidMovement(String),
+    /// The target node of the movement fails a hard constraint
+    ConstraintViolated {
+        node_id: NodeId,
+        reason: NodeRejectReason,
+    },
+    /// Another replica of the failover unit is already moved or dropped, by the plan or a movement in flight
+    ReplicaInMotion,
+    /// The failover unit has, or would be left with, fewer active replicas than the minimum replica set size of its
+    /// service
+    BelowMinReplicaSetSize {
+        replicas: usize,
+        min_replica_set_size: usize,
+    },
+}
+
+/// A movement of a plan rejected by the validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedMovement {
+    /// Index of the movement in the plan
+    pub index: usize,
+    pub movement: Movement,
+    pub violation: PlanViolation,
+}
+
+/// The outcome of the validation of a plan
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlanValidation {
+    /// The movements that passed the validation, in the order of the plan. The move of the replica of a singleton
+    /// failover unit is replaced by its make-before-break sequence.
+    pub accepted: Vec<Movement>,
+    /// Indexes in `accepted` of the movements to carry out only once the movement before them succeeded
+    pub follow_ups: BTreeSet<usize>,
+    pub rejected: Vec<RejectedMovement>,
+}
+
+impl PlanValidation {
+    pub fn is_valid(&self) -> bool {
+        self.rejected.is_empty()
+    }
+
+    /// Returns the movements of a valid plan, or an error describing every rejected movement
+    pub fn into_result(self) -> Result<Vec<Movement>> {
+        if self.is_valid() {
+            return Ok(self.accepted);
+        }
+
+        let details = self
+            .rejected
+            .iter()
+            .map(|rejected| {
+                format!(
+                    "movement {} ({:?}): {:?}",
+                    rejected.index, rejected.movement, rejected.violation
+                )
+            })
+            .collect::<Vec<String>>();
+        Err(anyhow!(
+            "Invalid plan, {} movements rejected: {}",
+            self.rejected.len(),
+            details.join("; ")
+        ))
+    }
+}
+
+/// Validates movements one after the other, keeping track of the movements accepted so far
+pub(crate) struct PlanValidator<'a> {
+    snapshot: &'a ClusterSnapshot,
+    checker: PlacementChecker<'a>,
+    /// The failover units touched by the accepted movements, with the movements applied
+    scratch: BTreeMap<Uuid, FailoverUnitDescription>,
+    /// The failover units with a replica moved or dropped by an accepted movement or a movement in flight
+    in_motion: BTreeSet<Uuid>,
+}
+
+impl<'a> PlanValidator<'a> {
+    pub(crate) fn new(snapshot: &'a ClusterSnapshot) -> Self {
+        PlanValidator {
+            snapshot,
+            checker: PlacementChecker::new(snapshot),
+            scratch: BTreeMap::new(),
+            in_motion: BTreeSet::new(),
+        }
+    }
+
+    /// Counts the replicas moved or dropped by the movements in flight and their follow-ups as in motion
+    pub(crate) fn with_pending(mut self, pending: &PendingMovements) -> Self {
+        self.in_motion.extend(
+            pending
+                .iter()
+                .map(|pending_movement| &pending_movement.movement)
+                .chain(pending.follow_ups())
+                .filter(|movement| moves_replica(movement))
+                .map(Movement::fu_id),
+        );
+        self
+    }
+
+    /// Validates the movements in order, dropping the ones that are rejected
+    pub(crate) fn validate_plan(&mut self, movements: Vec<Movement>) -> PlanValidation {
+        let mut validation = PlanValidation::default();
+        for (index, movement) in movements.into_iter().enumerate() {
+            match self.validate(&movement) {
+                Ok(accepted) => {
+                    let first = validation.accepted.len();
+                    validation
+                        .follow_ups
+                        .extend(first + 1..first + accepted.len());
+                    validation.accepted.extend(accepted);
+                }
+                Err(violation) => validation.rejected.push(RejectedMovement {
+                    index,
+                    movement,
+                    violation,
+                }),
+            }
+        }
+
+        validation
+    }
+
+    /// Checks the movement against the failover unit, its quorum and the hard constraints, and applies it if it passes.
+    /// Returns the movements to carry out for it.
+    pub(crate) fn validate(&mut self, movement: &Movement) -> Result<Vec<Movement>, PlanViolation> {
+        let fu_id = movement.fu_id();
+        let Some(fu) = self.snapshot.failover_units.get(&fu_id) else {
+            return Err(PlanViolation::FailoverUnitNotFound);
+        };
+        let mut fu_desc = self
+            .scratch
+            .get(&fu_id)
+            .cloned()
+            .unwrap_or_else(|| fu.failover_unit_description.clone());
+        let role_on = |fu_desc: &FailoverUnitDescription, node_id: NodeId| {
+            fu_desc
+                .replicas
+                .values()
+                .find(|replica| replica.is_active() && replica.location() == node_id)
+                .map(|replica| replica.role())
+        };
+        let reject = |node_id: NodeId, reason: NodeRejectReason| {
+            PlanViolation::ConstraintViolated { node_id, reason }
+        };
+        if moves_replica(movement) {
+            if self.in_motion.contains(&fu_id) {
+                return Err(PlanViolation::ReplicaInMotion);
+            }
+            let replicas = fu_desc
+                .replicas
+                .values()
+                .filter(|replica| replica.is_active())
+                .count();
+            let min_replica_set_size = self
+                .snapshot
+                .service_of(fu)
+                .map(|service| service.min_replica_set_size())
+                .unwrap_or_default();
+            let remaining = match movement {
+                Movement::DropReplica { .. } => replicas.saturating_sub(1),
+                _ => replicas,
+            };
+            if remaining < min_replica_set_size {
+                return Err(PlanViolation::BelowMinReplicaSetSize {
+                    replicas,
+                    min_replica_set_size,
+                });
+            }
+        }
+        let mut accepted = vec![movement.clone()];
+
+        match *movement {
+            Movement::AddReplica { node, .. } => {
+                self.checker
+                    .check(fu, ReplicaRole::Secondary, node)
+                    .map_err(|reason| reject(node, reason))?;
+                apply(&mut fu_desc, movement)?;
+                self.checker
+                    .apply(fu, ReplicaRole::Secondary, None, Some(node));
+            }
+            Movement::DropReplica { node, .. } => {
+                let role = role_on(&fu_desc, node);
+                apply(&mut fu_desc, movement)?;
+                if let Some(role) = role {
+                    self.checker.apply(fu, role, Some(node), None);
+                }
+            }
+            Movement::MoveReplica { role, from, to, .. } => {
+                if role_on(&fu_desc, from).is_some_and(|actual| actual != role) {
+                    return Err(PlanViolation::InvalidMovement(format!(
+                        "the replica on {:?} is not {:?}",
+                        from, role
+                    )));
+                }
+                self.checker
+                    .check_move(fu, role, Some(from), to)
+                    .map_err(|reason| reject(to, reason))?;
+                let singleton = fu_desc
+                    .replicas
+                    .values()
+                    .filter(|replica| replica.is_active())
+                    .count()
+                    == 1;
+                if singleton {
+                    accepted = make_before_break(fu_id, role, from, to);
+                    // the sequence moves the replica without changing the replica difference
+                    let replica_diff = fu_desc.replica_diff;
+                    for movement in &accepted {
+                        apply(&mut fu_desc, movement)?;
+                    }
+                    fu_desc.replica_diff = replica_diff;
+                } else {
+                    apply(&mut fu_desc, movement)?;
+                }
+                self.checker.apply(fu, role, Some(from), Some(to));
+            }
+            Movement::SwapPrimary { from, to, .. } => {
+                // the roles are checked before the target node
+                apply(&mut fu_desc, movement)?;
+                self.check_swap(fu, to)
+                    .map_err(|reason| reject(to, reason))?;
+                self.checker.apply_swap(fu, from, to);
+            }
+        }
+        self.scratch.insert(fu_id, fu_desc);
+        if moves_replica(movement) {
+            self.in_motion.insert(fu_id);
+        }
+
+        Ok(accepted)
+    }
+
+    /// Checks whether the secondary of the failover unit on the node can become its primary
+    fn check_swap(&self, fu: &FailoverUnit, node_id: NodeId) -> Result<(), NodeRejectReason> {
+        let Some(node) = self.snapshot.nodes.get(&node_id) else {
+            return Err(NodeRejectReason::NodeNotFound);
+        };
+        if !node.is_up() {
+            return Err(NodeRejectReason::NodeDown);
+        }
+        if self.snapshot.is_upgrading(fu, node_id) {
+            return Err(NodeRejectReason::UpgradeDomainUpgrading);
+        }
+        let secondary_loads = self
+            .snapshot
+            .replica_loads(fu, ReplicaRole::Secondary)
+            .into_iter()
+            .collect::<BTreeMap<String, u32>>();
+        for (metric_name, load) in self.snapshot.replica_loads(fu, ReplicaRole::Primary) {
+            let Some(capacity) = node.capacity(&metric_name) else {
+                continue;
+            };
+            // the node trades the load of its secondary for the load of the primary
+            let secondary_load = secondary_loads
+                .get(&metric_name)
+                .copied()
+                .unwrap_or_default();
+            let new_load = (self.checker.node_load(node_id, &metric_name) as u64 + load as u64)
+                .saturating_sub(secondary_load as u64);
+            if load > secondary_load && new_load > capacity as u64 {
+                return Err(NodeRejectReason::CapacityExceeded(metric_name));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the movement takes a replica away from its node
+fn moves_replica(movement: &Movement) -> bool {
+    matches!(
+        movement,
+        Movement::MoveReplica { .. } | Movement::DropReplica { .. }
+    )
+}
+
+/// The movements moving the replica of a singleton failover unit without ever leaving it without a replica
+fn make_before_break(fu_id: Uuid, role: ReplicaRole, from: NodeId, to: NodeId) -> Vec<Movement> {
+    let mut movements = vec![Movement::AddReplica { fu_id, node: to }];
+    if role == ReplicaRole::Primary {
+        movements.push(Movement::SwapPrimary { fu_id, from, to });
+    }
+    movements.push(Movement::DropReplica { fu_id, node: from });
+    movements
+}
+
+fn apply(fu_desc: &mut FailoverUnitDescription, movement: &Movement) -> Result<(), PlanViolation> {
+    fu_desc
+        .apply_movement(movement)
+        .map_err(|err| PlanViolation::InvalidMovement(err.to_string()))
+}
@@ -0,0 +1,303 @@
+//! This module simulates what PLB would do after hypothetical changes to the cluster topology, e.g. before
+//! decommissioning nodes or adding a node type.
+//!
+//! The changes are applied to a copy of the cluster snapshot, on which the movements in flight are done, and the phases
+//! are run against the copy with the search strategies of the configuration, without touching the live state:
+//!     1. placement and constraint check run in rounds, the validated movements of a round being done before the next
+//!        one, until they generate no movement. A removed node is deactivated with the RemoveNode intent, so the
+//!        constraint check drains it.
+//!     2. the removed nodes are taken down, and load balancing runs once on the resulting cluster
+//!
+//! The replicas that cannot be placed are the ones still missing once the rounds are done, plus the ones left on the
+//! removed nodes.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use uuid::Uuid;
+
+use crate::{
+    balance::LoadTable,
+    config::PLBConfig,
+    constraint::ConstraintViolation,
+    failoverunit::failover_unit::FailoverUnit,
+    node::{
+        node::Node,
+        node_description::{NodeDeactivationIntent, NodeDescription},
+        node_id::NodeId,
+    },
+    pending::PendingMovements,
+    scheduler::Phase,
+    score::ClusterScore,
+    solver::Movement,
+    strategy::{create_strategy, SearchStrategy, SnapshotView},
+    validation::PlanValidator,
+    ClusterSnapshot,
+};
+
+/// Maximum number of placement and constraint check rounds of a simulation
+const MAX_ROUNDS: usize = 32;
+
+/// A hypothetical change to the cluster
+#[derive(Debug, Clone)]
+pub enum TopologyChange {
+    /// A new node joins the cluster
+    AddNode(NodeDescription),
+    /// The node is decommissioned, its replicas are moved away before it leaves the cluster
+    RemoveNode(NodeId),
+    /// The capacity of the node for the metric changes
+    SetNodeCapacity {
+        node_id: NodeId,
+        metric_name: String,
+        capacity: u32,
+    },
+    /// Every failover unit of the service targets the given number of replicas
+    SetReplicaCount {
+        service_name: String,
+        replica_count: u32,
+    },
+}
+
+/// A movement generated by the simulation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulatedMovement {
+    pub phase: Phase,
+    pub movement: Movement,
+}
+
+/// What PLB would do after the changes
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhatIfReport {
+    /// Score of the cluster before the changes, with the movements in flight done
+    pub score_before: ClusterScore,
+    /// The validated movements, in the order they would be carried out
+    pub movements: Vec<SimulatedMovement>,
+    /// Number of replicas that cannot be placed per failover unit
+    pub unplaceable_replicas: BTreeMap<Uuid, u32>,
+    /// Hard constraints still broken once the movements are done
+    pub violations: Vec<ConstraintViolation>,
+    /// Score of the cluster once the movements are done and the removed nodes are gone
+    pub score_after: ClusterScore,
+    /// Whether placement and constraint check stopped generating movements within the maximum number of rounds
+    pub converged: bool,
+}
+
+impl WhatIfReport {
+    /// Total number of replicas that cannot be placed
+    pub fn unplaceable_replica_count(&self) -> u32 {
+        self.unplaceable_replicas.values().sum()
+    }
+}
+
+pub(crate) struct WhatIfSimulator<'a> {
+    snapshot: ClusterSnapshot,
+    config: &'a PLBConfig,
+    score_before: ClusterScore,
+    removed_nodes: Vec<NodeId>,
+    movements: Vec<SimulatedMovement>,
+}
+
+impl<'a> WhatIfSimulator<'a> {
+    /// Copies the snapshot with the pending movements done, and applies the changes to the copy. Returns an error if a
+    /// change refers to an entity that is not in the snapshot, or adds a node that already is.
+    pub(crate) fn new(
+        snapshot: &ClusterSnapshot,
+        pending: &PendingMovements,
+        config: &'a PLBConfig,
+        changes: &[TopologyChange],
+    ) -> Result<Self> {
+        let mut snapshot = snapshot.clone();
+        snapshot.apply_pending_movements(pending);
+        let score_before = ClusterScore::new(
+            &LoadTable::new(&snapshot),
+            &config.metric_activity_thresholds,
+        );
+        let mut simulator = WhatIfSimulator {
+            snapshot,
+            config,
+            score_before,
+            removed_nodes: vec![],
+            movements: vec![],
+        };
+        for change in changes {
+            simulator.apply_change(change)?;
+        }
+
+        Ok(simulator)
+    }
+
+    fn apply_change(&mut self, change: &TopologyChange) -> Result<()> {
+        match change {
+            TopologyChange::AddNode(node_desc) => {
+                let node_id = node_desc.node_instance.id;
+                if self.snapshot.nodes.contains_key(&node_id) {
+                    return Err(anyhow!(
+                        "Node {:?} is already in the cluster snapshot",
+                        node_id
+                    ));
+                }
+                self.snapshot.insert_node(Node {
+                    node_description: node_desc.clone(),
+                });
+            }
+            TopologyChange::RemoveNode(node_id) => {
+                let mut node_desc = self.node_description(*node_id)?;
+                node_desc.deactivation_intent = NodeDeactivationIntent::RemoveNode;
+                self.snapshot.insert_node(Node {
+                    node_description: node_desc,
+                });
+                self.removed_nodes.push(*node_id);
+            }
+            TopologyChange::SetNodeCapacity {
+                node_id,
+                metric_name,
+                capacity,
+            } => {
+                let mut node_desc = self.node_description(*node_id)?;
+                node_desc.capacities.insert(metric_name.clone(), *capacity);
+                self.snapshot.insert_node(Node {
+                    node_description: node_desc,
+                });
+            }
+            TopologyChange::SetReplicaCount {
+                service_name,
+                replica_count,
+            } => {
+                if !self.snapshot.services.contains_key(service_name) {
+                    return Err(anyhow!(
+                        "Service {} is not in the cluster snapshot",
+                        service_name
+                    ));
+                }
+                let fus = self
+                    .snapshot
+                    .failover_units_of_service(service_name)
+                    .cloned()
+                    .collect::<Vec<FailoverUnit>>();
+                for mut fu in fus {
+                    let replicas = fu.active_replicas().count() as i32;
+                    fu.failover_unit_description.replica_diff = *replica_count as i32 - replicas;
+                    self.snapshot.insert_failover_unit(fu);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn node_description(&self, node_id: NodeId) -> Result<NodeDescription> {
+        self.snapshot
+            .nodes
+            .get(&node_id)
+            .map(|node| node.node_description.clone())
+            .ok_or_else(|| anyhow!("Node {:?} is not in the cluster snapshot", node_id))
+    }
+
+    pub(crate) fn run(mut self) -> WhatIfReport {
+        let mut strategies = BTreeMap::<Phase, Box<dyn SearchStrategy>>::new();
+        let converged = self.place(&mut strategies);
+        let unplaceable_replicas = self.unplaceable_replicas();
+        self.take_removed_nodes_down();
+        self.run_phase(&mut strategies, Phase::LoadBalancing);
+
+        WhatIfReport {
+            violations: self.snapshot.constraint_violations(),
+            score_after: self.score(),
+            score_before: self.score_before,
+            unplaceable_replicas,
+            movements: self.movements,
+            converged,
+        }
+    }
+
+    /// Runs only the placement and constraint check rounds, and counts the replicas that cannot be placed. Load
+    /// balancing does not change the count, so it is skipped.
+    pub(crate) fn unplaceable_replica_count(mut self) -> u32 {
+        let mut strategies = BTreeMap::<Phase, Box<dyn SearchStrategy>>::new();
+        self.place(&mut strategies);
+        self.unplaceable_replicas().values().sum()
+    }
+
+    /// Runs the placement and constraint check rounds, returns whether they stopped generating movements within the
+    /// maximum number of rounds
+    fn place(&mut self, strategies: &mut BTreeMap<Phase, Box<dyn SearchStrategy>>) -> bool {
+        for _ in 0..MAX_ROUNDS {
+            let mut moved = false;
+            for phase in [Phase::Placement, Phase::ConstraintCheck] {
+                moved |= self.run_phase(strategies, phase);
+            }
+            if !moved {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// The replicas still missing, plus the ones still on the removed nodes, which are lost when the nodes leave the
+    /// cluster
+    fn unplaceable_replicas(&self) -> BTreeMap<Uuid, u32> {
+        let mut unplaceable_replicas = BTreeMap::<Uuid, u32>::new();
+        for node_id in &self.removed_nodes {
+            for (fu, _) in self.snapshot.replicas_on(*node_id) {
+                *unplaceable_replicas.entry(fu.id()).or_default() += 1;
+            }
+        }
+        for fu in self.snapshot.failover_units.values() {
+            if fu.replia_diff() > 0 {
+                *unplaceable_replicas.entry(fu.id()).or_default() += fu.replia_diff() as u32;
+            }
+        }
+
+        unplaceable_replicas
+    }
+
+    fn take_removed_nodes_down(&mut self) {
+        for node_id in &self.removed_nodes {
+            let mut node_desc = self.snapshot.nodes[node_id].node_description.clone();
+            node_desc.is_up = false;
+            self.snapshot.insert_node(Node {
+                node_description: node_desc,
+            });
+        }
+    }
+
+    /// Runs the phase against the simulated snapshot and carries out the validated movements, returns whether there
+    /// was any
+    fn run_phase(
+        &mut self,
+        strategies: &mut BTreeMap<Phase, Box<dyn SearchStrategy>>,
+        phase: Phase,
+    ) -> bool {
+        let config = self.config;
+        let strategy = strategies
+            .entry(phase)
+            .or_insert_with(|| create_strategy(config.strategy_for(phase), config));
+        let outcome = strategy.search(SnapshotView::new(&self.snapshot), phase);
+        let accepted = PlanValidator::new(&self.snapshot)
+            .validate_plan(outcome.movements)
+            .accepted;
+        for movement in &accepted {
+            let fu_id = movement.fu_id();
+            let mut fu = self.snapshot.failover_units[&fu_id].clone();
+            // the validation already applied the movement to a copy of the failover unit
+            let _ = fu.failover_unit_description.apply_movement(movement);
+            self.snapshot.insert_failover_unit(fu);
+        }
+
+        let moved = !accepted.is_empty();
+        self.movements.extend(
+            accepted
+                .into_iter()
+                .map(|movement| SimulatedMovement { phase, movement }),
+        );
+        moved
+    }
+
+    fn score(&self) -> ClusterScore {
+        ClusterScore::new(
+            &LoadTable::new(&self.snapshot),
+            &self.config.metric_activity_thresholds,
+        )
+    }
+}